@@ -0,0 +1,93 @@
+//! Benchmarks of the stable curve Newton iteration, against the constant
+//! product curve for reference
+
+use {
+    criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion},
+    token_swap::curve::{
+        calculator::{CurveCalculator, TradeDirection},
+        constant_product::ConstantProductCurve,
+        stable::{compute_d, StableCurve},
+    },
+};
+
+const AMPS: [u64; 4] = [1, 10, 100, 1_000];
+
+/// Pool reserves as `(token_a_amount, token_b_amount)`, from balanced to very
+/// imbalanced
+const RESERVES: [(u128, u128); 3] = [
+    (1_000_000_000, 1_000_000_000),
+    (1_000_000_000, 100_000_000),
+    (1_000_000_000, 1_000_000),
+];
+
+const SOURCE_AMOUNT: u128 = 1_000_000;
+
+fn bench_compute_d(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compute_d");
+    for amp in AMPS {
+        for (token_a_amount, token_b_amount) in RESERVES {
+            group.bench_with_input(
+                BenchmarkId::new(format!("amp_{}", amp), token_a_amount / token_b_amount),
+                &(amp, token_a_amount, token_b_amount),
+                |b, &(amp, token_a_amount, token_b_amount)| {
+                    // the leverage is the amplifier times the number of coins
+                    b.iter(|| {
+                        compute_d(
+                            black_box(amp * 2),
+                            black_box(token_a_amount),
+                            black_box(token_b_amount),
+                        )
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_swap_without_fees(c: &mut Criterion) {
+    let mut group = c.benchmark_group("swap_without_fees");
+    for amp in AMPS {
+        let curve = StableCurve { amp };
+        for (token_a_amount, token_b_amount) in RESERVES {
+            group.bench_with_input(
+                BenchmarkId::new(
+                    format!("stable_amp_{}", amp),
+                    token_a_amount / token_b_amount,
+                ),
+                &(token_a_amount, token_b_amount),
+                |b, &(token_a_amount, token_b_amount)| {
+                    b.iter(|| {
+                        curve.swap_without_fees(
+                            black_box(SOURCE_AMOUNT),
+                            black_box(token_a_amount),
+                            black_box(token_b_amount),
+                            TradeDirection::AtoB,
+                        )
+                    })
+                },
+            );
+        }
+    }
+    let curve = ConstantProductCurve {};
+    for (token_a_amount, token_b_amount) in RESERVES {
+        group.bench_with_input(
+            BenchmarkId::new("constant_product", token_a_amount / token_b_amount),
+            &(token_a_amount, token_b_amount),
+            |b, &(token_a_amount, token_b_amount)| {
+                b.iter(|| {
+                    curve.swap_without_fees(
+                        black_box(SOURCE_AMOUNT),
+                        black_box(token_a_amount),
+                        black_box(token_b_amount),
+                        TradeDirection::AtoB,
+                    )
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_compute_d, bench_swap_without_fees);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+//! Checks that trading fees never exceed the traded amount, and never
+//! decrease as the traded amount grows
+
+#![no_main]
+
+use {arbitrary::Arbitrary, libfuzzer_sys::fuzz_target, token_swap::curve::fees::Fees};
+
+#[derive(Debug, Arbitrary)]
+struct FeeData {
+    fees: Fees,
+    amount: u128,
+    other_amount: u128,
+}
+
+fuzz_target!(|data: FeeData| {
+    let FeeData {
+        fees,
+        amount,
+        other_amount,
+    } = data;
+    if fees.validate().is_err() {
+        return;
+    }
+    let (smaller, larger) = if amount <= other_amount {
+        (amount, other_amount)
+    } else {
+        (other_amount, amount)
+    };
+
+    // fees overflow on very large amounts, but only ever cleanly
+    let smaller_fee = match fees.trading_fee(smaller) {
+        Some(fee) => fee,
+        None => return,
+    };
+    assert!(smaller_fee <= smaller);
+    let larger_fee = match fees.trading_fee(larger) {
+        Some(fee) => fee,
+        None => return,
+    };
+    assert!(larger_fee <= larger);
+    assert!(smaller_fee <= larger_fee);
+});
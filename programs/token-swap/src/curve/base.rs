@@ -1,14 +1,2559 @@
 //! Base curve implementation
 
-use crate::curve::{
-    calculator::{CurveCalculator, SwapWioutFeeResult, TradeDirection},
-    constant_price::ConstantPriceCurve, 
-    constant_product::ConstantProductCurve,
-    fees::Fees,
-}
+use {
+    crate::{
+        curve::{
+            calculator::{
+                dilution_bps, map_zero_to_none, CurveCalculator, RoundDirection,
+                SwapWithoutFeesResult, TradeDirection, TradingTokenResult,
+                INITIAL_SWAP_POOL_AMOUNT, MINIMUM_LIQUIDITY,
+            },
+            concentrated::ConcentratedCurve,
+            constant_price::ConstantPriceCurve,
+            constant_product::ConstantProductCurve,
+            fees::Fees,
+            offset::Offset,
+            stable::{StableCurve, ITERATIONS},
+        },
+        errors::SwapError,
+    },
+    anchor_lang::{
+        prelude::{borsh, AnchorDeserialize, AnchorSerialize},
+        solana_program::{program_error::ProgramError, program_pack::Pack},
+    },
+    spl_math::precise_number::PreciseNumber,
+    std::{cmp::Ordering, sync::Arc},
+};
 
 #[cfg(feature = "fuzz")]
 use arbitrary::Arbitrary;
 
+/// Curve types supported by the token-swap program.
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum CurveType {
+    /// Uniswap-style constant product curve, invariant = token_a_amount * token_b_amount
+    #[default]
+    ConstantProduct,
+    /// Flat line, always providing 1:1 from one token to another
+    ConstantPrice,
+    /// Stable, like uniswap, but with wide zone of 1:1 instead of one point
+    Stable,
+    /// Offset curve, like Uniswap, but the token B side has a faked offset
+    Offset,
+    /// Constant price around a center price, constant product outside of it
+    Concentrated,
+}
+
+impl CurveType {
+    /// Curve type from its Borsh-encoded tag, as stored in the swap state
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(CurveType::ConstantProduct),
+            1 => Some(CurveType::ConstantPrice),
+            2 => Some(CurveType::Stable),
+            3 => Some(CurveType::Offset),
+            4 => Some(CurveType::Concentrated),
+            _ => None,
+        }
+    }
+
+    /// Rough estimate of the compute units spent on the curve calculation of
+    /// an operation, to help integrators set compute budgets. Token program
+    /// invocations and account loading are not included.
+    ///
+    /// The stable curve's cost is dominated by its Newton iterations, so its
+    /// estimate assumes all `ITERATIONS` are run for each solved equation.
+    pub fn estimated_compute_units(&self, operation: CurveOp) -> u32 {
+        /// Compute units of one stable curve Newton iteration in U256
+        const STABLE_ITERATION_UNITS: u32 = 1_500;
+        let stable_iterations = 2 * u32::from(ITERATIONS);
+        match (self, operation) {
+            (CurveType::ConstantPrice, _) => 1_000,
+            (CurveType::ConstantProduct | CurveType::Offset, CurveOp::Swap) => 2_000,
+            (CurveType::ConstantProduct | CurveType::Offset, _) => 5_000,
+            (CurveType::Concentrated, CurveOp::Swap) => 4_000,
+            (CurveType::Concentrated, _) => 7_000,
+            // swaps solve for D and then the new destination amount, while
+            // single-sided deposits and withdrawals solve for D twice
+            (CurveType::Stable, _) => 2_000 + stable_iterations * STABLE_ITERATION_UNITS,
+        }
+    }
+}
+
+/// Curve calculation performed by an instruction, used to estimate its
+/// compute cost
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveOp {
+    /// Swap one token for the other
+    Swap,
+    /// Deposit a single token type
+    Deposit,
+    /// Withdraw a single token type
+    Withdraw,
+}
+
+/// How the pool token supply of a new pool is determined
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InitialSupply {
+    /// Balancer-style fixed amount, regardless of the deposited amounts
+    Fixed(u128),
+    /// Uniswap-style supply derived from the deposited amounts, using the
+    /// normalized value of the curve, ie. `sqrt(token_a * token_b)` for
+    /// constant product
+    GeometricMean,
+}
+
+impl Default for InitialSupply {
+    fn default() -> Self {
+        InitialSupply::Fixed(INITIAL_SWAP_POOL_AMOUNT)
+    }
+}
+
+/// Unpack a single curve from its packed parameters, rejecting parameters
+/// which are too short or fail the curve's validation
+fn unpack_curve<T: CurveCalculator + Pack>(parameters: &[u8]) -> Result<T, SwapError> {
+    if parameters.len() < T::LEN {
+        return Err(SwapError::InvalidCurveParameters);
+    }
+    let curve = T::unpack_from_slice(parameters).map_err(|_| SwapError::InvalidCurveParameters)?;
+    curve
+        .validate()
+        .map_err(|_| SwapError::InvalidCurveParameters)?;
+    Ok(curve)
+}
+
+/// Validate everything a new pool is configured with, returning the first
+/// error found: the fees, then the curve parameters, then the initial supply
+/// of trading tokens against the curve
+pub fn validate_swap_config(
+    curve: &dyn CurveCalculator,
+    fees: &Fees,
+    token_a_amount: u64,
+    token_b_amount: u64,
+) -> Result<(), SwapError> {
+    fees.validate()?;
+    curve.validate()?;
+    curve.validate_supply(token_a_amount, token_b_amount)?;
+    Ok(())
+}
+
+/// Validate the configs of a batch of new pools with `validate_swap_config`,
+/// returning the index and error of the first invalid config.
+///
+/// Each config is the curve type and packed curve parameters, as given to the
+/// initialize instruction, then the fees and initial token A and B amounts of
+/// a pool. Parameters which don't unpack into a curve of the type are
+/// reported as `SwapError::InvalidCurveParameters`.
+pub fn validate_batch(
+    configs: &[(CurveType, &[u8], Fees, u64, u64)],
+) -> Result<(), (usize, SwapError)> {
+    for (index, (curve_type, parameters, fees, token_a_amount, token_b_amount)) in
+        configs.iter().enumerate()
+    {
+        let swap_curve = SwapCurve::from_parameters(*curve_type, parameters)
+            .map_err(|_| (index, SwapError::InvalidCurveParameters))?;
+        validate_swap_config(
+            swap_curve.calculator.as_ref(),
+            fees,
+            *token_a_amount,
+            *token_b_amount,
+        )
+        .map_err(|error| (index, error))?;
+    }
+    Ok(())
+}
+
+/// A single pool of a route: its curve, source and destination reserves,
+/// fees and the direction of the trade through it
+pub type RouteHop<'a> = (&'a SwapCurve, u64, u64, &'a Fees, TradeDirection);
+
+/// Amount received at the end of a route for `amount_in`, feeding the output
+/// of each hop into the next, or `None` if the route is empty or any hop
+/// fails
+fn route_output(route: &[RouteHop], amount_in: u64) -> Option<u128> {
+    if route.is_empty() {
+        return None;
+    }
+    let mut amount = u128::from(amount_in);
+    for (swap_curve, swap_source_amount, swap_destination_amount, fees, trade_direction) in route {
+        amount = swap_curve
+            .swap(
+                amount,
+                u128::from(*swap_source_amount),
+                u128::from(*swap_destination_amount),
+                *trade_direction,
+                fees,
+            )?
+            .destination_amount_swapped;
+    }
+    Some(amount)
+}
+
+/// Index of the route paying out the most for `amount_in`, or `None` if no
+/// route can be swapped through.
+///
+/// Routes with equal outputs are ordered deterministically: the fewest hops
+/// wins, then the lowest index.
+pub fn best_route(routes: &[Vec<RouteHop>], amount_in: u64) -> Option<usize> {
+    let mut best: Option<(usize, u128)> = None;
+    for (index, route) in routes.iter().enumerate() {
+        let Some(output) = route_output(route, amount_in) else {
+            continue;
+        };
+        let is_better = match best {
+            None => true,
+            Some((best_index, best_output)) => {
+                output > best_output
+                    || (output == best_output && route.len() < routes[best_index].len())
+            }
+        };
+        if is_better {
+            best = Some((index, output));
+        }
+    }
+    best.map(|(index, _)| index)
+}
+
+/// Encodes all results of swapping from a source token to a destination token
+#[derive(Debug, PartialEq)]
+pub struct SwapResult {
+    /// New amount of source token
+    pub new_swap_source_amount: u128,
+    /// New amount of destination token
+    pub new_swap_destination_amount: u128,
+    /// Amount of source token swapped (includes fees)
+    pub source_amount_swapped: u128,
+    /// Amount of destination token swapped
+    pub destination_amount_swapped: u128,
+    /// Amount of source tokens going to pool holders
+    pub trade_fee: u128,
+    /// Amount of source tokens going to owner
+    pub owner_fee: u128,
+}
+
+/// Summary of a prospective swap, as returned by `SwapCurve::preview`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapPreview {
+    /// Amount of destination token received
+    pub amount_out: u128,
+    /// Amount of source tokens going to pool holders
+    pub trade_fee: u128,
+    /// Amount of source tokens going to owner
+    pub owner_fee: u128,
+    /// Movement of the price along the curve caused by the trade, in basis
+    /// points of the spot price
+    pub price_impact_bps: u128,
+    /// Spot price before the trade, in destination tokens per source token
+    pub spot_price_before: PreciseNumber,
+    /// Price obtained by the trade including fees, in destination tokens per
+    /// source token
+    pub effective_price: PreciseNumber,
+}
+
+/// Summary of a prospective withdrawal of both trading tokens, as returned by
+/// `SwapCurve::withdraw_preview`
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithdrawPreview {
+    /// Amount of token A received
+    pub token_a_out: u128,
+    /// Amount of token B received
+    pub token_b_out: u128,
+    /// Pool tokens going to the owner rather than being burned
+    pub owner_withdraw_fee: u128,
+}
+
+/// Concrete struct to wrap around the trait object which performs calculation.
+#[derive(Clone, Debug)]
+pub struct SwapCurve {
+    /// The type of curve contained in the calculator, helpful for outside
+    /// queries
+    pub curve_type: CurveType,
+    /// The actual calculator, represented as a trait object to allow for many
+    /// different types of curves
+    pub calculator: Arc<dyn CurveCalculator + Sync + Send>,
+    /// How many pool tokens to mint when the pool is created
+    pub initial_supply: InitialSupply,
+}
+
+impl SwapCurve {
+    /// Size of the packed curve parameters, large enough for any curve type
+    pub const PARAMETERS_LEN: usize = 32;
+
+    /// Decimals of the price limit given to `swap_partial`
+    pub const PRICE_LIMIT_DECIMALS: u8 = 6;
+
+    /// Largest difference, in basis points of the spot price, between the
+    /// spot price and the effective price of a swap reported as favorable by
+    /// `is_favorable`
+    pub const FAVORABLE_PRICE_IMPACT_BPS: u128 = 100;
+
+    /// Build a swap curve of the given type from its packed parameters, as
+    /// provided to the initialize instruction and stored in the swap state.
+    ///
+    /// Fails with `SwapError::InvalidCurveParameters` if the parameters are
+    /// too short or don't describe a valid curve.
+    pub fn from_parameters(curve_type: CurveType, parameters: &[u8]) -> Result<Self, ProgramError> {
+        let calculator: Arc<dyn CurveCalculator + Sync + Send> = match curve_type {
+            CurveType::ConstantProduct => {
+                Arc::new(unpack_curve::<ConstantProductCurve>(parameters)?)
+            }
+            CurveType::ConstantPrice => Arc::new(unpack_curve::<ConstantPriceCurve>(parameters)?),
+            CurveType::Stable => Arc::new(unpack_curve::<StableCurve>(parameters)?),
+            CurveType::Offset => Arc::new(unpack_curve::<Offset>(parameters)?),
+            CurveType::Concentrated => Arc::new(unpack_curve::<ConcentratedCurve>(parameters)?),
+        };
+        Ok(Self {
+            curve_type,
+            calculator,
+            initial_supply: InitialSupply::default(),
+        })
+    }
+
+    /// Pack the curve's parameters into the blob given to the initialize
+    /// instruction, the inverse of `from_parameters`
+    pub fn pack_parameters(&self) -> [u8; Self::PARAMETERS_LEN] {
+        let mut parameters = [0u8; Self::PARAMETERS_LEN];
+        self.calculator.pack_into_slice(&mut parameters);
+        parameters
+    }
+
+    /// Get the amount of pool tokens to mint for a new pool, given the initial
+    /// deposit of both trading tokens
+    pub fn initial_pool_tokens(&self, token_a_amount: u128, token_b_amount: u128) -> Option<u128> {
+        match self.initial_supply {
+            InitialSupply::Fixed(supply) => Some(supply),
+            InitialSupply::GeometricMean => self
+                .calculator
+                .normalized_value(token_a_amount, token_b_amount)?
+                .floor()?
+                .to_imprecise(),
+        }
+    }
+
+    /// Pool tokens received by the creator of a new pool, which are the
+    /// initial pool tokens less the locked `MINIMUM_LIQUIDITY`
+    pub fn creator_pool_tokens(&self, token_a_amount: u128, token_b_amount: u128) -> Option<u128> {
+        self.initial_pool_tokens(token_a_amount, token_b_amount)?
+            .checked_sub(MINIMUM_LIQUIDITY)
+            .and_then(map_zero_to_none)
+    }
+
+    /// Subtract fees and calculate how much destination token will be provided
+    /// given an amount of source token.
+    ///
+    /// As in SPL token-swap, the trading fee and the owner trading fee are
+    /// both calculated on the full source amount, not one after the other,
+    /// and the remainder is swapped.
+    pub fn swap(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<SwapResult> {
+        // debit the fee to calculate the amount swapped
+        let trade_fee = fees.trading_fee(source_amount)?;
+        let owner_fee = if fees.charges_owner_fee(trade_direction) {
+            fees.owner_trading_fee(source_amount)?
+        } else {
+            0
+        };
+
+        let total_fees = trade_fee.checked_add(owner_fee)?;
+        let source_amount_less_fees = source_amount.checked_sub(total_fees)?;
+
+        let SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        } = self.calculator.swap_without_fees(
+            source_amount_less_fees,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )?;
+
+        let source_amount_swapped = source_amount_swapped.checked_add(total_fees)?;
+        Some(SwapResult {
+            new_swap_source_amount: swap_source_amount.checked_add(source_amount_swapped)?,
+            new_swap_destination_amount: swap_destination_amount
+                .checked_sub(destination_amount_swapped)?,
+            source_amount_swapped,
+            destination_amount_swapped,
+            trade_fee,
+            owner_fee,
+        })
+    }
+
+    /// Calculate how much source token, including fees, is needed to receive
+    /// at least `destination_amount` of destination token, and the result of
+    /// swapping it.
+    ///
+    /// Since every fee charges at least one token, the source amount is a
+    /// conservative estimate, so slightly more than `destination_amount` may
+    /// be received. Returns `None` if the curve doesn't support exact-out
+    /// swaps.
+    pub fn swap_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<SwapResult> {
+        let source_amount = self.exact_out_source_amount(
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+            fees,
+        )?;
+        let result = self.swap(
+            source_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+            fees,
+        )?;
+        if result.destination_amount_swapped < destination_amount {
+            None
+        } else {
+            Some(result)
+        }
+    }
+
+    /// Source amount, including fees, to swap for at least
+    /// `destination_amount` of destination token, rounded up.
+    ///
+    /// The curve calculates the source amount without fees, which is then
+    /// grossed up by the trading fee, and by the owner trading fee if it is
+    /// charged in this direction. Returns `None` if the curve doesn't support
+    /// exact-out swaps, or if the amount doesn't fit in a token account.
+    pub fn amount_in_for_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u64> {
+        let source_amount = self.exact_out_source_amount(
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+            fees,
+        )?;
+        u64::try_from(source_amount).ok()
+    }
+
+    fn exact_out_source_amount(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        if !self.calculator.supports_exact_out() {
+            return None;
+        }
+        let SwapWithoutFeesResult {
+            source_amount_swapped,
+            ..
+        } = self.calculator.swap_without_fees_exact_out(
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )?;
+        if fees.charges_owner_fee(trade_direction) {
+            fees.pre_trading_fee_amount(source_amount_swapped)
+        } else {
+            Fees {
+                owner_trade_fee_numerator: 0,
+                ..fees.clone()
+            }
+            .pre_trading_fee_amount(source_amount_swapped)
+        }
+    }
+
+    /// Convert the owner trade fee, given in source trading tokens, into the
+    /// equivalent amount of pool tokens to mint for the owner.
+    ///
+    /// The fee stays in the pool's token accounts, so it is valued as a
+    /// single-sided deposit of the source token. Reserves should be given
+    /// after the swap has been applied.
+    ///
+    /// Any fee, reserves and pool supply up to `u64::MAX`, ie. anything held
+    /// in token accounts, are safe. Larger values return `None` when the
+    /// result or an intermediate value overflows, never panicking.
+    pub fn owner_fee_to_pool_tokens(
+        &self,
+        owner_fee: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        self.calculator.deposit_single_token_type(
+            owner_fee,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    /// Get the amount of pool tokens for a deposit of only the source token,
+    /// for use by the single token deposit instruction.
+    ///
+    /// Half of the deposit is effectively swapped for the other token, so
+    /// trading fees are charged on that half. A deposit into an empty source
+    /// reserve can't be priced, which is reported as `EmptySupply` rather
+    /// than a generic calculation failure.
+    pub fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Result<u128, SwapError> {
+        let swap_source_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => swap_token_b_amount,
+        };
+        if swap_source_amount == 0 || pool_supply == 0 {
+            return Err(SwapError::EmptySupply);
+        }
+        let (trade_fee, owner_fee) = fees
+            .single_deposit_fees(source_amount)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        let source_amount = trade_fee
+            .checked_add(owner_fee)
+            .and_then(|total_fees| source_amount.checked_sub(total_fees))
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        self.calculator.try_deposit_single_token_type(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    /// Get the amount of trading tokens for the given amount of pool tokens,
+    /// for use by the deposit and withdraw instructions.
+    ///
+    /// A pool with no outstanding pool tokens has been completely drained and
+    /// cannot be converted against, which is reported as `EmptySupply` rather
+    /// than a generic calculation failure.
+    pub fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Result<TradingTokenResult, SwapError> {
+        if pool_token_supply == 0 {
+            return Err(SwapError::EmptySupply);
+        }
+        self.calculator
+            .pool_tokens_to_trading_tokens(
+                pool_tokens,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                round_direction,
+            )
+            .ok_or(SwapError::CalculationFailure)
+    }
+
+    /// Get the amounts of token A and B needed to mint `pool_tokens`, rounding
+    /// up in favor of the pool, as `(token_a_amount, token_b_amount)`.
+    ///
+    /// Returns `None` if either amount doesn't fit in a token amount.
+    pub fn tokens_for_pool_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<(u64, u64)> {
+        let TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        } = self
+            .pool_tokens_to_trading_tokens(
+                pool_tokens,
+                pool_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                RoundDirection::Ceiling,
+            )
+            .ok()?;
+        Some((
+            u64::try_from(token_a_amount).ok()?,
+            u64::try_from(token_b_amount).ok()?,
+        ))
+    }
+
+    /// Get the pool tokens minted for depositing `token_a_amount` and
+    /// `token_b_amount` into the pool, rounding down in favor of the pool.
+    ///
+    /// Each side is worth its share of the reserves in pool tokens, and only
+    /// the smaller of the two is minted, so an imbalanced deposit can't mint
+    /// more than its scarcer side is worth. Returns `None` for an empty pool.
+    pub fn pool_tokens_for_deposit(
+        &self,
+        token_a_amount: u128,
+        token_b_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+    ) -> Option<u128> {
+        let pool_tokens_a = token_a_amount
+            .checked_mul(pool_supply)?
+            .checked_div(swap_token_a_amount)?;
+        let pool_tokens_b = token_b_amount
+            .checked_mul(pool_supply)?
+            .checked_div(swap_token_b_amount)?;
+        Some(std::cmp::min(pool_tokens_a, pool_tokens_b))
+    }
+
+    /// Get how much depositing `token_a_amount` and `token_b_amount` dilutes
+    /// the existing pool token holders, in basis points, using the pool
+    /// tokens `pool_tokens_for_deposit` would mint
+    pub fn deposit_dilution_bps(
+        &self,
+        token_a_amount: u128,
+        token_b_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+    ) -> Option<u128> {
+        let minted = self.pool_tokens_for_deposit(
+            token_a_amount,
+            token_b_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+        )?;
+        Some(dilution_bps(pool_supply, minted))
+    }
+
+    /// Get the value of a single pool token, as the normalized value of the
+    /// pool divided by the pool token supply
+    pub fn pool_token_price(
+        &self,
+        pool_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        self.calculator
+            .normalized_value(swap_token_a_amount, swap_token_b_amount)?
+            .checked_div(&PreciseNumber::new(pool_supply)?)
+    }
+
+    /// Get the liquidity depth of the pool, as its normalized value, eg.
+    /// `sqrt(token_a * token_b)` for constant product. Trades move the price
+    /// less the deeper the pool.
+    pub fn liquidity_depth(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        self.calculator
+            .normalized_value(swap_token_a_amount, swap_token_b_amount)
+    }
+
+    /// Whether the liquidity depth of the pool is below `threshold`, so that
+    /// even modest trades move the price significantly. A pool whose depth
+    /// can't be computed is reported as thin.
+    pub fn is_thin(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        threshold: u128,
+    ) -> bool {
+        let depth = self.liquidity_depth(swap_token_a_amount, swap_token_b_amount);
+        match (depth, PreciseNumber::new(threshold)) {
+            (Some(depth), Some(threshold)) => depth.less_than(&threshold),
+            _ => true,
+        }
+    }
+
+    /// Get the spot price of token A in token B as a Q64.64 fixed-point
+    /// number, for integrations expecting that format.
+    ///
+    /// The spot price only carries the 12 decimal places of `PreciseNumber`,
+    /// so roughly the lowest 24 fractional bits are noise, and the result is
+    /// floored. Returns `None` if the price is `2^64` or more.
+    pub fn spot_price_q64(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<u128> {
+        let q64_one = PreciseNumber::new(1u128 << 64)?;
+        let spot_price = self
+            .calculator
+            .spot_price(swap_token_a_amount, swap_token_b_amount)?;
+        // `to_imprecise` panics instead of failing on overflow
+        if spot_price.greater_than_or_equal(&q64_one) {
+            return None;
+        }
+        spot_price.checked_mul(&q64_one)?.floor()?.to_imprecise()
+    }
+
+    /// Value a liquidity position entirely in token A.
+    ///
+    /// The pool tokens are converted into both trading tokens, rounding down,
+    /// and the token B share is priced into token A at the current spot price.
+    pub fn lp_value_in_token_a(
+        &self,
+        pool_tokens: u128,
+        pool_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<u128> {
+        let TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        } = self.calculator.pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            RoundDirection::Floor,
+        )?;
+        let spot_price = self
+            .calculator
+            .spot_price(swap_token_a_amount, swap_token_b_amount)?;
+        let token_b_value = PreciseNumber::new(token_b_amount)?
+            .checked_div(&spot_price)?
+            .floor()?
+            .to_imprecise()?;
+        token_a_amount.checked_add(token_b_value)
+    }
+
+    /// Replay a series of trades against the pool, threading the reserves
+    /// from one trade into the next.
+    ///
+    /// `initial_reserves` is given as `(token_a_amount, token_b_amount)`, and
+    /// each trade is an amount of source token along with its direction.
+    /// Returns the result of every trade in order, or `None` if any trade
+    /// fails.
+    pub fn simulate(
+        &self,
+        initial_reserves: (u64, u64),
+        trades: &[(u64, TradeDirection)],
+        fees: &Fees,
+    ) -> Option<Vec<SwapResult>> {
+        let (mut swap_token_a_amount, mut swap_token_b_amount) = (
+            u128::from(initial_reserves.0),
+            u128::from(initial_reserves.1),
+        );
+        let mut results = Vec::with_capacity(trades.len());
+        for (source_amount, trade_direction) in trades {
+            let (swap_source_amount, swap_destination_amount) = match trade_direction {
+                TradeDirection::AtoB => (swap_token_a_amount, swap_token_b_amount),
+                TradeDirection::BtoA => (swap_token_b_amount, swap_token_a_amount),
+            };
+            let result = self.swap(
+                u128::from(*source_amount),
+                swap_source_amount,
+                swap_destination_amount,
+                *trade_direction,
+                fees,
+            )?;
+            (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+                TradeDirection::AtoB => (
+                    result.new_swap_source_amount,
+                    result.new_swap_destination_amount,
+                ),
+                TradeDirection::BtoA => (
+                    result.new_swap_destination_amount,
+                    result.new_swap_source_amount,
+                ),
+            };
+            results.push(result);
+        }
+        Some(results)
+    }
+
+    /// Preview a swap of `source_amount`, gathering the output, fees and
+    /// prices a frontend needs to display in a single calculation.
+    ///
+    /// Prices are given in destination tokens per source token. The price
+    /// impact compares the spot price with the price obtained for the source
+    /// tokens left after fees, so it only reflects the movement along the
+    /// curve, whereas the effective price includes the fees.
+    pub fn preview(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<SwapPreview> {
+        let result = self.swap(
+            source_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+            fees,
+        )?;
+        let spot_price_before = self.directional_spot_price(
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )?;
+        let destination_amount = PreciseNumber::new(result.destination_amount_swapped)?;
+        let effective_price =
+            destination_amount.checked_div(&PreciseNumber::new(result.source_amount_swapped)?)?;
+        let curve_source_amount = result
+            .source_amount_swapped
+            .checked_sub(result.trade_fee)?
+            .checked_sub(result.owner_fee)?;
+        let curve_price =
+            destination_amount.checked_div(&PreciseNumber::new(curve_source_amount)?)?;
+        let price_impact_bps = if curve_price.greater_than_or_equal(&spot_price_before) {
+            0
+        } else {
+            spot_price_before
+                .checked_sub(&curve_price)?
+                .checked_mul(&PreciseNumber::new(10_000)?)?
+                .checked_div(&spot_price_before)?
+                .floor()?
+                .to_imprecise()?
+        };
+        Some(SwapPreview {
+            amount_out: result.destination_amount_swapped,
+            trade_fee: result.trade_fee,
+            owner_fee: result.owner_fee,
+            price_impact_bps,
+            spot_price_before,
+            effective_price,
+        })
+    }
+
+    /// Preview withdrawing both trading tokens for `pool_tokens`, as done by
+    /// the withdraw all token types instruction.
+    ///
+    /// The owner withdraw fee is taken out of the pool tokens first, and the
+    /// rest are converted into trading tokens, rounding down.
+    pub fn withdraw_preview(
+        &self,
+        pool_tokens: u128,
+        pool_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        fees: &Fees,
+    ) -> Option<WithdrawPreview> {
+        let owner_withdraw_fee = fees.owner_withdraw_fee(pool_tokens)?;
+        let TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        } = self
+            .pool_tokens_to_trading_tokens(
+                pool_tokens.checked_sub(owner_withdraw_fee)?,
+                pool_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                RoundDirection::Floor,
+            )
+            .ok()?;
+        Some(WithdrawPreview {
+            token_a_out: token_a_amount,
+            token_b_out: token_b_amount,
+            owner_withdraw_fee,
+        })
+    }
+
+    /// Whether a swap of `source_amount` gets an effective price, after fees,
+    /// within `FAVORABLE_PRICE_IMPACT_BPS` of the spot price, as reported by
+    /// `preview`.
+    ///
+    /// Dust trades can lose most of their value to the rounding of the
+    /// output, so this lets a frontend warn about them, as well as about
+    /// trades large enough to move the price significantly. A swap which
+    /// fails isn't favorable.
+    pub fn is_favorable(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> bool {
+        self.preview(
+            source_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+            fees,
+        )
+        .and_then(|preview| {
+            let bound = preview
+                .spot_price_before
+                .checked_mul(&PreciseNumber::new(
+                    10_000 - Self::FAVORABLE_PRICE_IMPACT_BPS,
+                )?)?
+                .checked_div(&PreciseNumber::new(10_000)?)?;
+            Some(preview.effective_price.greater_than_or_equal(&bound))
+        })
+        .unwrap_or(false)
+    }
+
+    /// Spot price of the pool in destination tokens per source token
+    fn directional_spot_price(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<PreciseNumber> {
+        match trade_direction {
+            TradeDirection::AtoB => self
+                .calculator
+                .spot_price(swap_source_amount, swap_destination_amount),
+            TradeDirection::BtoA => PreciseNumber::new(1)?.checked_div(
+                &self
+                    .calculator
+                    .spot_price(swap_destination_amount, swap_source_amount)?,
+            ),
+        }
+    }
+
+    /// Swap as much of `source_amount` as possible, rather than failing when
+    /// the whole amount can't be filled.
+    ///
+    /// The fill stops before the swap fails, eg. by exceeding the destination
+    /// reserves, or before the spot price after the swap drops below
+    /// `price_limit`, given in destination tokens per source token scaled by
+    /// `10 ^ PRICE_LIMIT_DECIMALS`. Returns the result of the executed swap
+    /// along with the unfilled source amount, or `None` if nothing can be
+    /// filled.
+    ///
+    /// Binary searches the filled amount, assuming the spot price falls as
+    /// the trade size grows.
+    pub fn swap_partial(
+        &self,
+        source_amount: u64,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+        price_limit: Option<u64>,
+    ) -> Option<(SwapResult, u64)> {
+        let scale = PreciseNumber::new(10u128.pow(Self::PRICE_LIMIT_DECIMALS as u32))?;
+        let fill = |source_amount: u64| {
+            let result = self.swap(
+                u128::from(source_amount),
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+                fees,
+            )?;
+            if let Some(price_limit) = price_limit {
+                let price = self
+                    .directional_spot_price(
+                        result.new_swap_source_amount,
+                        result.new_swap_destination_amount,
+                        trade_direction,
+                    )?
+                    .checked_mul(&scale)?
+                    .floor()?
+                    .to_imprecise()?;
+                if price < u128::from(price_limit) {
+                    return None;
+                }
+            }
+            Some(result)
+        };
+        if let Some(result) = fill(source_amount) {
+            return Some((result, 0));
+        }
+        // invariant: `high` can't be filled, and `low` can, or is zero if no
+        // amount that can be filled was found yet
+        let (mut low, mut high) = (0, source_amount);
+        while high - low > 1 {
+            let middle = low + (high - low) / 2;
+            if fill(middle).is_some() {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        if low == 0 {
+            return None;
+        }
+        Some((fill(low)?, source_amount - low))
+    }
+
+    /// Find the largest amount of source token which can be swapped with a
+    /// price impact of at most `max_impact_bps`, as reported by `preview`.
+    ///
+    /// Binary searches the amount, assuming the price impact grows with the
+    /// trade size. Very small trades can have a larger impact due to rounding,
+    /// so the result is an amount within the limit for which one more token
+    /// exceeds it. Returns `None` if no such amount is found.
+    pub fn max_amount_in_for_impact(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        max_impact_bps: u128,
+        fees: &Fees,
+    ) -> Option<u64> {
+        let within_impact = |source_amount: u64| {
+            self.preview(
+                u128::from(source_amount),
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+                fees,
+            )
+            .is_some_and(|preview| preview.price_impact_bps <= max_impact_bps)
+        };
+        if within_impact(u64::MAX) {
+            return Some(u64::MAX);
+        }
+        // invariant: `high` is beyond the limit, and `low` is within it, or
+        // zero if no amount within the limit was found yet
+        let (mut low, mut high) = (0, u64::MAX);
+        while high - low > 1 {
+            let middle = low + (high - low) / 2;
+            if within_impact(middle) {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        if low == 0 {
+            None
+        } else {
+            Some(low)
+        }
+    }
+
+    /// Find the amount and direction of the swap moving the pool to
+    /// `target_price`, the spot price of token A in token B scaled by
+    /// `10 ^ PRICE_LIMIT_DECIMALS`.
+    ///
+    /// Prices move in steps of whole tokens, so this is the smallest amount
+    /// whose swap, fees included, reaches or passes the target. Returns an
+    /// amount of zero if the pool is already at the target, and `None` if no
+    /// swap of at most `u64::MAX` reaches the target.
+    ///
+    /// Binary searches the amount, assuming the spot price moves towards the
+    /// target as the trade size grows.
+    pub fn amount_to_reach_price(
+        &self,
+        target_price: u64,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        fees: &Fees,
+    ) -> Option<(u64, TradeDirection)> {
+        let target_price = u128::from(target_price);
+        let spot_price = |swap_token_a_amount, swap_token_b_amount| {
+            self.calculator.spot_price_decimal(
+                swap_token_a_amount,
+                swap_token_b_amount,
+                Self::PRICE_LIMIT_DECIMALS,
+            )
+        };
+        let current_price = spot_price(swap_token_a_amount, swap_token_b_amount)?;
+        // selling token A lowers its price, buying it raises it
+        let trade_direction = match current_price.cmp(&target_price) {
+            Ordering::Equal => return Some((0, TradeDirection::AtoB)),
+            Ordering::Greater => TradeDirection::AtoB,
+            Ordering::Less => TradeDirection::BtoA,
+        };
+        let (swap_source_amount, swap_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_token_a_amount, swap_token_b_amount),
+            TradeDirection::BtoA => (swap_token_b_amount, swap_token_a_amount),
+        };
+        let reaches_target = |source_amount: u64| {
+            let result = match self.swap(
+                u128::from(source_amount),
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+                fees,
+            ) {
+                Some(result) => result,
+                None => return false,
+            };
+            let price = match trade_direction {
+                TradeDirection::AtoB => spot_price(
+                    result.new_swap_source_amount,
+                    result.new_swap_destination_amount,
+                ),
+                TradeDirection::BtoA => spot_price(
+                    result.new_swap_destination_amount,
+                    result.new_swap_source_amount,
+                ),
+            };
+            match (price, trade_direction) {
+                (Some(price), TradeDirection::AtoB) => price <= target_price,
+                (Some(price), TradeDirection::BtoA) => price >= target_price,
+                (None, _) => false,
+            }
+        };
+        // double the amount until the target is reached, since swaps far too
+        // large for the pool may fail rather than overshoot
+        let (mut low, mut high) = (0, 1);
+        while !reaches_target(high) {
+            if high == u64::MAX {
+                return None;
+            }
+            low = high;
+            high = high.saturating_mul(2);
+        }
+        // invariant: `high` reaches the target, and `low` doesn't
+        while high - low > 1 {
+            let middle = low + (high - low) / 2;
+            if reaches_target(middle) {
+                high = middle;
+            } else {
+                low = middle;
+            }
+        }
+        Some((high, trade_direction))
+    }
+}
+
+/// Default implementation for SwapCurve cannot be derived because of
+/// the contained Arc.
+impl Default for SwapCurve {
+    fn default() -> Self {
+        let curve_type: CurveType = Default::default();
+        let calculator: ConstantProductCurve = Default::default();
+        Self {
+            curve_type,
+            calculator: Arc::new(calculator),
+            initial_supply: InitialSupply::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{calculator::geometric_mean_supply, fees::OwnerFeeDirection};
+    use proptest::prelude::*;
+
+    #[test]
+    fn constant_product_trade_fee() {
+        // calculation on https://github.com/solana-labs/solana-program-library/issues/341
+        let swap_source_amount = 1000;
+        let swap_destination_amount = 50000;
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 100;
+        let owner_trade_fee_numerator = 0;
+        let owner_trade_fee_denominator = 0;
+        let owner_withdraw_fee_numerator = 0;
+        let owner_withdraw_fee_denominator = 0;
+        let host_fee_numerator = 0;
+        let host_fee_denominator = 0;
+
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+            owner_fee_direction: OwnerFeeDirection::All,
+        };
+        let source_amount = 100;
+        let curve = ConstantProductCurve {};
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(curve),
+            initial_supply: InitialSupply::default(),
+        };
+        let result = swap_curve
+            .swap(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        assert_eq!(result.new_swap_source_amount, 1100);
+        assert_eq!(result.destination_amount_swapped, 4504);
+        assert_eq!(result.new_swap_destination_amount, 45496);
+        assert_eq!(result.trade_fee, 1);
+        assert_eq!(result.owner_fee, 0);
+    }
+
+    #[test]
+    fn initial_pool_tokens() {
+        let token_a_amount = 1_000;
+        let token_b_amount = 4_000_000;
+
+        let swap_curve = SwapCurve::default();
+        assert_eq!(
+            swap_curve.initial_pool_tokens(token_a_amount, token_b_amount),
+            Some(INITIAL_SWAP_POOL_AMOUNT)
+        );
+        let swap_curve = SwapCurve {
+            initial_supply: InitialSupply::Fixed(42),
+            ..SwapCurve::default()
+        };
+        assert_eq!(
+            swap_curve.initial_pool_tokens(token_a_amount, token_b_amount),
+            Some(42)
+        );
+
+        let swap_curve = SwapCurve {
+            initial_supply: InitialSupply::GeometricMean,
+            ..SwapCurve::default()
+        };
+        assert_eq!(
+            swap_curve.initial_pool_tokens(token_a_amount, token_b_amount),
+            Some(63_245)
+        );
+        // the supply follows the deposit, unlike the fixed supply
+        assert_eq!(
+            swap_curve.initial_pool_tokens(4 * token_a_amount, token_b_amount),
+            Some(126_491)
+        );
+        assert_eq!(
+            swap_curve.initial_pool_tokens(token_a_amount, token_b_amount),
+            geometric_mean_supply(token_a_amount, token_b_amount)
+        );
+    }
+
+    #[test]
+    fn creator_pool_tokens() {
+        let swap_curve = SwapCurve::default();
+        assert_eq!(
+            swap_curve.creator_pool_tokens(1_000, 4_000_000),
+            Some(swap_curve.calculator.new_pool_supply() - MINIMUM_LIQUIDITY)
+        );
+
+        // too small a deposit to lock the minimum liquidity
+        let swap_curve = SwapCurve {
+            initial_supply: InitialSupply::GeometricMean,
+            ..SwapCurve::default()
+        };
+        assert_eq!(swap_curve.creator_pool_tokens(1_000, 1_000), None);
+        assert_eq!(swap_curve.creator_pool_tokens(1_001, 1_001), Some(1));
+    }
+
+    #[test]
+    fn deposit_single_token_type() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default();
+        // an empty source reserve can't be deposited into
+        assert_eq!(
+            swap_curve.deposit_single_token_type(
+                1_000,
+                0,
+                1_000_000,
+                INITIAL_SWAP_POOL_AMOUNT,
+                TradeDirection::AtoB,
+                &fees
+            ),
+            Err(SwapError::EmptySupply)
+        );
+        // the destination reserve doesn't matter for a deposit
+        assert!(swap_curve
+            .deposit_single_token_type(
+                1_000,
+                0,
+                1_000_000,
+                INITIAL_SWAP_POOL_AMOUNT,
+                TradeDirection::BtoA,
+                &fees
+            )
+            .is_ok());
+
+        let without_fees = swap_curve
+            .deposit_single_token_type(
+                1_000,
+                1_000_000,
+                1_000_000,
+                INITIAL_SWAP_POOL_AMOUNT,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let with_fees = swap_curve
+            .deposit_single_token_type(
+                1_000,
+                1_000_000,
+                1_000_000,
+                INITIAL_SWAP_POOL_AMOUNT,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        assert!(with_fees < without_fees);
+    }
+
+    #[test]
+    fn deposit_single_token_type_fees_on_half() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let deposit = |source_amount| {
+            swap_curve
+                .calculator
+                .deposit_single_token_type(
+                    source_amount,
+                    1_000_000,
+                    1_000_000,
+                    INITIAL_SWAP_POOL_AMOUNT,
+                    TradeDirection::AtoB,
+                )
+                .unwrap()
+        };
+        let pool_tokens = swap_curve
+            .deposit_single_token_type(
+                100_000,
+                1_000_000,
+                1_000_000,
+                INITIAL_SWAP_POOL_AMOUNT,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
 
+        // only the half swapped for the other token pays trading fees
+        let half_fees = fees.trading_fee(50_000).unwrap() + fees.owner_trading_fee(50_000).unwrap();
+        assert_eq!(pool_tokens, deposit(100_000 - half_fees));
+        let full_fees =
+            fees.trading_fee(100_000).unwrap() + fees.owner_trading_fee(100_000).unwrap();
+        assert!(pool_tokens > deposit(100_000 - full_fees));
+    }
 
+    #[test]
+    fn owner_fee_to_pool_tokens() {
+        let swap_curve = SwapCurve::default();
+        let swap_token_a_amount = 1_000_000;
+        let swap_token_b_amount = 1_000_000;
+        let pool_supply = INITIAL_SWAP_POOL_AMOUNT;
+
+        let pool_tokens = swap_curve
+            .owner_fee_to_pool_tokens(
+                1_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert!(pool_tokens > 0);
+        // 0.1% of token A is worth roughly 0.05% of the pool
+        assert_eq!(pool_tokens, 499_875);
+
+        let double_pool_tokens = swap_curve
+            .owner_fee_to_pool_tokens(
+                2_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        // small fees are close to linear, with the square root only slightly
+        // penalizing the larger amount
+        assert!(double_pool_tokens <= 2 * pool_tokens);
+        assert!(double_pool_tokens >= 2 * pool_tokens * 999 / 1_000);
+
+        // the pool is balanced, so the direction doesn't matter
+        let opposite_pool_tokens = swap_curve
+            .owner_fee_to_pool_tokens(
+                1_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(opposite_pool_tokens, pool_tokens);
+    }
+
+    #[test]
+    fn pool_tokens_to_trading_tokens_empty_supply() {
+        let swap_curve = SwapCurve::default();
+        assert_eq!(
+            swap_curve.pool_tokens_to_trading_tokens(10, 0, 1_000, 1_000, RoundDirection::Floor),
+            Err(SwapError::EmptySupply)
+        );
+
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Arc::new(ConstantPriceCurve { token_b_price: 2 }),
+            ..SwapCurve::default()
+        };
+        assert_eq!(
+            swap_curve.pool_tokens_to_trading_tokens(10, 0, 1_000, 1_000, RoundDirection::Ceiling),
+            Err(SwapError::EmptySupply)
+        );
+
+        let result = swap_curve
+            .pool_tokens_to_trading_tokens(10, 100, 1_000, 1_000, RoundDirection::Floor)
+            .unwrap();
+        assert_eq!(result.token_a_amount, 150);
+        assert_eq!(result.token_b_amount, 75);
+    }
+
+    #[test]
+    fn simulate_sequential_trades() {
+        let fees = Fees {
+            trade_fee_numerator: 25,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 5,
+            owner_trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        let swap_curve = SwapCurve::default();
+        let trades = [
+            (10_000, TradeDirection::AtoB),
+            (10_000, TradeDirection::AtoB),
+            (10_000, TradeDirection::AtoB),
+        ];
+        let results = swap_curve
+            .simulate((1_000_000, 1_000_000), &trades, &fees)
+            .unwrap();
+        assert_eq!(results.len(), trades.len());
+
+        // the first trade starts from the initial reserves
+        let first = swap_curve
+            .swap(10_000, 1_000_000, 1_000_000, TradeDirection::AtoB, &fees)
+            .unwrap();
+        assert_eq!(results[0], first);
+
+        for window in results.windows(2) {
+            let (previous, next) = (&window[0], &window[1]);
+            // reserves are threaded from one trade into the next
+            let expected = swap_curve
+                .swap(
+                    10_000,
+                    previous.new_swap_source_amount,
+                    previous.new_swap_destination_amount,
+                    TradeDirection::AtoB,
+                    &fees,
+                )
+                .unwrap();
+            assert_eq!(*next, expected);
+            assert!(next.new_swap_source_amount > previous.new_swap_source_amount);
+            assert!(next.new_swap_destination_amount < previous.new_swap_destination_amount);
+            // slippage accumulates, so the same input buys less each time
+            assert!(next.destination_amount_swapped < previous.destination_amount_swapped);
+        }
+    }
+
+    #[test]
+    fn simulate_fails_on_invalid_trade() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default();
+        let trades = [(10, TradeDirection::AtoB), (0, TradeDirection::BtoA)];
+        assert!(swap_curve
+            .simulate((1_000_000, 1_000_000), &trades, &fees)
+            .is_none());
+    }
+
+    #[test]
+    fn from_parameters() {
+        let mut parameters = [0u8; SwapCurve::PARAMETERS_LEN];
+        let curve = StableCurve { amp: 100 };
+        Pack::pack_into_slice(&curve, &mut parameters);
+        let swap_curve = SwapCurve::from_parameters(CurveType::Stable, &parameters).unwrap();
+        assert_eq!(swap_curve.curve_type, CurveType::Stable);
+        let mut repacked = [0u8; SwapCurve::PARAMETERS_LEN];
+        swap_curve.calculator.pack_into_slice(&mut repacked);
+        assert_eq!(parameters, repacked);
+
+        let curve = ConcentratedCurve {
+            center_price: 2,
+            width: 1,
+        };
+        Pack::pack_into_slice(&curve, &mut parameters);
+        let swap_curve = SwapCurve::from_parameters(CurveType::Concentrated, &parameters).unwrap();
+        assert_eq!(swap_curve.curve_type, CurveType::Concentrated);
+        assert!(swap_curve.calculator.validate().is_ok());
+
+        let swap_curve = SwapCurve::from_parameters(CurveType::ConstantProduct, &[]).unwrap();
+        assert_eq!(swap_curve.curve_type, CurveType::ConstantProduct);
+    }
+
+    #[test]
+    fn pack_parameters() {
+        let calculators: [(CurveType, Arc<dyn CurveCalculator + Sync + Send>); 5] = [
+            (
+                CurveType::ConstantProduct,
+                Arc::new(ConstantProductCurve {}),
+            ),
+            (
+                CurveType::ConstantPrice,
+                Arc::new(ConstantPriceCurve { token_b_price: 10 }),
+            ),
+            (CurveType::Stable, Arc::new(StableCurve { amp: 100 })),
+            (
+                CurveType::Offset,
+                Arc::new(Offset {
+                    token_b_offset: 1_000,
+                }),
+            ),
+            (
+                CurveType::Concentrated,
+                Arc::new(ConcentratedCurve {
+                    center_price: 2,
+                    width: 1,
+                }),
+            ),
+        ];
+        for (curve_type, calculator) in calculators {
+            let swap_curve = SwapCurve {
+                curve_type,
+                calculator,
+                initial_supply: InitialSupply::default(),
+            };
+            let parameters = swap_curve.pack_parameters();
+            let unpacked = SwapCurve::from_parameters(curve_type, &parameters).unwrap();
+            assert_eq!(unpacked.curve_type, curve_type);
+            assert_eq!(
+                format!("{:?}", unpacked.calculator),
+                format!("{:?}", swap_curve.calculator)
+            );
+            assert_eq!(unpacked.pack_parameters(), parameters);
+        }
+    }
+
+    #[test]
+    fn amount_in_for_exact_out() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        for amount_out in [1, 10, 1_000, 100_000, 2_000_000] {
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                let amount_in = swap_curve
+                    .amount_in_for_exact_out(
+                        amount_out,
+                        1_000_000,
+                        4_000_000,
+                        trade_direction,
+                        &fees,
+                    )
+                    .unwrap();
+                let result = swap_curve
+                    .swap(
+                        u128::from(amount_in),
+                        1_000_000,
+                        4_000_000,
+                        trade_direction,
+                        &fees,
+                    )
+                    .unwrap();
+                assert!(result.destination_amount_swapped >= amount_out);
+            }
+        }
+
+        // the owner fee is only added in the direction it's charged
+        let directional_fees = Fees {
+            owner_fee_direction: OwnerFeeDirection::BtoA,
+            ..fees.clone()
+        };
+        let amount_in = |fees: &Fees| {
+            swap_curve
+                .amount_in_for_exact_out(100_000, 1_000_000, 4_000_000, TradeDirection::AtoB, fees)
+                .unwrap()
+        };
+        assert!(amount_in(&directional_fees) < amount_in(&fees));
+
+        // can't take out the whole pool, nor use curves without exact-out
+        assert!(swap_curve
+            .amount_in_for_exact_out(4_000_000, 1_000_000, 4_000_000, TradeDirection::AtoB, &fees)
+            .is_none());
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+            initial_supply: InitialSupply::default(),
+        };
+        assert!(swap_curve
+            .amount_in_for_exact_out(1_000, 1_000_000, 1_000_000, TradeDirection::AtoB, &fees)
+            .is_none());
+    }
+
+    #[test]
+    fn estimated_compute_units() {
+        for operation in [CurveOp::Swap, CurveOp::Deposit, CurveOp::Withdraw] {
+            assert!(
+                CurveType::Stable.estimated_compute_units(operation)
+                    > CurveType::ConstantProduct.estimated_compute_units(operation)
+            );
+            assert!(
+                CurveType::ConstantPrice.estimated_compute_units(operation)
+                    <= CurveType::ConstantProduct.estimated_compute_units(operation)
+            );
+        }
+        assert_eq!(
+            CurveType::Stable.estimated_compute_units(CurveOp::Swap),
+            2_000 + 2 * u32::from(ITERATIONS) * 1_500
+        );
+    }
+
+    #[test]
+    fn curve_type_from_u8() {
+        for curve_type in [
+            CurveType::ConstantProduct,
+            CurveType::ConstantPrice,
+            CurveType::Stable,
+            CurveType::Offset,
+            CurveType::Concentrated,
+        ] {
+            let tag = curve_type.try_to_vec().unwrap()[0];
+            assert_eq!(CurveType::from_u8(tag), Some(curve_type));
+        }
+        assert_eq!(CurveType::from_u8(5), None);
+    }
+
+    #[test]
+    fn from_parameters_invalid() {
+        // a stable curve tag with a zero amplifier
+        let parameters = [0u8; SwapCurve::PARAMETERS_LEN];
+        assert_eq!(
+            SwapCurve::from_parameters(CurveType::Stable, &parameters).unwrap_err(),
+            SwapError::InvalidCurveParameters.into()
+        );
+        assert_eq!(
+            SwapCurve::from_parameters(CurveType::Offset, &parameters).unwrap_err(),
+            SwapError::InvalidCurveParameters.into()
+        );
+        // too short to hold a concentrated curve
+        assert_eq!(
+            SwapCurve::from_parameters(CurveType::Concentrated, &[1, 0, 0]).unwrap_err(),
+            SwapError::InvalidCurveParameters.into()
+        );
+    }
+
+    #[test]
+    fn validate_swap_config() {
+        let curve = ConstantPriceCurve { token_b_price: 10 };
+        let fees = Fees::default().with_trade_fee_bps(30);
+        assert_eq!(super::validate_swap_config(&curve, &fees, 1_000, 0), Ok(()));
+
+        // fees are checked first
+        let bad_fees = Fees {
+            trade_fee_numerator: 2,
+            trade_fee_denominator: 1,
+            ..fees.clone()
+        };
+        let bad_curve = ConstantPriceCurve { token_b_price: 0 };
+        assert_eq!(
+            super::validate_swap_config(&bad_curve, &bad_fees, 0, 0),
+            Err(SwapError::InvalidFee)
+        );
+
+        // then the curve
+        assert_eq!(
+            super::validate_swap_config(&bad_curve, &fees, 0, 0),
+            Err(SwapError::InvalidCurve)
+        );
+
+        // then the supply, which the constant price curve only needs on side A
+        assert_eq!(
+            super::validate_swap_config(&curve, &fees, 0, 1_000),
+            Err(SwapError::EmptySupply)
+        );
+        assert_eq!(
+            super::validate_swap_config(&ConstantProductCurve, &fees, 1_000, 0),
+            Err(SwapError::EmptySupply)
+        );
+    }
+
+    #[test]
+    fn validate_batch() {
+        let fees = Fees::default().with_trade_fee_bps(30);
+        let constant_product = SwapCurve::default().pack_parameters();
+        let stable = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+            ..SwapCurve::default()
+        }
+        .pack_parameters();
+        let mut configs = vec![
+            (
+                CurveType::ConstantProduct,
+                &constant_product[..],
+                fees.clone(),
+                1_000,
+                1_000,
+            ),
+            (CurveType::Stable, &stable[..], fees.clone(), 1_000, 1_000),
+            (
+                CurveType::ConstantProduct,
+                &constant_product[..],
+                fees.clone(),
+                5_000,
+                2_000,
+            ),
+        ];
+        assert_eq!(super::validate_batch(&configs), Ok(()));
+        assert_eq!(super::validate_batch(&[]), Ok(()));
+
+        configs[2].2 = Fees {
+            trade_fee_numerator: 2,
+            trade_fee_denominator: 1,
+            ..fees.clone()
+        };
+        assert_eq!(
+            super::validate_batch(&configs),
+            Err((2, SwapError::InvalidFee))
+        );
+
+        // the first invalid config is reported
+        configs[1].3 = 0;
+        assert_eq!(
+            super::validate_batch(&configs),
+            Err((1, SwapError::EmptySupply))
+        );
+
+        // as are parameters which don't describe a curve of the type
+        configs[0].0 = CurveType::Stable;
+        assert_eq!(
+            super::validate_batch(&configs),
+            Err((0, SwapError::InvalidCurveParameters))
+        );
+    }
+
+    #[test]
+    fn best_route() {
+        let fees = Fees::default();
+        let one_to_one = SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Arc::new(ConstantPriceCurve { token_b_price: 1 }),
+            ..SwapCurve::default()
+        };
+        let direction = TradeDirection::AtoB;
+        let hop = (&one_to_one, 1_000_000, 1_000_000, &fees, direction);
+
+        // both routes pay out the amount in, so the shorter one wins
+        let routes = vec![vec![hop, hop], vec![hop]];
+        assert_eq!(super::best_route(&routes, 1_000), Some(1));
+        // then the lowest index
+        let routes = vec![vec![hop, hop], vec![hop], vec![hop]];
+        assert_eq!(super::best_route(&routes, 1_000), Some(1));
+
+        // a longer route paying out more still wins
+        let swap_curve = SwapCurve::default();
+        let expensive_hop = (&swap_curve, 1_000_000, 1_000_000, &fees, direction);
+        let routes = vec![vec![expensive_hop], vec![hop, hop]];
+        assert_eq!(super::best_route(&routes, 1_000), Some(1));
+
+        // routes which can't be swapped through are skipped
+        let empty_hop = (&swap_curve, 1_000_000, 0, &fees, direction);
+        let routes = vec![vec![hop, empty_hop], vec![], vec![expensive_hop]];
+        assert_eq!(super::best_route(&routes, 1_000), Some(2));
+        assert_eq!(super::best_route(&routes[..2], 1_000), None);
+        assert_eq!(super::best_route(&[], 1_000), None);
+    }
+
+    #[test]
+    fn fee_application_order() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default()
+            .with_trade_fee_bps(30)
+            .with_owner_trade_fee_bps(10);
+        let result = swap_curve
+            .swap(
+                1_000_000,
+                1_000_000_000,
+                1_000_000_000,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        // both fees are taken from the full input: the owner fee would be 997
+        // if it were taken after the trading fee
+        assert_eq!(result.trade_fee, 3_000);
+        assert_eq!(result.owner_fee, 1_000);
+        assert_eq!(
+            result,
+            SwapResult {
+                new_swap_source_amount: 1_001_000_000,
+                new_swap_destination_amount: 999_004_992,
+                source_amount_swapped: 1_000_000,
+                destination_amount_swapped: 995_008,
+                trade_fee: 3_000,
+                owner_fee: 1_000,
+            }
+        );
+    }
+
+    #[test]
+    fn swap_does_not_exceed_max_output() {
+        // curves whose calculations can exceed the destination reserve
+        let curves: [Arc<dyn CurveCalculator + Sync + Send>; 2] = [
+            Arc::new(ConstantPriceCurve { token_b_price: 10 }),
+            Arc::new(Offset {
+                token_b_offset: 1_000_000,
+            }),
+        ];
+        let fees = Fees::default();
+        for calculator in curves {
+            let max_output = calculator.max_output(1_000);
+            let swap_curve = SwapCurve {
+                calculator,
+                ..SwapCurve::default()
+            };
+            for source_amount in [1, 100, 1_000, 100_000, 10_000_000] {
+                for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                    if let Some(result) =
+                        swap_curve.swap(source_amount, 1_000, 1_000, trade_direction, &fees)
+                    {
+                        assert!(result.destination_amount_swapped <= max_output);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn owner_fee_to_pool_tokens_near_limits() {
+        let max = u128::from(u64::MAX);
+        let mut parameters = [0u8; SwapCurve::PARAMETERS_LEN];
+        parameters[0] = 100;
+        parameters[8] = 1;
+        for curve_type in [
+            CurveType::ConstantProduct,
+            CurveType::ConstantPrice,
+            CurveType::Stable,
+            CurveType::Offset,
+            CurveType::Concentrated,
+        ] {
+            let swap_curve = SwapCurve::from_parameters(curve_type, &parameters).unwrap();
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                // everything at the limit of a token account
+                assert!(swap_curve
+                    .owner_fee_to_pool_tokens(max, max, max, max, trade_direction)
+                    .is_some());
+                // a huge fee against tiny reserves
+                assert!(swap_curve
+                    .owner_fee_to_pool_tokens(max, 1, 1, max, trade_direction)
+                    .is_some());
+                // beyond token amounts, overflow is reported rather than
+                // panicking
+                assert_eq!(
+                    swap_curve.owner_fee_to_pool_tokens(max, 1, 1, u128::MAX, trade_direction),
+                    None
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn pool_tokens_for_deposit() {
+        let swap_curve = SwapCurve::default();
+        // a proportional deposit of 10% of the reserves mints 10% of the supply
+        assert_eq!(
+            swap_curve.pool_tokens_for_deposit(500, 2_000, 5_000, 20_000, 1_000),
+            Some(100)
+        );
+        // an imbalanced deposit mints the smaller side's amount
+        assert_eq!(
+            swap_curve.pool_tokens_for_deposit(1_000, 2_000, 5_000, 20_000, 1_000),
+            Some(100)
+        );
+        assert_eq!(
+            swap_curve.pool_tokens_for_deposit(500, 8_000, 5_000, 20_000, 1_000),
+            Some(100)
+        );
+        // fractional amounts are rounded down in favor of the pool
+        assert_eq!(
+            swap_curve.pool_tokens_for_deposit(10, 20, 30, 60, 10),
+            Some(3)
+        );
+        assert_eq!(
+            swap_curve.pool_tokens_for_deposit(500, 2_000, 0, 20_000, 1_000),
+            None
+        );
+    }
+
+    #[test]
+    fn deposit_dilution_bps() {
+        let swap_curve = SwapCurve::default();
+        // depositing as much as the reserves doubles the supply
+        assert_eq!(
+            swap_curve.deposit_dilution_bps(5_000, 20_000, 5_000, 20_000, 1_000),
+            Some(5_000)
+        );
+        assert_eq!(
+            swap_curve.deposit_dilution_bps(5, 20, 5_000_000, 20_000_000, 1_000_000),
+            Some(0)
+        );
+        assert_eq!(
+            swap_curve.deposit_dilution_bps(500, 2_000, 0, 20_000, 1_000),
+            None
+        );
+    }
+
+    #[test]
+    fn tokens_for_pool_tokens() {
+        let swap_curve = SwapCurve::default();
+        // 10% of the supply needs 10% of each reserve
+        assert_eq!(
+            swap_curve.tokens_for_pool_tokens(100, 1_000, 5_000, 20_000),
+            Some((500, 2_000))
+        );
+        // twice the pool tokens need twice the tokens
+        assert_eq!(
+            swap_curve.tokens_for_pool_tokens(200, 1_000, 5_000, 20_000),
+            Some((1_000, 4_000))
+        );
+        // fractional amounts are rounded up in favor of the pool
+        assert_eq!(
+            swap_curve.tokens_for_pool_tokens(1, 3, 10, 20),
+            Some((4, 7))
+        );
+        assert_eq!(swap_curve.tokens_for_pool_tokens(1, 0, 10, 20), None);
+
+        // amounts beyond u64 are rejected rather than truncated
+        let reserve = u64::MAX as u128;
+        assert_eq!(
+            swap_curve.tokens_for_pool_tokens(1_000, 1_000, reserve, 1),
+            Some((u64::MAX, 1))
+        );
+        assert_eq!(
+            swap_curve.tokens_for_pool_tokens(2_000, 1_000, reserve, 1),
+            None
+        );
+    }
+
+    #[test]
+    fn withdraw_preview() {
+        let swap_curve = SwapCurve::default();
+        // 10% of the supply gets 10% of each reserve
+        assert_eq!(
+            swap_curve.withdraw_preview(100, 1_000, 5_000, 20_000, &Fees::default()),
+            Some(WithdrawPreview {
+                token_a_out: 500,
+                token_b_out: 2_000,
+                owner_withdraw_fee: 0,
+            })
+        );
+
+        // a 1% fee leaves 99 pool tokens to convert, rounding down
+        let fees = Fees {
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 100,
+            ..Fees::default()
+        };
+        assert_eq!(
+            swap_curve.withdraw_preview(100, 1_000, 5_005, 20_000, &fees),
+            Some(WithdrawPreview {
+                token_a_out: 495,
+                token_b_out: 1_980,
+                owner_withdraw_fee: 1,
+            })
+        );
+
+        assert_eq!(
+            swap_curve.withdraw_preview(100, 0, 5_000, 20_000, &fees),
+            None
+        );
+    }
+
+    #[test]
+    fn preview() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees {
+            trade_fee_numerator: 25,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 5,
+            owner_trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+
+        let mut previous_impact = 0;
+        for source_amount in [1_000, 10_000, 100_000, 1_000_000] {
+            let preview = swap_curve
+                .preview(
+                    source_amount,
+                    1_000_000,
+                    4_000_000,
+                    TradeDirection::AtoB,
+                    &fees,
+                )
+                .unwrap();
+            let result = swap_curve
+                .swap(
+                    source_amount,
+                    1_000_000,
+                    4_000_000,
+                    TradeDirection::AtoB,
+                    &fees,
+                )
+                .unwrap();
+            assert_eq!(preview.amount_out, result.destination_amount_swapped);
+            assert_eq!(preview.trade_fee, result.trade_fee);
+            assert_eq!(preview.owner_fee, result.owner_fee);
+            assert_eq!(preview.spot_price_before, PreciseNumber::new(4).unwrap());
+            assert!(preview
+                .spot_price_before
+                .greater_than(&preview.effective_price));
+            assert!(preview.price_impact_bps > previous_impact);
+            previous_impact = preview.price_impact_bps;
+        }
+        // swapping close to the whole reserve, after fees, nearly halves the
+        // price
+        assert_eq!(previous_impact, 4_992);
+
+        // prices are always in destination tokens per source token
+        let preview = swap_curve
+            .preview(1_000, 4_000_000, 1_000_000, TradeDirection::BtoA, &fees)
+            .unwrap();
+        assert_eq!(
+            preview.spot_price_before,
+            PreciseNumber::new(1)
+                .unwrap()
+                .checked_div(&PreciseNumber::new(4).unwrap())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn max_amount_in_for_impact() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let preview_impact = |source_amount: u64| {
+            swap_curve
+                .preview(
+                    u128::from(source_amount),
+                    1_000_000,
+                    4_000_000,
+                    TradeDirection::AtoB,
+                    &fees,
+                )
+                .unwrap()
+                .price_impact_bps
+        };
+        for max_impact_bps in [10, 100, 1_000] {
+            let amount = swap_curve
+                .max_amount_in_for_impact(
+                    1_000_000,
+                    4_000_000,
+                    TradeDirection::AtoB,
+                    max_impact_bps,
+                    &fees,
+                )
+                .unwrap();
+            assert!(preview_impact(amount) <= max_impact_bps);
+            assert!(preview_impact(amount + 1) > max_impact_bps);
+        }
+
+        // a tiny pool can't take a single token within 1bps
+        assert_eq!(
+            swap_curve.max_amount_in_for_impact(10, 10, TradeDirection::AtoB, 1, &fees),
+            None
+        );
+    }
+
+    #[test]
+    fn swap_partial_price_limit() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let spot_price = |result: &SwapResult| {
+            swap_curve
+                .calculator
+                .spot_price_decimal(
+                    result.new_swap_source_amount,
+                    result.new_swap_destination_amount,
+                    SwapCurve::PRICE_LIMIT_DECIMALS,
+                )
+                .unwrap()
+        };
+
+        // the price of 4 B per A may only drop to 1 B per A, which is reached
+        // at about 1_000_000 A in
+        let price_limit = 1_000_000;
+        let (result, unfilled) = swap_curve
+            .swap_partial(
+                5_000_000,
+                1_000_000,
+                4_000_000,
+                TradeDirection::AtoB,
+                &fees,
+                Some(price_limit),
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped + unfilled as u128, 5_000_000);
+        assert!(result.source_amount_swapped > 900_000);
+        assert!(result.source_amount_swapped < 1_100_000);
+        assert!(spot_price(&result) >= price_limit as u128);
+        let next = swap_curve
+            .swap(
+                result.source_amount_swapped + 1,
+                1_000_000,
+                4_000_000,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        assert!(spot_price(&next) < price_limit as u128);
+
+        // the price is already below the limit
+        assert!(swap_curve
+            .swap_partial(
+                5_000_000,
+                1_000_000,
+                4_000_000,
+                TradeDirection::AtoB,
+                &fees,
+                Some(5_000_000),
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn swap_partial_fills_fully() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default().with_trade_fee_bps(25);
+        for price_limit in [None, Some(1)] {
+            let expected = swap_curve
+                .swap(5_000_000, 1_000_000, 4_000_000, TradeDirection::BtoA, &fees)
+                .unwrap();
+            assert_eq!(
+                swap_curve.swap_partial(
+                    5_000_000,
+                    1_000_000,
+                    4_000_000,
+                    TradeDirection::BtoA,
+                    &fees,
+                    price_limit,
+                ),
+                Some((expected, 0))
+            );
+        }
+    }
+
+    #[test]
+    fn swap_partial_destination_reserves() {
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Arc::new(ConstantPriceCurve { token_b_price: 1 }),
+            initial_supply: InitialSupply::default(),
+        };
+        let fees = Fees::default();
+        assert!(swap_curve
+            .swap(5_000, 1_000, 1_000, TradeDirection::AtoB, &fees)
+            .is_none());
+        let (result, unfilled) = swap_curve
+            .swap_partial(5_000, 1_000, 1_000, TradeDirection::AtoB, &fees, None)
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, 1_000);
+        assert_eq!(result.new_swap_destination_amount, 0);
+        assert_eq!(unfilled, 4_000);
+    }
+
+    #[test]
+    fn is_favorable() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let (swap_source_amount, swap_destination_amount) = (1_000_000_000_000, 4_000_000_000_000);
+        let is_favorable = |source_amount| {
+            swap_curve.is_favorable(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &fees,
+            )
+        };
+        assert!(is_favorable(1_000_000));
+
+        // a single token buys 3 rather than 4 B, as the output is rounded down
+        assert!(!is_favorable(1));
+        assert_eq!(
+            swap_curve
+                .preview(
+                    1,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    TradeDirection::AtoB,
+                    &Fees::default()
+                )
+                .unwrap()
+                .amount_out,
+            3
+        );
+
+        // moving the price by more than 1% isn't favorable either
+        assert!(!is_favorable(100_000_000_000));
+
+        // nor is losing more than 1% to fees, even without moving the price
+        let fees = Fees::default().with_trade_fee_bps(200);
+        assert!(!swap_curve.is_favorable(
+            1_000_000,
+            swap_source_amount,
+            swap_destination_amount,
+            TradeDirection::AtoB,
+            &fees,
+        ));
+    }
+
+    #[test]
+    fn amount_to_reach_price() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let (swap_token_a_amount, swap_token_b_amount) = (1_000_000, 1_000_000);
+        let price_after = |source_amount: u64, trade_direction| {
+            let (swap_source_amount, swap_destination_amount) = match trade_direction {
+                TradeDirection::AtoB => (swap_token_a_amount, swap_token_b_amount),
+                TradeDirection::BtoA => (swap_token_b_amount, swap_token_a_amount),
+            };
+            let result = swap_curve
+                .swap(
+                    u128::from(source_amount),
+                    swap_source_amount,
+                    swap_destination_amount,
+                    trade_direction,
+                    &fees,
+                )
+                .unwrap();
+            let (new_swap_token_a_amount, new_swap_token_b_amount) = match trade_direction {
+                TradeDirection::AtoB => (
+                    result.new_swap_source_amount,
+                    result.new_swap_destination_amount,
+                ),
+                TradeDirection::BtoA => (
+                    result.new_swap_destination_amount,
+                    result.new_swap_source_amount,
+                ),
+            };
+            swap_curve
+                .calculator
+                .spot_price_decimal(
+                    new_swap_token_a_amount,
+                    new_swap_token_b_amount,
+                    SwapCurve::PRICE_LIMIT_DECIMALS,
+                )
+                .unwrap()
+        };
+
+        // from 1 B per A up to 4 B per A, buying A with about 1_000_000 B
+        let target_price = 4_000_000;
+        let (amount, trade_direction) = swap_curve
+            .amount_to_reach_price(
+                target_price,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                &fees,
+            )
+            .unwrap();
+        assert_eq!(trade_direction, TradeDirection::BtoA);
+        assert!(amount > 1_000_000);
+        assert!(amount < 1_010_000);
+        assert!(price_after(amount, trade_direction) >= u128::from(target_price));
+        assert!(price_after(amount - 1, trade_direction) < u128::from(target_price));
+
+        // down to 0.25 B per A, selling about 1_000_000 A
+        let target_price = 250_000;
+        let (amount, trade_direction) = swap_curve
+            .amount_to_reach_price(
+                target_price,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                &fees,
+            )
+            .unwrap();
+        assert_eq!(trade_direction, TradeDirection::AtoB);
+        assert!(amount > 1_000_000);
+        assert!(amount < 1_010_000);
+        assert!(price_after(amount, trade_direction) <= u128::from(target_price));
+        assert!(price_after(amount - 1, trade_direction) > u128::from(target_price));
+
+        // already there
+        assert_eq!(
+            swap_curve.amount_to_reach_price(
+                1_000_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                &fees
+            ),
+            Some((0, TradeDirection::AtoB))
+        );
+    }
+
+    #[test]
+    fn lp_value_in_token_a() {
+        let swap_curve = SwapCurve::default();
+        // 10% of a pool with 1_000 A and 4_000 B, where 1 B is worth 0.25 A
+        assert_eq!(
+            swap_curve.lp_value_in_token_a(100, 1_000, 1_000, 4_000),
+            Some(200)
+        );
+        assert_eq!(swap_curve.lp_value_in_token_a(0, 1_000, 1_000, 4_000), Some(0));
+        assert_eq!(swap_curve.lp_value_in_token_a(100, 0, 1_000, 4_000), None);
+
+        // B is always worth `token_b_price` A, regardless of the reserves, so
+        // the pool is worth 1_000 + 4_000 * 2 A
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Arc::new(ConstantPriceCurve { token_b_price: 2 }),
+            ..SwapCurve::default()
+        };
+        assert_eq!(
+            swap_curve.lp_value_in_token_a(100, 1_000, 1_000, 4_000),
+            Some(900)
+        );
+    }
+
+    #[test]
+    fn is_thin() {
+        let swap_curve = SwapCurve::default();
+        assert_eq!(
+            swap_curve.liquidity_depth(1_000_000, 4_000_000),
+            PreciseNumber::new(2_000_000)
+        );
+        assert!(!swap_curve.is_thin(1_000_000, 4_000_000, 10_000));
+        assert!(swap_curve.is_thin(10, 40, 10_000));
+        assert!(!swap_curve.is_thin(5_000, 20_000, 10_000));
+        assert!(swap_curve.is_thin(0, 0, 1));
+        assert!(!swap_curve.is_thin(0, 0, 0));
+    }
+
+    #[test]
+    fn spot_price_q64() {
+        let swap_curve = SwapCurve::default();
+        assert_eq!(swap_curve.spot_price_q64(1_000, 1_000), Some(1 << 64));
+        assert_eq!(swap_curve.spot_price_q64(1_000, 2_000), Some(2 << 64));
+        assert_eq!(swap_curve.spot_price_q64(2_000, 1_000), Some(1 << 63));
+        assert_eq!(swap_curve.spot_price_q64(1, 1 << 64), None);
+        assert_eq!(swap_curve.spot_price_q64(0, 1_000), None);
+    }
+
+    #[test]
+    fn pool_token_price_unchanged_by_proportional_deposit() {
+        let swap_curve = SwapCurve::default();
+        let pool_supply = 1_000_000;
+        let swap_token_a_amount = 1_000_000_000;
+        let swap_token_b_amount = 4_000_000_000;
+        let price = swap_curve
+            .pool_token_price(pool_supply, swap_token_a_amount, swap_token_b_amount)
+            .unwrap();
+        assert_eq!(price, PreciseNumber::new(2_000).unwrap());
+
+        let pool_tokens = 12_345;
+        let deposit = swap_curve
+            .pool_tokens_to_trading_tokens(
+                pool_tokens,
+                pool_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                RoundDirection::Ceiling,
+            )
+            .unwrap();
+        let new_price = swap_curve
+            .pool_token_price(
+                pool_supply + pool_tokens,
+                swap_token_a_amount + deposit.token_a_amount,
+                swap_token_b_amount + deposit.token_b_amount,
+            )
+            .unwrap();
+        // rounding up the deposit can only increase the price, by less than
+        // one token in the normalized value
+        assert!(new_price.greater_than_or_equal(&price));
+        let epsilon = PreciseNumber::new(1)
+            .unwrap()
+            .checked_div(&PreciseNumber::new(pool_supply).unwrap())
+            .unwrap();
+        assert!(new_price
+            .checked_sub(&price)
+            .unwrap()
+            .less_than_or_equal(&epsilon));
+
+        assert!(swap_curve
+            .pool_token_price(0, swap_token_a_amount, swap_token_b_amount)
+            .is_none());
+    }
+
+    #[test]
+    fn owner_fee_direction() {
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 100,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 100,
+            owner_fee_direction: OwnerFeeDirection::AtoB,
+            ..Fees::default()
+        };
+        let swap_curve = SwapCurve::default();
+
+        // matching direction, both fees are charged
+        let result = swap_curve
+            .swap(1_000, 100_000, 100_000, TradeDirection::AtoB, &fees)
+            .unwrap();
+        assert_eq!(result.trade_fee, 10);
+        assert_eq!(result.owner_fee, 10);
+
+        // other direction, only the trade fee is charged
+        let result = swap_curve
+            .swap(1_000, 100_000, 100_000, TradeDirection::BtoA, &fees)
+            .unwrap();
+        assert_eq!(result.trade_fee, 10);
+        assert_eq!(result.owner_fee, 0);
+        assert_eq!(result.source_amount_swapped, 1_000);
+
+        // no direction given, owner fees on all trades
+        let fees = Fees {
+            owner_fee_direction: OwnerFeeDirection::All,
+            ..fees
+        };
+        let result = swap_curve
+            .swap(1_000, 100_000, 100_000, TradeDirection::BtoA, &fees)
+            .unwrap();
+        assert_eq!(result.owner_fee, 10);
+    }
+
+    #[test]
+    fn swap_exact_out() {
+        let fees = Fees {
+            trade_fee_numerator: 25,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 5,
+            owner_trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        let swap_curve = SwapCurve::default();
+        for destination_amount in [1, 10, 4_504, 40_000] {
+            let result = swap_curve
+                .swap_exact_out(
+                    destination_amount,
+                    1_000,
+                    50_000,
+                    TradeDirection::AtoB,
+                    &fees,
+                )
+                .unwrap();
+            assert!(result.destination_amount_swapped >= destination_amount);
+        }
+        // can't take everything out of the pool
+        assert!(swap_curve
+            .swap_exact_out(50_000, 1_000, 50_000, TradeDirection::AtoB, &fees)
+            .is_none());
+
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+            ..SwapCurve::default()
+        };
+        assert!(swap_curve
+            .swap_exact_out(10, 1_000, 50_000, TradeDirection::AtoB, &fees)
+            .is_none());
+    }
+
+    /// Check that applying a full swap, fees included, to the reserves never
+    /// lowers the value of the pool. Swaps which fail, eg. by running out of
+    /// destination tokens, or which overflow a token account are skipped.
+    fn check_curve_value_from_swap_with_fees(
+        swap_curve: &SwapCurve,
+        fees: &Fees,
+        source_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        trade_direction: TradeDirection,
+    ) {
+        let (swap_source_amount, swap_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_token_a_amount, swap_token_b_amount),
+            TradeDirection::BtoA => (swap_token_b_amount, swap_token_a_amount),
+        };
+        let Some(result) = swap_curve.swap(
+            source_token_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+            fees,
+        ) else {
+            return;
+        };
+        if result.new_swap_source_amount > u64::MAX as u128 {
+            return;
+        }
+        let (new_swap_token_a_amount, new_swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (
+                result.new_swap_source_amount,
+                result.new_swap_destination_amount,
+            ),
+            TradeDirection::BtoA => (
+                result.new_swap_destination_amount,
+                result.new_swap_source_amount,
+            ),
+        };
+        let previous_value = swap_curve
+            .calculator
+            .normalized_value(swap_token_a_amount, swap_token_b_amount)
+            .unwrap();
+        let new_value = swap_curve
+            .calculator
+            .normalized_value(new_swap_token_a_amount, new_swap_token_b_amount)
+            .unwrap();
+        assert!(new_value.greater_than_or_equal(&previous_value));
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap_with_fees(
+            source_token_amount in 1..u64::MAX,
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u64::MAX,
+            token_b_price in 1..u32::MAX,
+            trade_fee_bps in 0..5_000u64,
+            owner_trade_fee_bps in 0..5_000u64,
+        ) {
+            let fees = Fees::default()
+                .with_trade_fee_bps(trade_fee_bps)
+                .with_owner_trade_fee_bps(owner_trade_fee_bps);
+            let swap_curves = [
+                SwapCurve::default(),
+                SwapCurve {
+                    curve_type: CurveType::ConstantPrice,
+                    calculator: Arc::new(ConstantPriceCurve {
+                        token_b_price: u64::from(token_b_price),
+                    }),
+                    initial_supply: InitialSupply::default(),
+                },
+            ];
+            for swap_curve in &swap_curves {
+                for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                    check_curve_value_from_swap_with_fees(
+                        swap_curve,
+                        &fees,
+                        source_token_amount as u128,
+                        swap_token_a_amount as u128,
+                        swap_token_b_amount as u128,
+                        trade_direction,
+                    );
+                }
+            }
+        }
+    }
+}
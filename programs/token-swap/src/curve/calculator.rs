@@ -1,9 +1,13 @@
 //! Swap calculations
 
 use {
-    crate::errors::SwapError, 
-    spl_math::precise_number::PreciseNumber,
-    std::fmt::Debug,
+    crate::{
+        curve::fees::{Fees, BPS_DENOMINATOR},
+        errors::SwapError,
+    },
+    anchor_lang::prelude::{borsh, AnchorDeserialize, AnchorSerialize},
+    spl_math::{precise_number::PreciseNumber, uint::U256},
+    std::{any::Any, fmt::Debug},
 };
 
 #[cfg(feature = "fuzz")]
@@ -15,6 +19,11 @@ use arbitrary::Arbitrary;
 /// input amounts, and Balancer uses 100 * 10 ^ 18
 pub const INITIAL_SWAP_POOL_AMOUNT: u128 = 1_000_000_000;
 
+/// Amount of the initial pool tokens locked forever in the pool, mirroring
+/// Uniswap v2, so that the pool token supply can never be brought back to
+/// zero and a first depositor can't inflate the value of a single pool token
+pub const MINIMUM_LIQUIDITY: u128 = 1_000;
+
 /// HardCode the number of token types in a pool, used to calculate the
 /// equivalent pool tokens for the owner trading fee.
 pub const TOKENS_IN_POOL: u128 = 2;
@@ -28,11 +37,125 @@ pub fn map_zero_to_none(x: u128) -> Option<u128> {
     }
 }
 
+/// Convert a whole `PreciseNumber` back to a `u128`, returning `None` rather
+/// than panicking if it doesn't fit
+pub fn precise_to_u128(value: &PreciseNumber) -> Option<u128> {
+    if value.greater_than(&PreciseNumber::new(u128::MAX)?) {
+        None
+    } else {
+        value.to_imprecise()
+    }
+}
+
+/// Square root of a whole number, as computed by `PreciseNumber::sqrt` with
+/// Newton's method.
+///
+/// The result is precise to 11 significant digits over the whole `u128`
+/// range, and perfect squares round back to their exact root with
+/// `to_imprecise`.
+pub fn precise_sqrt(value: u128) -> Option<PreciseNumber> {
+    PreciseNumber::new(value)?.sqrt()
+}
+
+/// Uniswap-style initial pool token supply for a deposit of `token_a_amount`
+/// and `token_b_amount`: their geometric mean `sqrt(a * b)`, rounded down.
+///
+/// This is the constant product curve's normalized value, which is what
+/// `InitialSupply::GeometricMean` mints for that curve. Like `precise_sqrt`,
+/// the result is precise to 11 significant digits. Returns `None` if the
+/// product overflows, which can't happen for token amounts up to `u64::MAX`.
+pub fn geometric_mean_supply(token_a_amount: u128, token_b_amount: u128) -> Option<u128> {
+    precise_sqrt(token_a_amount.checked_mul(token_b_amount)?)?
+        .floor()?
+        .to_imprecise()
+}
+
+/// Normalized value of reserves given the spot price of token A in token B:
+/// the geometric mean of the reserves' value in token A and in token B, halved.
+///
+/// That is `(a * price + b) / (2 * sqrt(price))`, which gives the square root
+/// of the Uniswap invariant for constant product prices. The spot price only
+/// has `PreciseNumber`'s 12 decimals, so the value loses precision as the
+/// price gets very small, and is `None` once it rounds to zero.
+pub fn normalized_value_from_spot_price(
+    swap_token_a_amount: u128,
+    swap_token_b_amount: u128,
+    spot_price: &PreciseNumber,
+) -> Option<PreciseNumber> {
+    PreciseNumber::new(swap_token_a_amount)?
+        .checked_mul(spot_price)?
+        .checked_add(&PreciseNumber::new(swap_token_b_amount)?)?
+        .checked_div(&spot_price.sqrt()?.checked_mul(&PreciseNumber::new(2)?)?)
+}
+
+/// Impermanent loss of a liquidity position between two snapshots of the pool
+/// reserves, in basis points.
+///
+/// Both positions are valued in token B at the current spot price: holding the
+/// initial reserves, against owning the current reserves. The current reserves
+/// are scaled by the change in `normalized_value`, so that liquidity added or
+/// removed in between doesn't count as a gain or loss. Returns 0 if the pool
+/// position is worth at least as much as holding.
+pub fn impermanent_loss_bps(
+    curve: &dyn CurveCalculator,
+    initial: (u128, u128),
+    current: (u128, u128),
+) -> Option<u128> {
+    let price = curve.spot_price(current.0, current.1)?;
+    let value = |(token_a, token_b): (u128, u128)| {
+        PreciseNumber::new(token_a)?
+            .checked_mul(&price)?
+            .checked_add(&PreciseNumber::new(token_b)?)
+    };
+    let held = value(initial)?.checked_mul(&curve.normalized_value(current.0, current.1)?)?;
+    let pooled = value(current)?.checked_mul(&curve.normalized_value(initial.0, initial.1)?)?;
+    if !pooled.less_than(&held) {
+        return Some(0);
+    }
+    held.checked_sub(&pooled)?
+        .checked_mul(&PreciseNumber::new(BPS_DENOMINATOR as u128)?)?
+        .checked_div(&held)?
+        .floor()?
+        .to_imprecise()
+}
+
+/// Share of the pool diluted by minting `minted` pool tokens on top of
+/// `pool_supply`, in basis points of the new supply, rounding down. Returns 0
+/// if no pool tokens exist at all.
+pub fn dilution_bps(pool_supply: u128, minted: u128) -> u128 {
+    let new_supply = U256::from(pool_supply) + U256::from(minted);
+    if new_supply.is_zero() {
+        return 0;
+    }
+    (U256::from(minted) * U256::from(BPS_DENOMINATOR) / new_supply).as_u128()
+}
+
+impl Clone for Box<dyn CurveCalculator + Sync + Send> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl dyn CurveCalculator + '_ {
+    /// Get the concrete curve, eg. to read the `amp` of a `StableCurve`, or
+    /// `None` if the curve is of another type
+    pub fn downcast_ref<T: CurveCalculator + 'static>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+}
+
+impl dyn CurveCalculator + Sync + Send + '_ {
+    /// Get the concrete curve, or `None` if the curve is of another type
+    pub fn downcast_ref<T: CurveCalculator + 'static>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+}
+
 /// The direction of a trade, since curves can be specialized to treat each
 /// token differently (by adding offsets or weights)
 #[cfg_attr(feature = "fuzz", derive(Arbitrary))]
 #[repr(C)]
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
 pub enum TradeDirection {
     /// Input token A, output Token B
     AtoB,
@@ -81,6 +204,29 @@ pub struct TradingTokenResult {
     pub token_b_amount: u128,
 }
 
+/// `TradingTokenResult` reporting, for each side, whether the amount was
+/// rounded down from the exact share of the pool, leaving dust in the pool
+#[derive(Debug, PartialEq)]
+pub struct TradingTokenResultWithRounding {
+    /// Amount of token A
+    pub token_a_amount: u128,
+    /// Amount of token B
+    pub token_b_amount: u128,
+    /// Token A amount is below the exact share
+    pub token_a_rounded_down: bool,
+    /// Token B amount is below the exact share
+    pub token_b_rounded_down: bool,
+}
+
+impl From<TradingTokenResultWithRounding> for TradingTokenResult {
+    fn from(result: TradingTokenResultWithRounding) -> Self {
+        Self {
+            token_a_amount: result.token_a_amount,
+            token_b_amount: result.token_b_amount,
+        }
+    }
+}
+
 /// Trait for packing of trait objects, required because structs that implement
 /// `Pack` cannot be used as trait objects (as `dyn Pack`)
 pub trait DynPack {
@@ -88,7 +234,7 @@ pub trait DynPack {
 }
 
 /// Trait representing operations required on a swap curve
-pub trait CurveCalculator: Debug {
+pub trait CurveCalculator: Debug + DynPack {
     /// Calculate how much destination token will be provided given an amount
     /// of course token.
     fn swap_without_fees(
@@ -99,6 +245,27 @@ pub trait CurveCalculator: Debug {
         trade_direction: TradeDirection,
     ) -> Option<SwapWithoutFeesResult>;
 
+    /// Whether the curve can calculate the source amount needed for an exact
+    /// destination amount, through `swap_without_fees_exact_out`
+    fn supports_exact_out(&self) -> bool {
+        false
+    }
+
+    /// Calculate how much source token is needed to receive at least
+    /// `destination_amount` of destination token.
+    ///
+    /// Only implemented by curves that return true from `supports_exact_out`,
+    /// the default implementation always returns `None`.
+    fn swap_without_fees_exact_out(
+        &self,
+        _destination_amount: u128,
+        _swap_source_amount: u128,
+        _swap_destination_amount: u128,
+        _trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        None
+    }
+
     /// Get the supply for a new pool
     /// The default implementation is Balancer-style fixed initial supply
     fn new_pool_supply(&self) -> u128 {
@@ -107,6 +274,8 @@ pub trait CurveCalculator: Debug {
 
     /// Get the amount of trading tokens for the given amount of pool tokens,
     /// provided the total trading tokens and supply of pool tokens
+    ///
+    /// Implementations must return `None` when `pool_token_supply` is zero
     fn pool_tokens_to_trading_tokens(
         &self,
         pool_tokens: u128,
@@ -131,6 +300,26 @@ pub trait CurveCalculator: Debug {
         trade_direction: TradeDirection,
     ) -> Option<u128>;
 
+    /// Same as `deposit_single_token_type`, reporting why the calculation
+    /// failed. By default any failure is a `CalculationFailure`.
+    fn try_deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Result<u128, SwapError> {
+        self.deposit_single_token_type(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+        .ok_or(SwapError::CalculationFailure)
+    }
+
     /// Get the amount of pool tokens for the withdrawn amount of token A or B.
     ///
     /// This is used for single-sided withdrawals and owner trade fee
@@ -146,18 +335,60 @@ pub trait CurveCalculator: Debug {
         trade_direction: TradeDirection,
     ) -> Option<u128>;
 
+    /// Get an owned deep copy of the curve, eg. to modify it independently of
+    /// the `SwapCurve` sharing it
+    fn clone_box(&self) -> Box<dyn CurveCalculator + Sync + Send>;
+
+    /// Get the curve as `Any`, to recover its concrete type with
+    /// `downcast_ref`
+    fn as_any(&self) -> &dyn Any;
+
     /// Validate that the given curve has no invalid parameters
     fn validate(&self) -> Result<(), SwapError>;
 
-    /// Validate the given supply on initialization. This is useful for curves
-    /// that allow zero supply on one or both sides, since the standard constant
-    /// product curve must have a non-zero supply on both sides
+    /// Validate the curve parameters along with the reserves they are used
+    /// with, also rejecting combinations for which the normalized value of
+    /// the pool overflows
+    fn validate_with_reserves(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Result<(), SwapError> {
+        self.validate()?;
+        self.normalized_value(swap_token_a_amount, swap_token_b_amount)
+            .ok_or(SwapError::InvalidCurve)?;
+        Ok(())
+    }
+
+    /// The side of the pool which may start with an empty supply, if any,
+    /// given as the trade direction paying out of that side: `AtoB` means
+    /// token B may be empty, `BtoA` means token A may be empty. The standard
+    /// constant product curve must have a non-zero supply on both sides
+    fn allows_empty_side(&self) -> Option<TradeDirection> {
+        None
+    }
+
+    /// The smallest token A and token B reserves a pool can start with and
+    /// still pay out something on a swap
+    fn minimum_initial_reserves(&self) -> (u64, u64) {
+        (1, 1)
+    }
+
+    /// Validate the given supply on initialization, only allowing zero supply
+    /// on the side reported by `allows_empty_side`, and otherwise requiring
+    /// `minimum_initial_reserves`
     fn validate_supply(&self, token_a_amount: u64, token_b_amount: u64) -> Result<(), SwapError> {
-        if token_a_amount == 0 {
+        let empty_side = self.allows_empty_side();
+        if token_a_amount == 0 && empty_side != Some(TradeDirection::BtoA) {
+            return Err(SwapError::EmptySupply);
+        }
+
+        if token_b_amount == 0 && empty_side != Some(TradeDirection::AtoB) {
             return Err(SwapError::EmptySupply);
         }
 
-        if token_b_amount == 0 {
+        let (minimum_token_a_amount, minimum_token_b_amount) = self.minimum_initial_reserves();
+        if token_a_amount < minimum_token_a_amount || token_b_amount < minimum_token_b_amount {
             return Err(SwapError::EmptySupply);
         }
 
@@ -172,6 +403,122 @@ pub trait CurveCalculator: Debug {
         true
     }
 
+    /// The smallest amount of source token that yields at least one
+    /// destination token after fees, since fees charge a minimum of one token
+    /// and can eat small trades entirely.
+    ///
+    /// The default implementation is a conservative estimate assuming one
+    /// source token swaps for at least one destination token.
+    fn minimum_trade_amount(&self, fees: &Fees) -> u128 {
+        fees.pre_trading_fee_amount(1).unwrap_or(u128::MAX)
+    }
+
+    /// Get the most destination token any trade can extract from the pool,
+    /// used to bound trade sizes in clients.
+    ///
+    /// No trade can ever get more than the destination reserve.
+    fn max_output(&self, swap_destination_amount: u128) -> u128 {
+        swap_destination_amount
+    }
+
+    /// Whether the curve can price any swap at the given reserves, so clients
+    /// can disable trading on a degenerate pool.
+    ///
+    /// By default, the pool needs a positive normalized value.
+    fn is_tradeable(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> bool {
+        match (
+            self.normalized_value(swap_token_a_amount, swap_token_b_amount),
+            PreciseNumber::new(0),
+        ) {
+            (Some(value), Some(zero)) => value.greater_than(&zero),
+            _ => false,
+        }
+    }
+
+    /// The reserves the curve prices trades against, given the real token
+    /// reserves of the pool, including any virtual liquidity.
+    ///
+    /// Most curves price directly against the real reserves.
+    fn effective_reserves(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> (u128, u128) {
+        (swap_token_a_amount, swap_token_b_amount)
+    }
+
+    /// Calculates the spot price of token A in terms of token B, ie. how many
+    /// token B one token A is worth at the margin, given the reserves.
+    ///
+    /// The default implementation is the ratio of the reserves, which is the
+    /// marginal price of the constant product curve.
+    fn spot_price(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        PreciseNumber::new(swap_token_b_amount)?
+            .checked_div(&PreciseNumber::new(swap_token_a_amount)?)
+    }
+
+    /// Calculates the spot price as an integer scaled up by `10 ^ decimals`,
+    /// for display purposes. Any precision beyond `decimals` is truncated.
+    fn spot_price_decimal(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        decimals: u8,
+    ) -> Option<u128> {
+        let scale = PreciseNumber::new(10u128.checked_pow(decimals as u32)?)?;
+        self.spot_price(swap_token_a_amount, swap_token_b_amount)?
+            .checked_mul(&scale)?
+            .floor()?
+            .to_imprecise()
+    }
+
+    /// The destination amount paid out, without fees, for a single token of
+    /// source at the given reserves, ie. the spot price rounded in favor of
+    /// the pool. Returns `None` if a single token buys nothing, as for the
+    /// constant price curve selling token A for a `token_b_price` above one.
+    fn marginal_output(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        self.swap_without_fees(
+            1,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )
+        .map(|result| result.destination_amount_swapped)
+    }
+
+    /// Signed change to the source and destination reserves, in that order,
+    /// from swapping `source_amount` without fees, eg. to study the curve
+    /// independently of the pool's fees. The source reserve grows by the
+    /// amount actually swapped, which may be less than `source_amount`.
+    fn reserve_delta(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<(i128, i128)> {
+        let result = self.swap_without_fees(
+            source_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )?;
+        let source_delta = i128::try_from(result.source_amount_swapped).ok()?;
+        let destination_delta = i128::try_from(result.destination_amount_swapped)
+            .ok()?
+            .checked_neg()?;
+        Some((source_delta, destination_delta))
+    }
+
     /// Cauculates the total normalized value of the curve given the liquidity
     /// parameters.
     ///
@@ -182,18 +529,92 @@ pub trait CurveCalculator: Debug {
     ///
     /// This is useful for testing the curves, to make sure that value is not lost on any trade
     /// It can also be used to find out the relative value or pool tokens or liquidity tokens
+    ///
+    /// The default implementation values the reserves at the curve's spot
+    /// price, see `normalized_value_from_spot_price`. Curves with a closed
+    /// form invariant should override it.
     fn normalized_value(
         &self,
         swap_token_a_amount: u128,
         swap_token_b_amount: u128,
-    ) -> Option<PreciseNumber>;
+    ) -> Option<PreciseNumber> {
+        normalized_value_from_spot_price(
+            swap_token_a_amount,
+            swap_token_b_amount,
+            &self.spot_price(swap_token_a_amount, swap_token_b_amount)?,
+        )
+    }
 }
 
 #[cfg(test)]
 pub mod test {
     use super::*;
     use proptest::prelude::*;
-    use spl_math::uint::U256;
+
+    #[test]
+    fn dilution_bps() {
+        // doubling the supply halves the share of existing holders
+        assert_eq!(super::dilution_bps(1_000_000, 1_000_000), 5_000);
+        assert_eq!(super::dilution_bps(1_000_000, 100), 0);
+        assert_eq!(super::dilution_bps(1_000_000, 1_000), 9);
+        assert_eq!(super::dilution_bps(1_000_000, 0), 0);
+        assert_eq!(super::dilution_bps(0, 1_000), 10_000);
+        assert_eq!(super::dilution_bps(0, 0), 0);
+        assert_eq!(super::dilution_bps(u128::MAX, u128::MAX), 5_000);
+    }
+
+    #[test]
+    fn geometric_mean_supply() {
+        assert_eq!(super::geometric_mean_supply(4, 9), Some(6));
+        assert_eq!(super::geometric_mean_supply(1_000_000, 1), Some(1_000));
+        assert_eq!(super::geometric_mean_supply(2, 3), Some(2));
+        assert_eq!(super::geometric_mean_supply(0, 1_000), Some(0));
+
+        let max = u64::MAX as u128;
+        assert_eq!(super::geometric_mean_supply(max, max), Some(max));
+        let supply = super::geometric_mean_supply(max, max - 1_000_000).unwrap();
+        // within the 11 significant digits of the square root
+        let expected = max - 500_000;
+        assert!(supply.abs_diff(expected) <= expected / 100_000_000_000);
+
+        assert_eq!(super::geometric_mean_supply(u128::MAX, 2), None);
+    }
+
+    #[test]
+    fn precise_sqrt_perfect_squares() {
+        for root in [
+            0u128,
+            1,
+            2,
+            3,
+            10,
+            1_000_000,
+            u32::MAX as u128,
+            u64::MAX as u128,
+        ] {
+            let sqrt = precise_sqrt(root * root).unwrap();
+            assert_eq!(sqrt.to_imprecise(), Some(root));
+        }
+    }
+
+    #[test]
+    fn precise_sqrt_non_squares() {
+        // sqrt(2) = 1.41421356237309...
+        let expected = PreciseNumber::new(141_421_356_237)
+            .unwrap()
+            .checked_div(&PreciseNumber::new(100_000_000_000).unwrap())
+            .unwrap();
+        let sqrt = precise_sqrt(2).unwrap();
+        assert!(sqrt.almost_eq(&expected, U256::from(10u128)));
+
+        // rounds to the nearest whole number
+        assert_eq!(precise_sqrt(8).unwrap().to_imprecise(), Some(3));
+        assert_eq!(precise_sqrt(99).unwrap().to_imprecise(), Some(10));
+        assert_eq!(
+            precise_sqrt(u128::MAX).unwrap().to_imprecise(),
+            Some(u64::MAX as u128 + 1)
+        );
+    }
 
     /// The epsilon for most curves when performing the conversion test,
     /// comparing a one-sided deposit to a swap + deposit
@@ -277,11 +698,7 @@ pub mod test {
             1,
             pool_tokens_total_separate * epsilon_in_basis_points / 10_000,
         );
-        let difference = if pool_tokens_from_one_side >= pool_tokens_total_separate {
-            pool_tokens_from_one_side - pool_tokens_total_separate
-        } else {
-            pool_tokens_total_separate - pool_tokens_from_one_side
-        };
+        let difference = pool_tokens_from_one_side.abs_diff(pool_tokens_total_separate);
 
         assert!(
             difference <= epsilon,
@@ -362,11 +779,7 @@ pub mod test {
 
         // slippage due to rounding or truncation errors
         let epsilon = std::cmp::max(1, pool_token_amount * epsilon_in_basis_points / 10_000);
-        let difference = if pool_token_amount >= pool_token_amount_from_single_side_withdraw {
-            pool_token_amount - pool_token_amount_from_single_side_withdraw
-        } else {
-            pool_token_amount_from_single_side_withdraw - pool_token_amount
-        };
+        let difference = pool_token_amount.abs_diff(pool_token_amount_from_single_side_withdraw);
 
         assert!(
             difference <= epsilon,
@@ -437,6 +850,195 @@ pub mod test {
         assert!(difference <= epsilon);
     }
 
+    /// Test function checking both sides of a swap on a curve which is a
+    /// constant product over its effective reserves.
+    ///
+    /// The product of the effective reserves must not decrease, so the pool
+    /// never loses, and the trader must not receive more than the ideal swap
+    /// without rounding, `destination * source / (swap_source + source)`,
+    /// nor pay more than the given source amount.
+    pub fn check_swap_against_ideal(
+        curve: &dyn CurveCalculator,
+        source_token_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) {
+        let results = curve
+            .swap_without_fees(
+                source_token_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+            )
+            .unwrap();
+        assert!(results.source_amount_swapped <= source_token_amount);
+
+        let (swap_source_amount, swap_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => {
+                curve.effective_reserves(swap_source_amount, swap_destination_amount)
+            }
+            TradeDirection::BtoA => {
+                let (token_a, token_b) =
+                    curve.effective_reserves(swap_destination_amount, swap_source_amount);
+                (token_b, token_a)
+            }
+        };
+        let source_amount_swapped = U256::from(results.source_amount_swapped);
+        let destination_amount_swapped = U256::from(results.destination_amount_swapped);
+        let swap_source_amount = U256::from(swap_source_amount);
+        let swap_destination_amount = U256::from(swap_destination_amount);
+
+        let previous_invariant = swap_source_amount * swap_destination_amount;
+        let new_invariant = (swap_source_amount + source_amount_swapped)
+            * (swap_destination_amount - destination_amount_swapped);
+        assert!(
+            new_invariant >= previous_invariant,
+            "invariant decreased from {} to {}",
+            previous_invariant,
+            new_invariant
+        );
+
+        assert!(
+            destination_amount_swapped * (swap_source_amount + source_amount_swapped)
+                <= swap_destination_amount * source_amount_swapped,
+            "received {} for {}, more than the ideal swap",
+            destination_amount_swapped,
+            source_amount_swapped
+        );
+    }
+
+    /// Test function checking that swapping back and forth never profits the
+    /// trader.
+    ///
+    /// Swaps `source_token_amount` in the given direction, then swaps all of
+    /// the output back in the opposite direction against the updated
+    /// reserves. Every calculation truncates in favor of the pool, so the
+    /// epsilon is 0: the trader can get back at most exactly what they put in.
+    /// A trip that fails, ie. because the output was 0 or larger than the
+    /// pool's reserves, is also fine.
+    pub fn check_swap_roundtrip(
+        curve: &dyn CurveCalculator,
+        source_token_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) {
+        let Some(results) = curve.swap_without_fees(
+            source_token_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        ) else {
+            return;
+        };
+        // the pool can't pay out more than it holds
+        let Some(new_swap_destination_amount) =
+            swap_destination_amount.checked_sub(results.destination_amount_swapped)
+        else {
+            return;
+        };
+        let new_swap_source_amount = swap_source_amount
+            .checked_add(results.source_amount_swapped)
+            .unwrap();
+
+        let Some(return_results) = curve.swap_without_fees(
+            results.destination_amount_swapped,
+            new_swap_destination_amount,
+            new_swap_source_amount,
+            trade_direction.opposite(),
+        ) else {
+            return;
+        };
+
+        let epsilon = 0;
+        assert!(
+            return_results.destination_amount_swapped
+                <= results.source_amount_swapped + epsilon,
+            "round trip profit: put in {}, got back {}",
+            results.source_amount_swapped,
+            return_results.destination_amount_swapped
+        );
+    }
+
+    /// Test function checking that a swap never outputs more than the curve's
+    /// maximum output for the destination reserve
+    pub fn check_max_output(
+        curve: &dyn CurveCalculator,
+        source_token_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) {
+        if let Some(results) = curve.swap_without_fees(
+            source_token_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        ) {
+            assert!(
+                results.destination_amount_swapped <= curve.max_output(swap_destination_amount)
+            );
+        }
+    }
+
+    /// Test function to check that depositing a single token and then
+    /// withdrawing the same amount of it gives back about the same pool
+    /// tokens.
+    ///
+    /// The pool tokens burned to withdraw the deposited amount must stay within
+    /// the epsilon of the pool tokens minted by the deposit. Comparing in pool
+    /// tokens is equivalent to comparing the round-tripped source amount, since
+    /// both conversions are monotonic. The difference can go either way, since
+    /// square roots of `PreciseNumber` are approximations.
+    pub fn check_deposit_withdraw_roundtrip(
+        curve: &dyn CurveCalculator,
+        source_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        trade_direction: TradeDirection,
+        pool_supply: u128,
+        epsilon_in_basis_points: u128,
+    ) {
+        let deposit_pool_tokens = curve
+            .deposit_single_token_type(
+                source_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                trade_direction,
+            )
+            .unwrap();
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (
+                swap_token_a_amount + source_token_amount,
+                swap_token_b_amount,
+            ),
+            TradeDirection::BtoA => (
+                swap_token_a_amount,
+                swap_token_b_amount + source_token_amount,
+            ),
+        };
+        let withdraw_pool_tokens = curve
+            .withdraw_single_token_type_exact_out(
+                source_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply + deposit_pool_tokens,
+                trade_direction,
+            )
+            .unwrap();
+
+        let difference = withdraw_pool_tokens.abs_diff(deposit_pool_tokens);
+        let epsilon = std::cmp::max(1, deposit_pool_tokens * epsilon_in_basis_points / 10_000);
+        assert!(
+            difference <= epsilon,
+            "difference between round trip pool tokens {} and epsilon {}",
+            difference,
+            epsilon
+        );
+    }
+
     /// Test function checking that a deposit never reduces the value of pool
     /// tokens.
     ///
@@ -540,4 +1142,172 @@ pub mod test {
             (total, intermediate)
         }
     }
+
+    /// Generate the standard battery of curve tests for the curve built by
+    /// `$curve`: swap roundtrips in a `roundtrip` module, and the value of
+    /// pool tokens on deposit and withdrawal in a `pool_value` module.
+    ///
+    /// With `conversion_basis_points`, also compare single-sided deposits and
+    /// withdrawals against swapping and depositing or withdrawing both sides,
+    /// within the given epsilon, in a `conversion` module. Curves whose pool
+    /// tokens are not backed proportionally by the real reserves, like the
+    /// offset curve, can ask for the roundtrip checks alone with
+    /// `roundtrip_only`.
+    macro_rules! impl_curve_tests {
+        (@roundtrip $curve:expr) => {
+            mod roundtrip {
+                use super::*;
+                use $crate::curve::calculator::{test::check_swap_roundtrip, TradeDirection};
+
+                proptest::proptest! {
+                    #[test]
+                    fn swap_roundtrip_does_not_profit(
+                        source_token_amount in 1..u64::MAX,
+                        swap_source_amount in 1..u64::MAX,
+                        swap_destination_amount in 1..u64::MAX,
+                    ) {
+                        let curve = $curve;
+                        for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                            check_swap_roundtrip(
+                                &curve,
+                                source_token_amount as u128,
+                                swap_source_amount as u128,
+                                swap_destination_amount as u128,
+                                trade_direction,
+                            );
+                        }
+                    }
+                }
+            }
+        };
+        (@pool_value $curve:expr) => {
+            mod pool_value {
+                use super::*;
+                use $crate::curve::calculator::test::{
+                    check_pool_value_from_deposit, check_pool_value_from_withdraw,
+                    total_and_intermediate,
+                };
+
+                proptest::proptest! {
+                    #[test]
+                    fn curve_value_does_not_decrease_from_deposit(
+                        pool_token_amount in 1..u64::MAX,
+                        pool_token_supply in 1..u64::MAX,
+                        swap_token_a_amount in 1..u64::MAX,
+                        swap_token_b_amount in 1..u64::MAX,
+                    ) {
+                        let pool_token_amount = pool_token_amount as u128;
+                        let pool_token_supply = pool_token_supply as u128;
+                        let swap_token_a_amount = swap_token_a_amount as u128;
+                        let swap_token_b_amount = swap_token_b_amount as u128;
+
+                        // Make sure we will get at least one trading token out for each
+                        // side, otherwise the calculation fails
+                        proptest::prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+                        proptest::prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+                        check_pool_value_from_deposit(
+                            &$curve,
+                            pool_token_amount,
+                            pool_token_supply,
+                            swap_token_a_amount,
+                            swap_token_b_amount,
+                        );
+                    }
+                }
+
+                proptest::proptest! {
+                    #[test]
+                    fn curve_value_does_not_decrease_from_withdraw(
+                        (pool_token_supply, pool_token_amount) in total_and_intermediate(),
+                        swap_token_a_amount in 1..u64::MAX,
+                        swap_token_b_amount in 1..u64::MAX,
+                    ) {
+                        let pool_token_amount = pool_token_amount as u128;
+                        let pool_token_supply = pool_token_supply as u128;
+                        let swap_token_a_amount = swap_token_a_amount as u128;
+                        let swap_token_b_amount = swap_token_b_amount as u128;
+
+                        proptest::prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+                        proptest::prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+                        check_pool_value_from_withdraw(
+                            &$curve,
+                            pool_token_amount,
+                            pool_token_supply,
+                            swap_token_a_amount,
+                            swap_token_b_amount,
+                        );
+                    }
+                }
+            }
+        };
+        (@conversion $curve:expr, $epsilon:expr) => {
+            mod conversion {
+                use super::*;
+                use $crate::curve::calculator::{
+                    test::{
+                        check_deposit_token_conversion, check_withdraw_token_conversion,
+                        total_and_intermediate,
+                    },
+                    TradeDirection, INITIAL_SWAP_POOL_AMOUNT,
+                };
+
+                proptest::proptest! {
+                    #[test]
+                    fn deposit_token_conversion(
+                        // in the pool token conversion calcs, we simulate trading half of
+                        // source_token_amount, so this needs to be at least 2
+                        source_token_amount in 2..u64::MAX,
+                        swap_source_amount in 1..u64::MAX,
+                        swap_destination_amount in 1..u64::MAX,
+                        pool_supply in INITIAL_SWAP_POOL_AMOUNT..u64::MAX as u128,
+                    ) {
+                        for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                            check_deposit_token_conversion(
+                                &$curve,
+                                source_token_amount as u128,
+                                swap_source_amount as u128,
+                                swap_destination_amount as u128,
+                                trade_direction,
+                                pool_supply,
+                                $epsilon,
+                            );
+                        }
+                    }
+                }
+
+                proptest::proptest! {
+                    #[test]
+                    fn withdraw_token_conversion(
+                        (pool_token_supply, pool_token_amount) in total_and_intermediate(),
+                        swap_token_a_amount in 1..u64::MAX,
+                        swap_token_b_amount in 1..u64::MAX,
+                    ) {
+                        for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                            check_withdraw_token_conversion(
+                                &$curve,
+                                pool_token_amount as u128,
+                                pool_token_supply as u128,
+                                swap_token_a_amount as u128,
+                                swap_token_b_amount as u128,
+                                trade_direction,
+                                $epsilon,
+                            );
+                        }
+                    }
+                }
+            }
+        };
+        ($curve:expr, roundtrip_only) => {
+            $crate::curve::calculator::test::impl_curve_tests!(@roundtrip $curve);
+        };
+        ($curve:expr, conversion_basis_points = $epsilon:expr) => {
+            $crate::curve::calculator::test::impl_curve_tests!($curve);
+            $crate::curve::calculator::test::impl_curve_tests!(@conversion $curve, $epsilon);
+        };
+        ($curve:expr) => {
+            $crate::curve::calculator::test::impl_curve_tests!(@roundtrip $curve);
+            $crate::curve::calculator::test::impl_curve_tests!(@pool_value $curve);
+        };
+    }
+    pub(crate) use impl_curve_tests;
 }
@@ -0,0 +1,511 @@
+//! Concentrated liquidity curve, a simplified take on Curve v2
+use {
+    crate::{
+        curve::{
+            calculator::{
+                CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+                TradingTokenResult,
+            },
+            constant_price::{trading_tokens_to_pool_tokens, ConstantPriceCurve},
+            constant_product::{
+                deposit_single_token_type, normalized_value, pool_tokens_to_trading_tokens, swap,
+                withdraw_single_token_type_exact_out,
+            },
+        },
+        errors::SwapError,
+    },
+    anchor_lang::solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    spl_math::{precise_number::PreciseNumber, uint::U256},
+};
+
+/// Concentrated curve, trading at a constant price while the pool's spot price
+/// stays within `width` of `center_price`, and falling back to constant product
+/// outside of that range.
+///
+/// Prices are given as the price of token B in terms of token A, the same as
+/// `ConstantPriceCurve::token_b_price`. The regime is chosen from the reserves
+/// before the trade, so a single large trade is never split across regimes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConcentratedCurve {
+    /// Price of token B in token A around which liquidity is concentrated
+    pub center_price: u64,
+    /// Distance from the center price in which trades happen at a constant price
+    pub width: u64,
+}
+
+impl ConcentratedCurve {
+    /// Check if the spot price of the given reserves, `token_a / token_b`, lies
+    /// within `width` of `center_price`
+    pub fn is_concentrated(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> bool {
+        if swap_token_b_amount == 0 {
+            return false;
+        }
+        let swap_token_a_amount = U256::from(swap_token_a_amount);
+        let swap_token_b_amount = U256::from(swap_token_b_amount);
+        let lower_price = U256::from(self.center_price.saturating_sub(self.width));
+        let upper_price = U256::from(self.center_price).saturating_add(U256::from(self.width));
+        // both sides fit easily in U256, since the inputs are at most u128 * u64
+        swap_token_a_amount >= swap_token_b_amount * lower_price
+            && swap_token_a_amount <= swap_token_b_amount * upper_price
+    }
+
+    fn constant_price_curve(&self) -> ConstantPriceCurve {
+        ConstantPriceCurve {
+            token_b_price: self.center_price,
+        }
+    }
+}
+
+impl CurveCalculator for ConcentratedCurve {
+    /// Within the concentrated range, trade at `center_price` as long as the
+    /// destination side can cover it, otherwise trade on constant product
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_source_amount, swap_destination_amount),
+            TradeDirection::BtoA => (swap_destination_amount, swap_source_amount),
+        };
+        if self.is_concentrated(swap_token_a_amount, swap_token_b_amount) {
+            let result = self.constant_price_curve().swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+            )?;
+            if result.destination_amount_swapped >= swap_destination_amount {
+                return None;
+            }
+            Some(result)
+        } else {
+            swap(source_amount, swap_source_amount, swap_destination_amount)
+        }
+    }
+
+    /// Withdrawing and depositing both sides is always proportional to the
+    /// reserves, regardless of the regime
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            round_direction,
+        )
+    }
+
+    /// Get the amount of pool tokens for the deposited amount of token A or B,
+    /// valued with the formula of the current regime
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if self.is_concentrated(swap_token_a_amount, swap_token_b_amount) {
+            trading_tokens_to_pool_tokens(
+                self.center_price,
+                source_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                trade_direction,
+                RoundDirection::Floor,
+            )
+        } else {
+            deposit_single_token_type(
+                source_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                trade_direction,
+                RoundDirection::Floor,
+            )
+        }
+    }
+
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if self.is_concentrated(swap_token_a_amount, swap_token_b_amount) {
+            trading_tokens_to_pool_tokens(
+                self.center_price,
+                source_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                trade_direction,
+                RoundDirection::Ceiling,
+            )
+        } else {
+            withdraw_single_token_type_exact_out(
+                source_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                trade_direction,
+                RoundDirection::Ceiling,
+            )
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn CurveCalculator + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.center_price == 0 || self.width == 0 {
+            Err(SwapError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Within the concentrated range one token A is worth `1 / center_price`
+    /// token B, and outside of it the ratio of the reserves
+    fn spot_price(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        if self.is_concentrated(swap_token_a_amount, swap_token_b_amount) {
+            self.constant_price_curve()
+                .spot_price(swap_token_a_amount, swap_token_b_amount)
+        } else {
+            PreciseNumber::new(swap_token_b_amount)?
+                .checked_div(&PreciseNumber::new(swap_token_a_amount)?)
+        }
+    }
+
+    /// Within the concentrated range the value is the additive constant price
+    /// value, and outside of it the square root of the constant product
+    /// invariant
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        if self.is_concentrated(swap_token_a_amount, swap_token_b_amount) {
+            self.constant_price_curve()
+                .normalized_value(swap_token_a_amount, swap_token_b_amount)
+        } else {
+            normalized_value(swap_token_a_amount, swap_token_b_amount)
+        }
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for ConcentratedCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+
+impl Sealed for ConcentratedCurve {}
+
+impl Pack for ConcentratedCurve {
+    const LEN: usize = 16;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<ConcentratedCurve, ProgramError> {
+        let input = array_ref![input, 0, 16];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (center_price, width) = array_refs![input, 8, 8];
+        Ok(Self {
+            center_price: u64::from_le_bytes(*center_price),
+            width: u64::from_le_bytes(*width),
+        })
+    }
+}
+
+impl DynPack for ConcentratedCurve {
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, 16];
+        let (center_price, width) = mut_array_refs![dst, 8, 8];
+        *center_price = self.center_price.to_le_bytes();
+        *width = self.width.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::calculator::{
+        test::{check_curve_value_from_swap, check_max_output, check_swap_roundtrip},
+        INITIAL_SWAP_POOL_AMOUNT,
+    };
+    use proptest::prelude::*;
+
+    #[test]
+    fn requires_both_sides() {
+        let curve = ConcentratedCurve {
+            center_price: 2,
+            width: 1,
+        };
+        assert_eq!(curve.allows_empty_side(), None);
+        assert_eq!(curve.validate_supply(1_000, 0), Err(SwapError::EmptySupply));
+        assert_eq!(curve.validate_supply(0, 1_000), Err(SwapError::EmptySupply));
+        assert_eq!(curve.minimum_initial_reserves(), (1, 1));
+        assert_eq!(curve.validate_supply(1, 1), Ok(()));
+    }
+
+    #[test]
+    fn does_not_support_exact_out() {
+        let curve = ConcentratedCurve {
+            center_price: 2,
+            width: 1,
+        };
+        assert!(!curve.supports_exact_out());
+        assert!(curve
+            .swap_without_fees_exact_out(10, 1_000, 1_000, TradeDirection::AtoB)
+            .is_none());
+    }
+
+    #[test]
+    fn initial_pool_amount() {
+        let calculator = ConcentratedCurve {
+            center_price: 1,
+            width: 1,
+        };
+        assert_eq!(calculator.new_pool_supply(), INITIAL_SWAP_POOL_AMOUNT);
+    }
+
+    #[test]
+    fn pack_curve() {
+        let center_price = 1_251_258;
+        let width = 1_000;
+        let curve = ConcentratedCurve {
+            center_price,
+            width,
+        };
+
+        let mut packed = [0u8; ConcentratedCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = ConcentratedCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&center_price.to_le_bytes());
+        packed.extend_from_slice(&width.to_le_bytes());
+        let unpacked = ConcentratedCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    #[test]
+    fn validate_rejects_zero_parameters() {
+        let curve = ConcentratedCurve {
+            center_price: 10,
+            width: 0,
+        };
+        assert_eq!(curve.validate(), Err(SwapError::InvalidCurve));
+        let curve = ConcentratedCurve {
+            center_price: 0,
+            width: 10,
+        };
+        assert_eq!(curve.validate(), Err(SwapError::InvalidCurve));
+        let curve = ConcentratedCurve {
+            center_price: 10,
+            width: 1,
+        };
+        assert_eq!(curve.validate(), Ok(()));
+    }
+
+    #[test]
+    fn swap_regimes() {
+        let curve = ConcentratedCurve {
+            center_price: 2,
+            width: 1,
+        };
+
+        // spot price of 2, trades at the center price
+        let result = curve
+            .swap_without_fees(100, 2_000, 1_000, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 100);
+        assert_eq!(result.destination_amount_swapped, 50);
+        let result = curve
+            .swap_without_fees(100, 1_000, 2_000, TradeDirection::BtoA)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 100);
+        assert_eq!(result.destination_amount_swapped, 200);
+
+        // spot price of 10, trades on constant product
+        let result = curve
+            .swap_without_fees(100, 10_000, 1_000, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(result, swap(100, 10_000, 1_000).unwrap(),);
+
+        // the destination side can't be emptied at a constant price
+        assert!(curve
+            .swap_without_fees(2_000, 2_000, 1_000, TradeDirection::AtoB)
+            .is_none());
+    }
+
+    #[test]
+    fn spot_price() {
+        let curve = ConcentratedCurve {
+            center_price: 2,
+            width: 1,
+        };
+        // within the range, the center price applies
+        assert_eq!(curve.spot_price_decimal(2_500, 1_000, 6), Some(500_000));
+        // outside, the reserves ratio applies
+        assert_eq!(curve.spot_price_decimal(10_000, 1_000, 6), Some(100_000));
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap_a_to_b(
+            swap_token_b_amount in 1..u32::MAX,
+            center_price in 1..u32::MAX,
+            extra_width in 0..u32::MAX,
+            traded_basis_points in 1..1_000u128,
+        ) {
+            // a width of at least the center price keeps a trade of up to 10%
+            // of the pool within the concentrated range
+            let curve = ConcentratedCurve {
+                center_price: center_price as u64,
+                width: center_price as u64 + extra_width as u64,
+            };
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            let center_price = center_price as u128;
+            let swap_token_a_amount = swap_token_b_amount * center_price;
+            let source_token_amount = swap_token_a_amount * traded_basis_points / 10_000;
+
+            // make sure that the trade yields at least 1 token B
+            prop_assume!(source_token_amount / center_price >= 1);
+            // make sure there's enough tokens to get back on the other side
+            prop_assume!(source_token_amount / center_price < swap_token_b_amount);
+            // and that the pool stays in the concentrated range after the trade
+            let new_swap_token_a_amount = swap_token_a_amount + source_token_amount;
+            let new_swap_token_b_amount = swap_token_b_amount - source_token_amount / center_price;
+            prop_assume!(curve.is_concentrated(new_swap_token_a_amount, new_swap_token_b_amount));
+
+            check_curve_value_from_swap(
+                &curve,
+                source_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap_b_to_a(
+            source_token_amount in 1..u32::MAX,
+            swap_token_b_amount in 1..u32::MAX,
+            center_price in 1..u32::MAX,
+            width in 1..u32::MAX,
+        ) {
+            let curve = ConcentratedCurve {
+                center_price: center_price as u64,
+                width: width as u64,
+            };
+            let source_token_amount = source_token_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            let center_price = center_price as u128;
+            let swap_token_a_amount = swap_token_b_amount * center_price;
+
+            // The constant price regime needs to have enough destination amount
+            // on the other side to complete the swap
+            prop_assume!(center_price * source_token_amount < swap_token_a_amount);
+            let new_swap_token_a_amount = swap_token_a_amount - center_price * source_token_amount;
+            let new_swap_token_b_amount = swap_token_b_amount + source_token_amount;
+            prop_assume!(curve.is_concentrated(new_swap_token_a_amount, new_swap_token_b_amount));
+
+            check_curve_value_from_swap(
+                &curve,
+                source_token_amount,
+                swap_token_b_amount,
+                swap_token_a_amount,
+                TradeDirection::BtoA
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn swap_roundtrip_does_not_profit(
+            source_token_amount in 1..u32::MAX,
+            swap_token_a_amount in 1..u32::MAX,
+            swap_token_b_amount in 1..u32::MAX,
+            center_price in 1..u32::MAX,
+            width in 1..u32::MAX,
+        ) {
+            let curve = ConcentratedCurve {
+                center_price: center_price as u64,
+                width: width as u64,
+            };
+            check_swap_roundtrip(
+                &curve,
+                source_token_amount as u128,
+                swap_token_a_amount as u128,
+                swap_token_b_amount as u128,
+                TradeDirection::AtoB
+            );
+            check_swap_roundtrip(
+                &curve,
+                source_token_amount as u128,
+                swap_token_b_amount as u128,
+                swap_token_a_amount as u128,
+                TradeDirection::BtoA
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn swap_does_not_exceed_max_output(
+            source_token_amount in 1..u32::MAX,
+            swap_source_amount in 1..u32::MAX,
+            swap_destination_amount in 1..u32::MAX,
+            center_price in 1..u32::MAX,
+            width in 1..u32::MAX,
+        ) {
+            let curve = ConcentratedCurve {
+                center_price: center_price as u64,
+                width: width as u64,
+            };
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_max_output(
+                    &curve,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    trade_direction,
+                );
+            }
+        }
+    }
+}
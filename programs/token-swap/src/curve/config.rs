@@ -0,0 +1,191 @@
+//! Human-editable description of a swap curve, for off-chain tooling
+
+use {
+    crate::{
+        curve::{
+            base::{CurveType, SwapCurve},
+            concentrated::ConcentratedCurve,
+            constant_price::ConstantPriceCurve,
+            offset::Offset,
+            stable::StableCurve,
+        },
+        errors::SwapError,
+    },
+    anchor_lang::solana_program::{program_error::ProgramError, program_pack::Pack},
+};
+
+/// Type of a swap curve along with its parameters, named as in the curve
+/// calculators. Only the parameters used by the curve type need to be set.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CurveConfig {
+    /// Type of the swap curve
+    pub curve_type: CurveType,
+    /// Amount of token A required to get 1 token B, for the constant price
+    /// curve
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub token_b_price: Option<u64>,
+    /// Amplifier constant, for the stable curve
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub amp: Option<u64>,
+    /// Amount to offset the token B liquidity account, for the offset curve
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub token_b_offset: Option<u64>,
+    /// Price of token B in token A around which liquidity is concentrated,
+    /// for the concentrated curve
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub center_price: Option<u64>,
+    /// Distance from the center price in which trades happen at a constant
+    /// price, for the concentrated curve
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub width: Option<u64>,
+}
+
+/// Get a parameter required by the curve type
+fn required(parameter: Option<u64>) -> Result<u64, SwapError> {
+    parameter.ok_or(SwapError::InvalidCurveParameters)
+}
+
+impl CurveConfig {
+    /// Pack the parameters as expected by the initialize instruction,
+    /// failing with `SwapError::InvalidCurveParameters` if a parameter
+    /// required by the curve type is missing
+    pub fn curve_parameters(&self) -> Result<[u8; SwapCurve::PARAMETERS_LEN], SwapError> {
+        let mut parameters = [0u8; SwapCurve::PARAMETERS_LEN];
+        match self.curve_type {
+            CurveType::ConstantProduct => {}
+            CurveType::ConstantPrice => {
+                let curve = ConstantPriceCurve {
+                    token_b_price: required(self.token_b_price)?,
+                };
+                Pack::pack_into_slice(&curve, &mut parameters);
+            }
+            CurveType::Stable => {
+                let curve = StableCurve {
+                    amp: required(self.amp)?,
+                };
+                Pack::pack_into_slice(&curve, &mut parameters);
+            }
+            CurveType::Offset => {
+                let curve = Offset {
+                    token_b_offset: required(self.token_b_offset)?,
+                };
+                Pack::pack_into_slice(&curve, &mut parameters);
+            }
+            CurveType::Concentrated => {
+                let curve = ConcentratedCurve {
+                    center_price: required(self.center_price)?,
+                    width: required(self.width)?,
+                };
+                Pack::pack_into_slice(&curve, &mut parameters);
+            }
+        }
+        Ok(parameters)
+    }
+
+    /// Build the swap curve described by the configuration, validating its
+    /// parameters
+    pub fn to_swap_curve(&self) -> Result<SwapCurve, ProgramError> {
+        SwapCurve::from_parameters(self.curve_type, &self.curve_parameters()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configs() -> Vec<CurveConfig> {
+        vec![
+            CurveConfig {
+                curve_type: CurveType::ConstantProduct,
+                ..CurveConfig::default()
+            },
+            CurveConfig {
+                curve_type: CurveType::ConstantPrice,
+                token_b_price: Some(1_000),
+                ..CurveConfig::default()
+            },
+            CurveConfig {
+                curve_type: CurveType::Stable,
+                amp: Some(100),
+                ..CurveConfig::default()
+            },
+            CurveConfig {
+                curve_type: CurveType::Offset,
+                token_b_offset: Some(1_000_000),
+                ..CurveConfig::default()
+            },
+            CurveConfig {
+                curve_type: CurveType::Concentrated,
+                center_price: Some(2),
+                width: Some(1),
+                ..CurveConfig::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn to_swap_curve() {
+        for config in configs() {
+            let swap_curve = config.to_swap_curve().unwrap();
+            assert_eq!(swap_curve.curve_type, config.curve_type);
+            let mut packed = [0u8; SwapCurve::PARAMETERS_LEN];
+            swap_curve.calculator.pack_into_slice(&mut packed);
+            assert_eq!(packed, config.curve_parameters().unwrap());
+        }
+    }
+
+    #[test]
+    fn to_swap_curve_invalid() {
+        let config = CurveConfig {
+            curve_type: CurveType::Stable,
+            ..CurveConfig::default()
+        };
+        assert_eq!(
+            config.to_swap_curve().unwrap_err(),
+            SwapError::InvalidCurveParameters.into()
+        );
+        let config = CurveConfig {
+            curve_type: CurveType::Stable,
+            amp: Some(0),
+            ..CurveConfig::default()
+        };
+        assert_eq!(
+            config.to_swap_curve().unwrap_err(),
+            SwapError::InvalidCurveParameters.into()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        for config in configs() {
+            let json = serde_json::to_string(&config).unwrap();
+            let deserialized: CurveConfig = serde_json::from_str(&json).unwrap();
+            assert_eq!(config, deserialized);
+        }
+
+        let config: CurveConfig =
+            serde_json::from_str(r#"{ "curve_type": "Stable", "amp": 100 }"#).unwrap();
+        assert_eq!(config, configs()[2]);
+        assert_eq!(
+            serde_json::to_string(&config).unwrap(),
+            r#"{"curve_type":"Stable","amp":100}"#
+        );
+    }
+}
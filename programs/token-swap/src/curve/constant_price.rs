@@ -4,6 +4,7 @@ use {
             map_zero_to_none, CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult,
             TradeDirection, TradingTokenResult,
         },
+        curve::fees::Fees,
         errors::SwapError,
     },
     anchor_lang::solana_program::{
@@ -14,7 +15,13 @@ use {
     spl_math::{checked_ceil_div::CheckedCeilDiv, precise_number::PreciseNumber, uint::U256},
 };
 
-// Single asset deposit
+/// Single asset deposit.
+///
+/// The pool token amount is computed as `pool_supply * given_value /
+/// total_value` entirely in `U256`, so the only truncation is the final
+/// floor or ceiling. Taking the ratio as a `PreciseNumber` first would add
+/// rounding at its 12 decimals, which is amplified by the pool supply and can
+/// land past the exact floor or ceiling, so it does not reduce the drift.
 pub fn trading_tokens_to_pool_tokens(
     token_b_price: u64,
     source_amount: u128,
@@ -34,61 +41,143 @@ pub fn trading_tokens_to_pool_tokens(
         .checked_add(U256::from(swap_token_a_amount))?;
     let pool_supply = U256::from(pool_supply);
 
-    match round_direction {
-        RoundDirection::Floor => Some(
-            pool_supply
-                .checked_mul(given_value)?
-                .checked_div(tatal_value)?
-                .as_u128(),
-        ),
-        RoundDirection::Ceiling => Some(
+    let pool_tokens = match round_direction {
+        RoundDirection::Floor => pool_supply
+            .checked_mul(given_value)?
+            .checked_div(tatal_value)?,
+        RoundDirection::Ceiling => {
             pool_supply
                 .checked_mul(given_value)?
                 .checked_ceil_div(tatal_value)?
                 .0
-                .as_u128(),
-        ),
+        }
+    };
+    if pool_tokens > U256::from(u128::MAX) {
+        None
+    } else {
+        Some(pool_tokens.as_u128())
     }
 }
 
+/// ConstantPriceCurve struct implementing CurveCalculator
+///
+/// Prefer `ConstantPriceCurve::new`, which validates the price, over building
+/// the struct directly.
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct ConstantPriceCurve {
+    /// Amount of token A required to get 1 token B
     pub token_b_price: u64,
 }
 
-impl CurveCalculator for ConstantPriceCurve {
-    /// Constant price curve always returns 1:1
-    fn swap_without_fees(
+impl ConstantPriceCurve {
+    /// Create a constant price curve, rejecting a zero price
+    pub fn new(token_b_price: u64) -> Result<Self, SwapError> {
+        let curve = Self { token_b_price };
+        curve.validate()?;
+        Ok(curve)
+    }
+
+    /// Swap `source_amount` at the constant price, also returning the part of
+    /// the source amount left unswapped.
+    ///
+    /// Buying token B only takes whole multiples of `token_b_price`, so the
+    /// truncated remainder can be refunded instead of given to the pool.
+    /// Buying token A never leaves a remainder.
+    pub fn swap_with_remainder(
         &self,
-        source_amount: u128,            // 100
-        _swap_source_amount: u128,      // 0
-        _swap_destination_amount: u128, // 0
+        source_amount: u128,
         trade_direction: TradeDirection,
-    ) -> Option<SwapWithoutFeesResult> {
-        let token_b_price = self.token_b_price as u128; // 1
+    ) -> Option<(SwapWithoutFeesResult, u128)> {
+        let token_b_price = self.token_b_price as u128;
 
         let (source_amount_swapped, destination_amount_swapped) = match trade_direction {
             TradeDirection::BtoA => (source_amount, source_amount.checked_mul(token_b_price)?),
             TradeDirection::AtoB => {
                 let destination_amount_swapped = source_amount.checked_div(token_b_price)?;
-                let mut source_amount_swapped = source_amount;
-
-                let remainder = source_amount_swapped.checked_rem(token_b_price)?;
-                println!("remainder {:?}", remainder);
-                if remainder > 0 {
-                    source_amount_swapped = source_amount.checked_sub(remainder)?;
-                    println!("source_amount_swapped {:?}", source_amount_swapped);
-                }
-
-                (source_amount_swapped, destination_amount_swapped)
+                let remainder = source_amount.checked_rem(token_b_price)?;
+                (
+                    source_amount.checked_sub(remainder)?,
+                    destination_amount_swapped,
+                )
             }
         };
+        let remainder = source_amount.checked_sub(source_amount_swapped)?;
+        #[cfg(feature = "debug-logs")]
+        anchor_lang::prelude::msg!("constant price swap remainder {}", remainder);
 
         let source_amount_swapped = map_zero_to_none(source_amount_swapped)?;
         let destination_amount_swapped = map_zero_to_none(destination_amount_swapped)?;
+        Some((
+            SwapWithoutFeesResult {
+                source_amount_swapped,
+                destination_amount_swapped,
+            },
+            remainder,
+        ))
+    }
+
+    /// Surplus of token A in the pool compared to the equilibrium
+    /// `a = b * token_b_price`, negative for a deficit.
+    ///
+    /// Any deviation from the equilibrium is an arbitrage opportunity against
+    /// the external price of the pair. Returns `None` on overflow.
+    pub fn equilibrium_imbalance(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<i128> {
+        let equilibrium_token_a_amount =
+            swap_token_b_amount.checked_mul(u128::from(self.token_b_price))?;
+        i128::try_from(swap_token_a_amount)
+            .ok()?
+            .checked_sub(i128::try_from(equilibrium_token_a_amount).ok()?)
+    }
+}
+
+impl CurveCalculator for ConstantPriceCurve {
+    /// Constant price curve always returns 1:1
+    ///
+    /// The remainder not swapped when buying token B is dropped from
+    /// `source_amount_swapped`, see `swap_with_remainder`
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        _swap_source_amount: u128,
+        _swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        self.swap_with_remainder(source_amount, trade_direction)
+            .map(|(result, _remainder)| result)
+    }
+
+    fn supports_exact_out(&self) -> bool {
+        true
+    }
+
+    /// The source amount is the destination amount at the constant price,
+    /// rounded up when buying token A
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        _swap_source_amount: u128,
+        _swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let token_b_price = self.token_b_price as u128;
+        let source_amount_swapped = match trade_direction {
+            TradeDirection::AtoB => destination_amount.checked_mul(token_b_price)?,
+            TradeDirection::BtoA => {
+                let source_amount = destination_amount.checked_div(token_b_price)?;
+                if destination_amount.checked_rem(token_b_price)? > 0 {
+                    source_amount.checked_add(1)?
+                } else {
+                    source_amount
+                }
+            }
+        };
         Some(SwapWithoutFeesResult {
-            source_amount_swapped,
-            destination_amount_swapped,
+            source_amount_swapped: map_zero_to_none(source_amount_swapped)?,
+            destination_amount_swapped: map_zero_to_none(destination_amount)?,
         })
     }
 
@@ -96,6 +185,8 @@ impl CurveCalculator for ConstantPriceCurve {
     /// provided the total trading tokens and supply of pool tokens.
     /// For the constant price curve, the total value of the pool is weighted
     /// by the price of token B
+    ///
+    /// Returns `None` if the pool token supply is zero.
     fn pool_tokens_to_trading_tokens(
         &self,
         pool_tokens: u128,
@@ -104,6 +195,9 @@ impl CurveCalculator for ConstantPriceCurve {
         swap_token_b_amount: u128,
         round_direction: RoundDirection,
     ) -> Option<TradingTokenResult> {
+        if pool_token_supply == 0 {
+            return None;
+        }
         let token_b_price = self.token_b_price as u128;
         let total_value = self
             .normalized_value(swap_token_a_amount, swap_token_b_amount)?
@@ -180,19 +274,55 @@ impl CurveCalculator for ConstantPriceCurve {
         )
     }
 
+    fn clone_box(&self) -> Box<dyn CurveCalculator + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
     fn validate(&self) -> Result<(), SwapError> {
         if self.token_b_price == 0 {
-            Err(SwapError::InvalidCurve.into())
+            Err(SwapError::InvalidCurve)
         } else {
             Ok(())
         }
     }
 
-    fn validate_supply(&self, token_a_amount: u64, token_b_amount: u64) -> Result<(), SwapError> {
-        if token_a_amount == 0 {
-            return Err(SwapError::EmptySupply.into());
-        }
-        Ok(())
+    /// Token B is only bought at the constant price, so the pool may start
+    /// without any
+    fn allows_empty_side(&self) -> Option<TradeDirection> {
+        Some(TradeDirection::AtoB)
+    }
+
+    /// The smallest swap from token B buys `token_b_price` token A
+    fn minimum_initial_reserves(&self) -> (u64, u64) {
+        (self.token_b_price, 0)
+    }
+
+    /// One token A is always worth `1 / token_b_price` token B
+    fn spot_price(
+        &self,
+        _swap_token_a_amount: u128,
+        _swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        PreciseNumber::new(1)?.checked_div(&PreciseNumber::new(self.token_b_price as u128)?)
+    }
+
+    /// Swapping A to B needs at least `token_b_price` token A after fees to
+    /// get a single token B, which also covers swapping B to A
+    fn minimum_trade_amount(&self, fees: &Fees) -> u128 {
+        fees.pre_trading_fee_amount(self.token_b_price as u128)
+            .unwrap_or(u128::MAX)
+    }
+
+    /// The constant price calculation ignores the reserves, so a large enough
+    /// trade in either direction, including B to A, computes an output above
+    /// the destination reserve. Such trades are rejected by `SwapCurve::swap`,
+    /// so the most a trade can extract is still the whole reserve.
+    fn max_output(&self, swap_destination_amount: u128) -> u128 {
+        swap_destination_amount
     }
 
     /// The total normalized value of the constant price curve adds the total
@@ -211,8 +341,8 @@ impl CurveCalculator for ConstantPriceCurve {
     ) -> Option<PreciseNumber> {
         let swap_token_b_value = swap_token_b_amount.checked_mul(self.token_b_price as u128)?;
         // special logic in case we're close to the limits, avoid overflow u128
-        let value = if swap_token_b_value.saturating_sub(std::u64::MAX.into())
-            > (std::u128::MAX.saturating_sub(std::u64::MAX.into()))
+        let value = if swap_token_b_value.saturating_sub(u64::MAX.into())
+            > (u128::MAX.saturating_sub(u64::MAX.into()))
         {
             swap_token_b_value
                 .checked_div(2)?
@@ -263,6 +393,7 @@ mod tests {
     use crate::curve::calculator::{
         test::{
             check_curve_value_from_swap, check_deposit_token_conversion,
+            check_deposit_withdraw_roundtrip, check_swap_roundtrip,
             check_withdraw_token_conversion, total_and_intermediate,
             CONVERSION_BASIS_POINTS_GURANTEE,
         },
@@ -270,6 +401,10 @@ mod tests {
     };
     use proptest::prelude::*;
 
+    /// Tighter than `CONVERSION_BASIS_POINTS_GURANTEE`, since the deposit
+    /// conversion on this curve only truncates once
+    const DEPOSIT_CONVERSION_BASIS_POINTS: u128 = 30;
+
     #[test]
     fn swap_calculation_on_price() {
         let swap_source_amount: u128 = 0;
@@ -292,8 +427,6 @@ mod tests {
             )
             .unwrap();
 
-        // println!("BtoA {:?}", result);
-
         assert_eq!(result, expected_result);
 
         let result = curve
@@ -305,11 +438,237 @@ mod tests {
             )
             .unwrap();
 
-        println!("AtoB {:?}", result);
-
         assert_eq!(result, expected_result);
     }
 
+    #[test]
+    fn swap_with_remainder() {
+        let curve = ConstantPriceCurve { token_b_price: 10 };
+
+        // 105 A only buys 10 B, leaving 5 A to refund
+        let (result, remainder) = curve
+            .swap_with_remainder(105, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 100);
+        assert_eq!(result.destination_amount_swapped, 10);
+        assert_eq!(remainder, 5);
+        assert_eq!(
+            curve.swap_without_fees(105, 0, 0, TradeDirection::AtoB),
+            Some(result)
+        );
+
+        let (_, remainder) = curve
+            .swap_with_remainder(100, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(remainder, 0);
+        let (_, remainder) = curve
+            .swap_with_remainder(105, TradeDirection::BtoA)
+            .unwrap();
+        assert_eq!(remainder, 0);
+
+        // too little to buy a single token B
+        assert_eq!(curve.swap_with_remainder(9, TradeDirection::AtoB), None);
+    }
+
+    #[test]
+    #[cfg(not(feature = "debug-logs"))]
+    fn swap_does_not_log() {
+        use {
+            crate::instructions::test::install_syscall_stubs,
+            anchor_lang::solana_program::program_stubs::SyscallStubs,
+            std::sync::{Arc, Mutex},
+        };
+
+        struct RecordLogs(Arc<Mutex<Vec<String>>>);
+        impl SyscallStubs for RecordLogs {
+            fn sol_log(&self, message: &str) {
+                self.0.lock().unwrap().push(message.to_string());
+            }
+        }
+
+        let logs = Arc::new(Mutex::new(vec![]));
+        let stubs = install_syscall_stubs(Box::new(RecordLogs(logs.clone())));
+        let curve = ConstantPriceCurve { token_b_price: 10 };
+        for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+            curve.swap_without_fees(105, 1_000, 1_000, trade_direction);
+        }
+        drop(stubs);
+        assert!(logs.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn validate_with_reserves() {
+        let curve = ConstantPriceCurve {
+            token_b_price: u64::MAX,
+        };
+        let reserve = u64::MAX as u128;
+        assert_eq!(curve.validate_with_reserves(reserve, reserve), Ok(()));
+
+        // the value of token B no longer fits in a u128
+        let swap_token_b_amount = 1u128 << 65;
+        assert_eq!(curve.normalized_value(reserve, swap_token_b_amount), None);
+        assert_eq!(
+            curve.validate_with_reserves(reserve, swap_token_b_amount),
+            Err(SwapError::InvalidCurve)
+        );
+
+        let curve = ConstantPriceCurve { token_b_price: 0 };
+        assert_eq!(
+            curve.validate_with_reserves(1_000, 1_000),
+            Err(SwapError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn minimum_trade_amount() {
+        let token_b_price = 1_000;
+        let curve = ConstantPriceCurve { token_b_price };
+        let fees = Fees::default();
+        assert_eq!(curve.minimum_trade_amount(&fees), token_b_price as u128);
+
+        let minimum = curve.minimum_trade_amount(&fees);
+        let result = curve
+            .swap_without_fees(minimum, 0, 0, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, 1);
+        assert!(curve
+            .swap_without_fees(minimum - 1, 0, 0, TradeDirection::AtoB)
+            .is_none());
+
+        // fees push the minimum above the price
+        let fees = Fees {
+            trade_fee_numerator: 25,
+            trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        let minimum = curve.minimum_trade_amount(&fees);
+        assert!(minimum > token_b_price as u128);
+        let trade_fee = fees.trading_fee(minimum).unwrap();
+        assert!(minimum - trade_fee >= token_b_price as u128);
+    }
+
+    #[test]
+    fn new_validates_price() {
+        assert_eq!(ConstantPriceCurve::new(0), Err(SwapError::InvalidCurve));
+        assert_eq!(
+            ConstantPriceCurve::new(5),
+            Ok(ConstantPriceCurve { token_b_price: 5 })
+        );
+    }
+
+    #[test]
+    fn equilibrium_imbalance() {
+        let curve = ConstantPriceCurve { token_b_price: 4 };
+        assert_eq!(curve.equilibrium_imbalance(4_000, 1_000), Some(0));
+        assert_eq!(curve.equilibrium_imbalance(0, 0), Some(0));
+
+        // too much token A
+        assert_eq!(curve.equilibrium_imbalance(5_000, 1_000), Some(1_000));
+        // too little token A
+        assert_eq!(curve.equilibrium_imbalance(1_000, 1_000), Some(-3_000));
+        assert_eq!(curve.equilibrium_imbalance(0, 1_000), Some(-4_000));
+
+        assert_eq!(curve.equilibrium_imbalance(u128::MAX, 0), None);
+        assert_eq!(curve.equilibrium_imbalance(0, u128::MAX), None);
+    }
+
+    #[test]
+    fn clone_box() {
+        let curve: Box<dyn CurveCalculator + Sync + Send> =
+            Box::new(ConstantPriceCurve { token_b_price: 3 });
+        let cloned = curve.clone();
+        // a separate allocation holding the same curve
+        assert!(!std::ptr::addr_eq(curve.as_ref(), cloned.as_ref()));
+        assert_eq!(format!("{:?}", cloned), format!("{:?}", curve));
+        let mut packed = [0u8; ConstantPriceCurve::LEN];
+        let mut packed_clone = [0u8; ConstantPriceCurve::LEN];
+        curve.pack_into_slice(&mut packed);
+        cloned.pack_into_slice(&mut packed_clone);
+        assert_eq!(packed, packed_clone);
+    }
+
+    #[test]
+    fn is_tradeable() {
+        let curve = ConstantPriceCurve { token_b_price: 3 };
+        assert!(curve.is_tradeable(1_000, 1_000));
+        // a single side is enough to buy from at the constant price
+        assert!(curve.is_tradeable(1_000, 0));
+        assert!(curve.is_tradeable(0, 1_000));
+        assert!(!curve.is_tradeable(0, 0));
+    }
+
+    #[test]
+    fn allows_empty_token_b() {
+        let curve = ConstantPriceCurve { token_b_price: 3 };
+        assert_eq!(curve.allows_empty_side(), Some(TradeDirection::AtoB));
+        assert_eq!(curve.validate_supply(1_000, 0), Ok(()));
+        assert_eq!(curve.validate_supply(0, 1_000), Err(SwapError::EmptySupply));
+    }
+
+    #[test]
+    fn minimum_initial_reserves() {
+        let curve = ConstantPriceCurve { token_b_price: 3 };
+        assert_eq!(curve.minimum_initial_reserves(), (3, 0));
+        assert_eq!(curve.validate_supply(2, 0), Err(SwapError::EmptySupply));
+        assert_eq!(curve.validate_supply(3, 0), Ok(()));
+
+        // a single token B buys the whole minimum reserve
+        let result = curve
+            .swap_without_fees(1, 0, 3, TradeDirection::BtoA)
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, 3);
+    }
+
+    #[test]
+    fn swap_exact_out() {
+        let curve = ConstantPriceCurve { token_b_price: 3 };
+        assert!(curve.supports_exact_out());
+
+        // buying 10 B costs 30 A
+        let result = curve
+            .swap_without_fees_exact_out(10, 0, 0, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 30);
+
+        // buying 10 A costs 4 B, which gives 12 A
+        let result = curve
+            .swap_without_fees_exact_out(10, 0, 0, TradeDirection::BtoA)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 4);
+        let result = curve
+            .swap_without_fees(4, 0, 0, TradeDirection::BtoA)
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, 12);
+
+        assert!(curve
+            .swap_without_fees_exact_out(0, 0, 0, TradeDirection::AtoB)
+            .is_none());
+    }
+
+    #[test]
+    fn spot_price() {
+        let curve = ConstantPriceCurve { token_b_price: 4 };
+        // the reserves don't matter
+        assert_eq!(curve.spot_price_decimal(80, 40, 6), Some(250_000));
+        assert_eq!(curve.spot_price_decimal(1, 1_000_000, 6), Some(250_000));
+        let curve = ConstantPriceCurve { token_b_price: 1 };
+        assert_eq!(curve.spot_price(80, 40), PreciseNumber::new(1));
+    }
+
+    #[test]
+    fn marginal_output() {
+        let curve = ConstantPriceCurve { token_b_price: 4 };
+        // a single A buys a quarter of a B, which rounds to nothing
+        assert_eq!(
+            curve.marginal_output(1_000, 1_000, TradeDirection::AtoB),
+            None
+        );
+        assert_eq!(
+            curve.marginal_output(1_000, 1_000, TradeDirection::BtoA),
+            Some(4)
+        );
+    }
+
     #[test]
     fn pack_flat_curve() {
          let token_b_price = 1_251_258;
@@ -389,6 +748,74 @@ mod tests {
         assert_eq!(result.destination_amount_swapped, 1u128);
     }
 
+    proptest! {
+        #[test]
+        fn trading_tokens_to_pool_tokens_exact(
+            source_amount in 1..u64::MAX,
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u64::MAX,
+            pool_supply in INITIAL_SWAP_POOL_AMOUNT..u64::MAX as u128,
+            token_b_price in 1..u64::MAX,
+        ) {
+            let total_value = U256::from(swap_token_b_amount) * U256::from(token_b_price)
+                + U256::from(swap_token_a_amount);
+            let deposited = U256::from(pool_supply) * U256::from(source_amount);
+            let floor = trading_tokens_to_pool_tokens(
+                token_b_price,
+                source_amount as u128,
+                swap_token_a_amount as u128,
+                swap_token_b_amount as u128,
+                pool_supply,
+                TradeDirection::AtoB,
+                RoundDirection::Floor,
+            )
+            .unwrap();
+            // the ceiling division needs at least one whole pool token
+            prop_assume!(floor > 0);
+            let ceiling = trading_tokens_to_pool_tokens(
+                token_b_price,
+                source_amount as u128,
+                swap_token_a_amount as u128,
+                swap_token_b_amount as u128,
+                pool_supply,
+                TradeDirection::AtoB,
+                RoundDirection::Ceiling,
+            )
+            .unwrap();
+            // the results bracket the exact value within a single token
+            prop_assert!(U256::from(floor) * total_value <= deposited);
+            prop_assert!(U256::from(floor + 1) * total_value > deposited);
+            prop_assert!(U256::from(ceiling) * total_value >= deposited);
+            prop_assert!(ceiling - floor <= 1);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn deposit_withdraw_roundtrip(
+            source_token_amount in 1..u32::MAX,
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u32::MAX,
+            pool_supply in INITIAL_SWAP_POOL_AMOUNT..u64::MAX as u128,
+            token_b_price in 1..u32::MAX,
+        ) {
+            let curve = ConstantPriceCurve {
+                token_b_price: token_b_price as u64,
+            };
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_deposit_withdraw_roundtrip(
+                    &curve,
+                    source_token_amount as u128,
+                    swap_token_a_amount as u128,
+                    swap_token_b_amount as u128,
+                    trade_direction,
+                    pool_supply,
+                    CONVERSION_BASIS_POINTS_GURANTEE,
+                );
+            }
+        }
+    }
+
     proptest! {
         #[test]
         fn deposit_token_conversion_a_to_b(
@@ -417,7 +844,7 @@ mod tests {
                 swap_destination_amount as u128,
                 TradeDirection::AtoB,
                 pool_supply,
-                CONVERSION_BASIS_POINTS_GURANTEE
+                DEPOSIT_CONVERSION_BASIS_POINTS
             );
         }
     }
@@ -452,7 +879,7 @@ mod tests {
                 swap_destination_amount,
                 TradeDirection::BtoA,
                 pool_supply,
-                CONVERSION_BASIS_POINTS_GURANTEE
+                DEPOSIT_CONVERSION_BASIS_POINTS
             );
         }
     }
@@ -575,7 +1002,6 @@ mod tests {
         ) {
             let curve = ConstantPriceCurve {token_b_price: token_b_price as u64};
             let pool_token_amount = pool_token_amount as u128;
-            let pool_token_supply = pool_token_supply as u128;
             let swap_token_a_amount = swap_token_a_amount as u128;
             let swap_token_b_amount = swap_token_b_amount as u128;
             let token_b_price = token_b_price as u128;
@@ -670,4 +1096,30 @@ mod tests {
             );
         }
     }
+
+    proptest! {
+        #[test]
+        fn swap_roundtrip_does_not_profit(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            token_b_price in 1..u64::MAX,
+        ) {
+            let curve = ConstantPriceCurve { token_b_price };
+            check_swap_roundtrip(
+                &curve,
+                source_token_amount as u128,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                TradeDirection::AtoB
+            );
+            check_swap_roundtrip(
+                &curve,
+                source_token_amount as u128,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                TradeDirection::BtoA
+            );
+        }
+    }
 }
@@ -1,13 +1,17 @@
 use {
     crate::{
         curve::calculator::{
-            map_zero_to_none, CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult,
-            TradeDirection, TradingTokenResult,
+            map_zero_to_none, precise_sqrt, precise_to_u128, CurveCalculator, DynPack,
+            RoundDirection, SwapWithoutFeesResult, TradeDirection, TradingTokenResult,
+            TradingTokenResultWithRounding,
         },
         errors::SwapError,
     },
-    anchor_lang::solana_program::program_error::ProgramError,
-    spl_math::{checked_ceil_div::CheckedCeilDiv, precise_number::PreciseNumber},
+    anchor_lang::solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::{checked_ceil_div::CheckedCeilDiv, precise_number::PreciseNumber, uint::U256},
 };
 
 /// ConstantProductCurve struct implementing CurveCalculator
@@ -24,6 +28,14 @@ pub fn swap(
     swap_source_amount: u128,
     swap_destination_amount: u128,
 ) -> Option<SwapWithoutFeesResult> {
+    #[cfg(feature = "arithmetic-audit")]
+    debug_assert!(
+        swap_source_amount
+            .checked_mul(swap_destination_amount)
+            .and(swap_source_amount.checked_add(source_amount))
+            .is_some(),
+        "arithmetic audit: constant product swap overflows u128"
+    );
     let invariant = swap_source_amount.checked_mul(swap_destination_amount)?;
 
     let new_swap_source_amount = swap_source_amount.checked_add(source_amount)?;
@@ -40,11 +52,45 @@ pub fn swap(
     })
 }
 
+/// The constant product swap calculation, with the invariant computed in U256.
+///
+/// This gives the same results as `swap`, but also works for pools where
+/// `swap_source_amount * swap_destination_amount` exceeds u128::MAX.
+pub fn swap_u256(
+    source_amount: u128,
+    swap_source_amount: u128,
+    swap_destination_amount: u128,
+) -> Option<SwapWithoutFeesResult> {
+    let invariant =
+        U256::from(swap_source_amount).checked_mul(U256::from(swap_destination_amount))?;
+
+    let new_swap_source_amount =
+        U256::from(swap_source_amount).checked_add(U256::from(source_amount))?;
+    let (new_swap_destination_amount, new_swap_source_amount) =
+        invariant.checked_ceil_div(new_swap_source_amount)?;
+
+    let source_amount_swapped =
+        u128::try_from(new_swap_source_amount.checked_sub(U256::from(swap_source_amount))?).ok()?;
+    let destination_amount_swapped = u128::try_from(
+        U256::from(swap_destination_amount).checked_sub(new_swap_destination_amount)?,
+    )
+    .ok()?;
+    let destination_amount_swapped = map_zero_to_none(destination_amount_swapped)?;
+
+    Some(SwapWithoutFeesResult {
+        source_amount_swapped,
+        destination_amount_swapped,
+    })
+}
+
 /// Get the amount of trading tokens for the given amount of pool tokens,
 /// provided the total trading tokens and supply of pool tokens.
 ///
 /// The constant product implementation is a simple ratio calculation for how many
 /// trading tokens correspond to a certain number of pool tokens
+///
+/// Returns `None` if the pool token supply is zero, ie. the pool has been
+/// completely drained, since there is no ratio to apply.
 pub fn pool_tokens_to_trading_tokens(
     pool_tokens: u128,
     pool_token_supply: u128,
@@ -52,39 +98,53 @@ pub fn pool_tokens_to_trading_tokens(
     swap_token_b_amount: u128,
     round_direction: RoundDirection,
 ) -> Option<TradingTokenResult> {
-    let mut token_a_amount = pool_tokens
-        .checked_mul(swap_token_a_amount)?
-        .checked_div(pool_token_supply)?;
-    let mut token_b_amount = pool_tokens
-        .checked_mul(swap_token_b_amount)?
-        .checked_div(pool_token_supply)?;
-    let (token_a_amount, token_b_amount) = match round_direction {
-        RoundDirection::Floor => (token_a_amount, token_b_amount),
-        RoundDirection::Ceiling => {
-            let token_a_remainder = pool_tokens
-                .checked_mul(swap_token_a_amount)?
-                .checked_rem(pool_token_supply)?;
-
-            if token_a_remainder > 0 && token_a_amount > 0 {
-                token_a_amount += 1;
-            }
-
-            let token_b_remainder = pool_tokens
-                .checked_mul(swap_token_b_amount)?
-                .checked_rem(pool_token_supply)?;
-
-            // println!("{:?}", token_b_remainder);
-            if token_b_remainder > 0 && token_b_amount > 0 {
-                token_b_amount += 1;
-            }
+    pool_tokens_to_trading_tokens_with_rounding(
+        pool_tokens,
+        pool_token_supply,
+        swap_token_a_amount,
+        swap_token_b_amount,
+        round_direction,
+    )
+    .map(TradingTokenResult::from)
+}
 
-            (token_a_amount, token_b_amount)
+/// Same as `pool_tokens_to_trading_tokens`, also reporting which sides were
+/// rounded down.
+///
+/// An amount with a remainder is rounded down on `Floor`, and on `Ceiling`
+/// only if it is zero: rounding up a share worth less than one token would
+/// charge a whole token for it, so it is returned as zero, to be rejected
+/// later in processing.
+pub fn pool_tokens_to_trading_tokens_with_rounding(
+    pool_tokens: u128,
+    pool_token_supply: u128,
+    swap_token_a_amount: u128,
+    swap_token_b_amount: u128,
+    round_direction: RoundDirection,
+) -> Option<TradingTokenResultWithRounding> {
+    if pool_token_supply == 0 {
+        return None;
+    }
+    let share = |swap_token_amount: u128| {
+        let numerator = pool_tokens.checked_mul(swap_token_amount)?;
+        let amount = numerator.checked_div(pool_token_supply)?;
+        let remainder = numerator.checked_rem(pool_token_supply)?;
+        if remainder == 0 {
+            Some((amount, false))
+        } else if round_direction == RoundDirection::Ceiling && amount > 0 {
+            Some((amount + 1, false))
+        } else {
+            Some((amount, true))
         }
     };
+    let (token_a_amount, token_a_rounded_down) = share(swap_token_a_amount)?;
+    let (token_b_amount, token_b_rounded_down) = share(swap_token_b_amount)?;
 
-    Some(TradingTokenResult {
+    Some(TradingTokenResultWithRounding {
         token_a_amount,
         token_b_amount,
+        token_a_rounded_down,
+        token_b_rounded_down,
     })
 }
 
@@ -100,22 +160,57 @@ pub fn deposit_single_token_type(
     trade_direction: TradeDirection,
     round_direction: RoundDirection,
 ) -> Option<u128> {
+    try_deposit_single_token_type(
+        source_amount,
+        swap_token_a_amount,
+        swap_token_b_amount,
+        pool_supply,
+        trade_direction,
+        round_direction,
+    )
+    .ok()
+}
+
+/// Same as `deposit_single_token_type`, failing with `DivisionByZero` on an
+/// empty source reserve, `SquareRootFailure` if the square root doesn't
+/// converge, and `CalculationFailure` on any overflow
+pub fn try_deposit_single_token_type(
+    source_amount: u128,
+    swap_token_a_amount: u128,
+    swap_token_b_amount: u128,
+    pool_supply: u128,
+    trade_direction: TradeDirection,
+    round_direction: RoundDirection,
+) -> Result<u128, SwapError> {
     let swap_source_amount = match trade_direction {
         TradeDirection::AtoB => swap_token_a_amount,
         TradeDirection::BtoA => swap_token_b_amount,
     };
-    let swap_source_amount = PreciseNumber::new(swap_source_amount)?;
-    let source_amount = PreciseNumber::new(source_amount)?;
-    let ratio = source_amount.checked_div(&swap_source_amount)?;
-    let one = PreciseNumber::new(1)?;
-    let base = one.checked_add(&ratio)?;
-    let root = base.sqrt()?.checked_sub(&one)?;
-    let pool_supply = PreciseNumber::new(pool_supply)?;
-    let pool_tokens = pool_supply.checked_mul(&root)?;
-    match round_direction {
-        RoundDirection::Floor => pool_tokens.floor()?.to_imprecise(),
-        RoundDirection::Ceiling => pool_tokens.ceiling()?.to_imprecise(),
+    if swap_source_amount == 0 {
+        return Err(SwapError::DivisionByZero);
+    }
+    let precise = |value| PreciseNumber::new(value).ok_or(SwapError::CalculationFailure);
+    let ratio = precise(source_amount)?
+        .checked_div(&precise(swap_source_amount)?)
+        .ok_or(SwapError::CalculationFailure)?;
+    let one = precise(1)?;
+    let base = one
+        .checked_add(&ratio)
+        .ok_or(SwapError::CalculationFailure)?;
+    let root = base
+        .sqrt()
+        .ok_or(SwapError::SquareRootFailure)?
+        .checked_sub(&one)
+        .ok_or(SwapError::CalculationFailure)?;
+    let pool_tokens = precise(pool_supply)?
+        .checked_mul(&root)
+        .ok_or(SwapError::CalculationFailure)?;
+    let pool_tokens = match round_direction {
+        RoundDirection::Floor => pool_tokens.floor(),
+        RoundDirection::Ceiling => pool_tokens.ceiling(),
     }
+    .ok_or(SwapError::CalculationFailure)?;
+    precise_to_u128(&pool_tokens).ok_or(SwapError::CalculationFailure)
 }
 
 /// Get the amount of pool tokens for the withdrawn amount of token A or B.
@@ -143,8 +238,8 @@ pub fn withdraw_single_token_type_exact_out(
     let pool_supply = PreciseNumber::new(pool_supply)?;
     let pool_tokens = pool_supply.checked_mul(&root)?;
     match round_direction {
-        RoundDirection::Floor => pool_tokens.floor()?.to_imprecise(),
-        RoundDirection::Ceiling => pool_tokens.ceiling()?.to_imprecise(),
+        RoundDirection::Floor => precise_to_u128(&pool_tokens.floor()?),
+        RoundDirection::Ceiling => precise_to_u128(&pool_tokens.ceiling()?),
     }
 }
 
@@ -156,11 +251,7 @@ pub fn normalized_value(
     swap_token_a_amount: u128,
     swap_token_b_amount: u128,
 ) -> Option<PreciseNumber> {
-    let swap_token_a_amount = PreciseNumber::new(swap_token_a_amount)?;
-    let swap_token_b_amount = PreciseNumber::new(swap_token_b_amount)?;
-    swap_token_a_amount
-        .checked_mul(&swap_token_b_amount)?
-        .sqrt()
+    precise_sqrt(swap_token_a_amount.checked_mul(swap_token_b_amount)?)
 }
 
 impl CurveCalculator for ConstantProductCurve {
@@ -175,6 +266,32 @@ impl CurveCalculator for ConstantProductCurve {
         swap(source_amount, swap_source_amount, swap_destination_amount)
     }
 
+    fn supports_exact_out(&self) -> bool {
+        true
+    }
+
+    /// Solve x * y = constant for the source amount, rounding up in favor of
+    /// the pool
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        _trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let invariant = swap_source_amount.checked_mul(swap_destination_amount)?;
+        let new_swap_destination_amount =
+            map_zero_to_none(swap_destination_amount.checked_sub(destination_amount)?)?;
+        let (new_swap_source_amount, _) =
+            invariant.checked_ceil_div(new_swap_destination_amount)?;
+        let source_amount_swapped =
+            map_zero_to_none(new_swap_source_amount.checked_sub(swap_source_amount)?)?;
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped: map_zero_to_none(destination_amount)?,
+        })
+    }
+
     /// The constant product implementation is a simple ratio calculation for how many
     /// trading tokens correspond to a certain number of pool tokens
     fn pool_tokens_to_trading_tokens(
@@ -213,6 +330,24 @@ impl CurveCalculator for ConstantProductCurve {
         )
     }
 
+    fn try_deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Result<u128, SwapError> {
+        try_deposit_single_token_type(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+            RoundDirection::Floor,
+        )
+    }
+
     fn withdraw_single_token_type_exact_out(
         &self,
         source_amount: u128,
@@ -239,25 +374,69 @@ impl CurveCalculator for ConstantProductCurve {
         normalized_value(swap_token_a_amount, swap_token_b_amount)
     }
 
+    fn clone_box(&self) -> Box<dyn CurveCalculator + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
     fn validate(&self) -> Result<(), SwapError> {
         Ok(())
     }
+
+    /// The invariant is rounded up in favor of the pool, so the last token of
+    /// either side can never be paid out
+    fn minimum_initial_reserves(&self) -> (u64, u64) {
+        (2, 2)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for ConstantProductCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+
+impl Sealed for ConstantProductCurve {}
+
+impl Pack for ConstantProductCurve {
+    const LEN: usize = 0;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(_input: &[u8]) -> Result<ConstantProductCurve, ProgramError> {
+        Ok(Self {})
+    }
+}
+
+impl DynPack for ConstantProductCurve {
+    fn pack_into_slice(&self, _output: &mut [u8]) {}
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::curve::calculator::{
+        impermanent_loss_bps, normalized_value_from_spot_price,
         test::{
-            check_curve_value_from_swap, check_deposit_token_conversion,
-            check_pool_value_from_deposit, check_pool_value_from_withdraw,
-            check_withdraw_token_conversion, total_and_intermediate,
+            check_curve_value_from_swap, check_deposit_withdraw_roundtrip, check_max_output,
+            check_swap_against_ideal, impl_curve_tests, total_and_intermediate,
             CONVERSION_BASIS_POINTS_GURANTEE,
         },
         RoundDirection, INITIAL_SWAP_POOL_AMOUNT,
     };
     use proptest::prelude::*;
 
+    impl_curve_tests!(
+        ConstantProductCurve {},
+        conversion_basis_points = CONVERSION_BASIS_POINTS_GURANTEE
+    );
+
     #[test]
     fn initial_pool_amount() {
         let calculator = ConstantProductCurve {};
@@ -293,6 +472,79 @@ mod tests {
         check_pool_token_rate(5, 501, 2, 10, 1, 101);
     }
 
+    #[test]
+    fn trading_token_conversion_with_rounding() {
+        let convert = |pool_tokens, round_direction| {
+            pool_tokens_to_trading_tokens_with_rounding(pool_tokens, 10, 2, 49, round_direction)
+                .unwrap()
+        };
+
+        // 1 token A exactly, 24.5 token B
+        assert_eq!(
+            convert(5, RoundDirection::Floor),
+            TradingTokenResultWithRounding {
+                token_a_amount: 1,
+                token_b_amount: 24,
+                token_a_rounded_down: false,
+                token_b_rounded_down: true,
+            }
+        );
+        assert_eq!(
+            convert(5, RoundDirection::Ceiling),
+            TradingTokenResultWithRounding {
+                token_a_amount: 1,
+                token_b_amount: 25,
+                token_a_rounded_down: false,
+                token_b_rounded_down: false,
+            }
+        );
+
+        // 0.2 token A isn't rounded up to a whole token, 4.9 token B is
+        assert_eq!(
+            convert(1, RoundDirection::Ceiling),
+            TradingTokenResultWithRounding {
+                token_a_amount: 0,
+                token_b_amount: 5,
+                token_a_rounded_down: true,
+                token_b_rounded_down: false,
+            }
+        );
+
+        assert_eq!(
+            TradingTokenResult::from(convert(5, RoundDirection::Floor)),
+            pool_tokens_to_trading_tokens(5, 10, 2, 49, RoundDirection::Floor).unwrap()
+        );
+    }
+
+    #[test]
+    fn try_deposit_single_token_type() {
+        let deposit = |source_amount, swap_token_a_amount| {
+            super::try_deposit_single_token_type(
+                source_amount,
+                swap_token_a_amount,
+                1_000,
+                1_000,
+                TradeDirection::AtoB,
+                RoundDirection::Floor,
+            )
+        };
+        // sqrt(1 + 1_000 / 3_000) - 1 of the supply
+        assert_eq!(deposit(1_000, 3_000), Ok(154));
+        assert_eq!(deposit(1_000, 0), Err(SwapError::DivisionByZero));
+        assert_eq!(deposit(u128::MAX, 1), Err(SwapError::SquareRootFailure));
+
+        // the trait method reports the same errors
+        let curve = ConstantProductCurve {};
+        assert_eq!(
+            curve.try_deposit_single_token_type(1_000, 0, 1_000, 1_000, TradeDirection::AtoB),
+            Err(SwapError::DivisionByZero)
+        );
+        assert_eq!(
+            curve.deposit_single_token_type(1_000, 0, 1_000, 1_000, TradeDirection::AtoB),
+            None
+        );
+    }
+
     #[test]
     fn fail_trading_token_coversion() {
         let calculator = ConstantProductCurve {};
@@ -304,61 +556,172 @@ mod tests {
         assert!(results.is_none());
     }
 
+    #[test]
+    fn spot_price() {
+        let calculator = ConstantProductCurve {};
+        let price = calculator.spot_price(80, 40).unwrap();
+        assert_eq!(
+            price,
+            PreciseNumber::new(1)
+                .unwrap()
+                .checked_div(&PreciseNumber::new(2).unwrap())
+                .unwrap()
+        );
+        assert_eq!(calculator.spot_price_decimal(80, 40, 6), Some(500_000));
+        assert_eq!(calculator.spot_price_decimal(40, 80, 0), Some(2));
+        assert_eq!(calculator.spot_price_decimal(3, 1, 4), Some(3_333));
+        assert!(calculator.spot_price(0, 40).is_none());
+    }
+
+    #[test]
+    fn marginal_output() {
+        let calculator = ConstantProductCurve {};
+        // 4 B per A at the margin, but the last fraction is kept by the pool
+        assert_eq!(
+            calculator.marginal_output(1_000_000, 4_000_000, TradeDirection::AtoB),
+            Some(3)
+        );
+        assert_eq!(
+            calculator.marginal_output(1_000, 4_000_000, TradeDirection::AtoB),
+            Some(3_996)
+        );
+        // a single B buys less than one A
+        assert_eq!(
+            calculator.marginal_output(4_000_000, 1_000_000, TradeDirection::BtoA),
+            None
+        );
+    }
+
+    #[test]
+    fn reserve_delta() {
+        let calculator = ConstantProductCurve {};
+        let (source_delta, destination_delta) = calculator
+            .reserve_delta(1_000, 1_000_000, 4_000_000, TradeDirection::AtoB)
+            .unwrap();
+        assert!(source_delta > 0);
+        assert!(destination_delta < 0);
+
+        let result = calculator
+            .swap_without_fees(1_000, 1_000_000, 4_000_000, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(source_delta as u128, result.source_amount_swapped);
+        assert_eq!(
+            destination_delta.unsigned_abs(),
+            result.destination_amount_swapped
+        );
+        // the invariant doesn't decrease with the new reserves
+        let new_source_amount = (1_000_000 + source_delta) as u128;
+        let new_destination_amount = (4_000_000 + destination_delta) as u128;
+        assert!(new_source_amount * new_destination_amount >= 1_000_000 * 4_000_000);
+
+        assert_eq!(
+            calculator.reserve_delta(1, 4_000_000, 1_000_000, TradeDirection::BtoA),
+            None
+        );
+    }
+
     proptest! {
         #[test]
-        fn deposit_token_conversion(
-            // in the pool token conversion calcs, we simulate trading half of
-            // source_token_amount, so this needs to be at least 2
-            source_token_amount in 2..u64::MAX,
-            swap_source_amount in 1..u64::MAX,
-            swap_destination_amount in 1..u64::MAX,
-            pool_supply in INITIAL_SWAP_POOL_AMOUNT..u64::MAX as u128,
+        fn marginal_output_below_spot_price(
+            swap_source_amount in 1_000_000..u32::MAX as u128,
+            price in 1..1_000u128,
         ) {
-                let curve = ConstantProductCurve {};
-                check_deposit_token_conversion(
-                    &curve,
-                    source_token_amount as u128,
-                    swap_source_amount as u128,
-                    swap_destination_amount as u128,
-                    TradeDirection::AtoB,
-                    pool_supply,
-                    CONVERSION_BASIS_POINTS_GURANTEE
-                );
-
-                check_deposit_token_conversion(
-                    &curve,
-                    source_token_amount as u128,
-                    swap_source_amount as u128,
-                    swap_destination_amount as u128,
-                    TradeDirection::BtoA,
-                    pool_supply,
-                    CONVERSION_BASIS_POINTS_GURANTEE
-                );
+            let calculator = ConstantProductCurve {};
+            let swap_destination_amount = swap_source_amount * price + 1;
+            let marginal_output = calculator
+                .marginal_output(swap_source_amount, swap_destination_amount, TradeDirection::AtoB)
+                .unwrap();
+            let spot_price = calculator
+                .spot_price(swap_source_amount, swap_destination_amount)
+                .unwrap()
+                .floor()
+                .unwrap()
+                .to_imprecise()
+                .unwrap();
+            // the marginal output is the price with one more token in the pool
+            prop_assert!(marginal_output <= spot_price);
+            prop_assert!(marginal_output + 1 >= spot_price);
         }
     }
 
+    #[test]
+    fn impermanent_loss() {
+        let calculator = ConstantProductCurve {};
+        let initial = (1_000_000, 1_000_000);
+
+        // price of A doubles: 2 * sqrt(2) / 3 - 1 = -5.72%
+        let current = (707_107, 1_414_214);
+        assert_eq!(
+            impermanent_loss_bps(&calculator, initial, current),
+            Some(571)
+        );
+        // price of A halves: same loss
+        let current = (1_414_214, 707_107);
+        assert_eq!(
+            impermanent_loss_bps(&calculator, initial, current),
+            Some(571)
+        );
+        // price of A quadruples: 2 * 2 / 5 - 1 = -20%
+        let current = (500_000, 2_000_000);
+        assert_eq!(
+            impermanent_loss_bps(&calculator, initial, current),
+            Some(2_000)
+        );
+
+        // no price move, with or without liquidity added
+        assert_eq!(impermanent_loss_bps(&calculator, initial, initial), Some(0));
+        let current = (3_000_000, 3_000_000);
+        assert_eq!(impermanent_loss_bps(&calculator, initial, current), Some(0));
+        // liquidity added after the price moved
+        let current = (1_414_214, 2_828_428);
+        assert_eq!(
+            impermanent_loss_bps(&calculator, initial, current),
+            Some(571)
+        );
+
+        assert_eq!(impermanent_loss_bps(&calculator, initial, (0, 0)), None);
+    }
+
+    #[test]
+    fn zero_supply_trading_token_conversion() {
+        let calculator = ConstantProductCurve {};
+        let results =
+            calculator.pool_tokens_to_trading_tokens(5, 0, 100, 100, RoundDirection::Floor);
+        assert!(results.is_none());
+        let results =
+            calculator.pool_tokens_to_trading_tokens(5, 0, 100, 100, RoundDirection::Ceiling);
+        assert!(results.is_none());
+    }
+
     proptest! {
         #[test]
-        fn withdraw_token_conversion((pool_token_supply, pool_token_amount) in total_and_intermediate(), swap_token_a_amount in 1..u64::MAX, swap_token_b_amount in 1..u64::MAX) {
+        fn deposit_withdraw_roundtrip(
+            (swap_source_amount, source_token_amount) in total_and_intermediate(),
+            swap_destination_amount in 1..u64::MAX,
+            pool_supply in INITIAL_SWAP_POOL_AMOUNT..u64::MAX as u128,
+        ) {
+            // the square roots in the conversions are approximations, with an
+            // error proportional to the pool supply, so the deposit needs to be
+            // a meaningful part of the pool for the comparison to hold
+            prop_assume!(source_token_amount >= swap_source_amount / 1_000);
             let curve = ConstantProductCurve {};
-            check_withdraw_token_conversion(
+            check_deposit_withdraw_roundtrip(
                 &curve,
-                pool_token_amount as u128,
-                pool_token_supply as u128,
-                swap_token_a_amount as u128,
-                swap_token_b_amount as u128,
+                source_token_amount as u128,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
                 TradeDirection::AtoB,
-                CONVERSION_BASIS_POINTS_GURANTEE
+                pool_supply,
+                CONVERSION_BASIS_POINTS_GURANTEE,
             );
-
-            check_withdraw_token_conversion(
+            check_deposit_withdraw_roundtrip(
                 &curve,
-                pool_token_amount as u128,
-                pool_token_supply as u128,
-                swap_token_a_amount as u128,
-                swap_token_b_amount as u128,
+                source_token_amount as u128,
+                swap_destination_amount as u128,
+                swap_source_amount as u128,
                 TradeDirection::BtoA,
-                CONVERSION_BASIS_POINTS_GURANTEE
+                pool_supply,
+                CONVERSION_BASIS_POINTS_GURANTEE,
             );
         }
     }
@@ -383,55 +746,208 @@ mod tests {
 
     proptest! {
         #[test]
-        fn curve_value_does_not_decrease_from_deposit(
-            pool_token_amount in 1..u64::MAX,
-            pool_token_supply in 1..u64::MAX,
+        fn default_normalized_value_matches(
             swap_token_a_amount in 1..u64::MAX,
             swap_token_b_amount in 1..u64::MAX,
         ) {
-            let pool_token_amount = pool_token_amount as u128;
-            let pool_token_supply = pool_token_supply as u128;
+            let curve = ConstantProductCurve {};
             let swap_token_a_amount = swap_token_a_amount as u128;
             let swap_token_b_amount = swap_token_b_amount as u128;
+            // tiny prices lose precision in the spot price's decimals
+            prop_assume!(swap_token_b_amount * 1_000 >= swap_token_a_amount);
+            let spot_price = curve.spot_price(swap_token_a_amount, swap_token_b_amount).unwrap();
+            let default_value = normalized_value_from_spot_price(
+                swap_token_a_amount,
+                swap_token_b_amount,
+                &spot_price,
+            )
+            .unwrap();
+            let explicit_value = curve
+                .normalized_value(swap_token_a_amount, swap_token_b_amount)
+                .unwrap();
+            // within one part in a million of the explicit invariant
+            let epsilon = explicit_value
+                .checked_div(&PreciseNumber::new(1_000_000).unwrap())
+                .unwrap();
+            let difference = if default_value.greater_than(&explicit_value) {
+                default_value.checked_sub(&explicit_value).unwrap()
+            } else {
+                explicit_value.checked_sub(&default_value).unwrap()
+            };
+            prop_assert!(!difference.greater_than(&epsilon));
+        }
+    }
 
-            // Make sure we will get at least one trading token out for each
-            // side, otherwise the calculation fails
-            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
-            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+    proptest! {
+        #[test]
+        fn swap_bounded_by_ideal(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+        ) {
             let curve = ConstantProductCurve {};
-            check_pool_value_from_deposit(
-                &curve,
-                pool_token_amount,
-                pool_token_supply,
-                swap_token_a_amount,
-                swap_token_b_amount
-            );
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_swap_against_ideal(
+                    &curve,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    trade_direction,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn arithmetic_audit_representative_inputs() {
+        // only checks anything with the `arithmetic-audit` feature in debug
+        for (source_amount, swap_source_amount, swap_destination_amount) in [
+            (1, 1, 1),
+            (1_000, 1_000_000, 4_000_000),
+            (u64::MAX as u128, u64::MAX as u128, u64::MAX as u128),
+        ] {
+            swap(source_amount, swap_source_amount, swap_destination_amount);
         }
     }
 
+    #[test]
+    // the audit flags the overflow which `swap` reports as `None`
+    #[cfg_attr(
+        all(feature = "arithmetic-audit", debug_assertions),
+        should_panic(expected = "arithmetic audit")
+    )]
+    fn swap_u256_above_u128_invariant() {
+        let swap_source_amount = 1u128 << 70;
+        let swap_destination_amount = 1u128 << 70;
+        let source_amount = 1u128 << 70;
+        assert!(swap(source_amount, swap_source_amount, swap_destination_amount).is_none());
+
+        // doubling the source side halves the destination side
+        let result = swap_u256(source_amount, swap_source_amount, swap_destination_amount).unwrap();
+        assert_eq!(result.source_amount_swapped, source_amount);
+        assert_eq!(
+            result.destination_amount_swapped,
+            swap_destination_amount / 2
+        );
+    }
+
     proptest! {
         #[test]
-        fn curve_value_does_not_decrease_from_withdraw(
-            (pool_token_supply, pool_token_amount) in total_and_intermediate(),
-            swap_token_a_amount in 1..u64::MAX,
-            swap_token_b_amount in 1..u64::MAX,
+        fn swap_u256_matches_swap(
+            source_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
         ) {
-            let pool_token_amount = pool_token_amount as u128;
-            let pool_token_supply = pool_token_supply as u128;
-            let swap_token_a_amount = swap_token_a_amount as u128;
-            let swap_token_b_amount = swap_token_b_amount as u128;
+            prop_assert_eq!(
+                swap_u256(
+                    source_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                ),
+                swap(
+                    source_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn is_tradeable() {
+        let curve = ConstantProductCurve {};
+        assert!(curve.is_tradeable(1_000, 1_000));
+        assert!(!curve.is_tradeable(0, 1_000));
+        assert!(!curve.is_tradeable(1_000, 0));
+        assert!(!curve.is_tradeable(0, 0));
+    }
+
+    #[test]
+    fn effective_reserves() {
+        let curve = ConstantProductCurve {};
+        assert_eq!(curve.effective_reserves(500, 200), (500, 200));
+        assert_eq!(curve.effective_reserves(0, 0), (0, 0));
+    }
+
+    #[test]
+    fn requires_both_sides() {
+        let curve = ConstantProductCurve {};
+        assert_eq!(curve.allows_empty_side(), None);
+        assert_eq!(curve.validate_supply(1_000, 0), Err(SwapError::EmptySupply));
+        assert_eq!(curve.validate_supply(0, 1_000), Err(SwapError::EmptySupply));
+        assert_eq!(curve.validate_supply(1_000, 1_000), Ok(()));
+    }
+
+    #[test]
+    fn minimum_initial_reserves() {
+        let curve = ConstantProductCurve {};
+        assert_eq!(curve.minimum_initial_reserves(), (2, 2));
+        assert_eq!(curve.validate_supply(1, 1_000), Err(SwapError::EmptySupply));
+        assert_eq!(curve.validate_supply(1_000, 1), Err(SwapError::EmptySupply));
+        assert_eq!(curve.validate_supply(2, 2), Ok(()));
+
+        // doubling the source reserve can't buy the last token
+        let swap = |swap_destination_amount| {
+            curve
+                .swap_without_fees(2, 2, swap_destination_amount, TradeDirection::AtoB)
+                .map(|result| result.destination_amount_swapped)
+        };
+        assert_eq!(swap(1), None);
+        assert_eq!(swap(2), Some(1));
+    }
+
+    #[test]
+    fn supports_exact_out() {
+        assert!(ConstantProductCurve {}.supports_exact_out());
+    }
 
-            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
-            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+    proptest! {
+        #[test]
+        fn exact_out_covers_destination_amount(
+            destination_amount in 1..u32::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+        ) {
+            let destination_amount = destination_amount as u128;
+            prop_assume!(destination_amount < swap_destination_amount as u128);
+            let curve = ConstantProductCurve {};
+            let exact_out = curve
+                .swap_without_fees_exact_out(
+                    destination_amount,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            let result = curve
+                .swap_without_fees(
+                    exact_out.source_amount_swapped,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            prop_assert!(result.destination_amount_swapped >= destination_amount);
+        }
+    }
 
+    proptest! {
+        #[test]
+        fn swap_does_not_exceed_max_output(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+        ) {
             let curve = ConstantProductCurve {};
-            check_pool_value_from_deposit(
-                &curve,
-                pool_token_amount,
-                pool_token_supply,
-                swap_token_a_amount,
-                swap_token_b_amount
-            );
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_max_output(
+                    &curve,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    trade_direction,
+                );
+            }
         }
     }
 }
@@ -1,10 +1,21 @@
-use crate::errors::SwapError;
-use anchor_lang::{solana_program::program_pack::{IsInitialized, Pack, Sealed}, prelude::ProgramError};
+use crate::{
+    curve::{base::CurveType, calculator::TradeDirection},
+    errors::SwapError,
+    state::SwapState,
+};
+use anchor_lang::{solana_program::program_pack::{IsInitialized, Pack, Sealed}, prelude::{borsh, AnchorDeserialize, AnchorSerialize, ProgramError}, Discriminator};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
-use std::convert::TryFrom;
+use spl_math::uint::U256;
+
+#[cfg(feature = "fuzz")]
+use arbitrary::Arbitrary;
+
+/// Denominator of fees given in basis points
+pub const BPS_DENOMINATOR: u64 = 10_000;
 
 // Encapsulates all fee information and calculations for swap operations
-#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq)]
 pub struct Fees {
     /// Trade fees are extra token amounts that are held inside the token
     /// accounts during a trade, making the value of liquidity tokens rise.
@@ -34,6 +45,48 @@ pub struct Fees {
     pub host_fee_numerator: u64,
     /// Host trading fee denominator
     pub host_fee_denominator: u64,
+
+    /// Trades charged the owner trading fee
+    pub owner_fee_direction: OwnerFeeDirection,
+}
+
+/// Trades charged the owner trading fee.
+///
+/// Encoded as a single byte by both Borsh and `Pack`, so that the fees, and
+/// the swap state holding them, have a fixed layout.
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OwnerFeeDirection {
+    /// Trades in both directions
+    #[default]
+    All,
+    /// Only trades from token A to token B
+    AtoB,
+    /// Only trades from token B to token A
+    BtoA,
+}
+
+impl OwnerFeeDirection {
+    /// Owner fee direction from its encoded tag, as stored in the swap state
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(OwnerFeeDirection::All),
+            1 => Some(OwnerFeeDirection::AtoB),
+            2 => Some(OwnerFeeDirection::BtoA),
+            _ => None,
+        }
+    }
+}
+
+/// Calculate `a * b / denom`, rounding down, with a `U256` intermediate
+/// product so that it only fails if `denom` is zero or the result doesn't fit
+/// in a `u128`
+pub fn mul_div(a: u128, b: u128, denom: u128) -> Option<u128> {
+    let result = U256::from(a)
+        .checked_mul(U256::from(b))?
+        .checked_div(U256::from(denom))?;
+    u128::try_from(result).ok()
 }
 
 pub fn calculate_fee(
@@ -44,9 +97,7 @@ pub fn calculate_fee(
     if fee_numerator == 0 || token_amount == 0 {
         Some(0)
     } else {
-        let fee = token_amount
-            .checked_mul(fee_numerator)?
-            .checked_div(fee_denominator)?;
+        let fee = mul_div(token_amount, fee_numerator, fee_denominator)?;
 
         if fee == 0 {
             Some(1) // minimum fee of one token
@@ -56,23 +107,125 @@ pub fn calculate_fee(
     }
 }
 
-fn validate_fraction(numerator: u64, denominator: u64) -> Result<(), SwapError> {
-    if denominator == 0 && numerator == 0 {
-        Ok(())
-    } else if numerator >= denominator {
-        Err(SwapError::InvalidFee)
+/// Calculate the trading and owner trading fees actually charged on a swap of
+/// `amount`, in basis points of the amount, so clients can warn users about
+/// dust trades. The owner fee is assumed to apply to the trade's direction.
+///
+/// Since every enabled fee charges a minimum of one token, the realized rate
+/// can be far above the configured rate for small amounts. Returns 0 for a
+/// zero amount, and saturates if the fees can't be calculated.
+pub fn effective_fee_bps_for_amount(fees: &Fees, amount: u128) -> u128 {
+    if amount == 0 {
+        return 0;
+    }
+    let total_fee = fees
+        .trading_fee(amount)
+        .zip(fees.owner_trading_fee(amount))
+        .and_then(|(trading_fee, owner_fee)| trading_fee.checked_add(owner_fee));
+    match total_fee {
+        Some(total_fee) => total_fee.saturating_mul(u128::from(BPS_DENOMINATOR)) / amount,
+        None => u128::MAX,
+    }
+}
+
+/// Helper function for calculating the amount before a fee fraction is taken,
+/// rounding up. Returns `None` if the fee takes the entire amount.
+fn pre_fee_amount(
+    post_fee_amount: u128,
+    fee_numerator: u128,
+    fee_denominator: u128,
+) -> Option<u128> {
+    if fee_numerator == 0 || fee_denominator == 0 {
+        Some(post_fee_amount)
     } else {
-        Ok(())
+        let numerator = post_fee_amount.checked_mul(fee_denominator)?;
+        let denominator = fee_denominator.checked_sub(fee_numerator)?;
+        numerator
+            .checked_add(denominator)?
+            .checked_sub(1)?
+            .checked_div(denominator)
+    }
+}
+
+/// Check that a fee fraction is either disabled, as `0 / 0`, or a proper
+/// fraction below one
+fn validate_fraction(numerator: u64, denominator: u64) -> Result<(), SwapError> {
+    match (numerator, denominator) {
+        // fee disabled
+        (0, 0) => Ok(()),
+        // a fee with no denominator can't be calculated
+        (_, 0) => Err(SwapError::InvalidFee),
+        // zero fee
+        (0, _) => Ok(()),
+        // fees must take less than the whole amount
+        (numerator, denominator) if numerator >= denominator => Err(SwapError::InvalidFee),
+        _ => Ok(()),
     }
 }
 
 impl Fees {
+    /// Read only the fees out of the data of a serialized `SwapState`
+    /// account, without deserializing the rest of the account.
+    ///
+    /// The Borsh-encoded fees take `Fees::LEN` bytes from
+    /// `SwapState::FEES_OFFSET`, laid out as by `Pack`.
+    pub fn unpack_from_swap_state(data: &[u8]) -> Result<Fees, SwapError> {
+        if !data.starts_with(&SwapState::discriminator()) {
+            return Err(SwapError::InvalidInput);
+        }
+        let fees = data
+            .get(SwapState::FEES_OFFSET..SwapState::FEES_OFFSET + Fees::LEN)
+            .ok_or(SwapError::InvalidInput)?;
+        Fees::unpack_from_slice(fees).map_err(|_| SwapError::InvalidInput)
+    }
+
+    /// Suggested fees for a new pool using the given curve, charging only a
+    /// trade fee suited to the expected volatility of the pair.
+    ///
+    /// This is a starting point for pool creators, and is not enforced.
+    pub fn recommended_for(curve_type: CurveType) -> Fees {
+        let trade_fee_bps = match curve_type {
+            CurveType::ConstantProduct | CurveType::Offset => 30,
+            CurveType::Concentrated => 10,
+            CurveType::Stable | CurveType::ConstantPrice => 4,
+        };
+        Fees::default().with_trade_fee_bps(trade_fee_bps)
+    }
+
+    /// Set the trade fee to `bps` basis points
+    pub fn with_trade_fee_bps(mut self, bps: u64) -> Self {
+        self.trade_fee_numerator = bps;
+        self.trade_fee_denominator = BPS_DENOMINATOR;
+        self
+    }
+
+    /// Set the owner trade fee to `bps` basis points
+    pub fn with_owner_trade_fee_bps(mut self, bps: u64) -> Self {
+        self.owner_trade_fee_numerator = bps;
+        self.owner_trade_fee_denominator = BPS_DENOMINATOR;
+        self
+    }
+
+    /// Set the owner withdraw fee to `bps` basis points
+    pub fn with_owner_withdraw_fee_bps(mut self, bps: u64) -> Self {
+        self.owner_withdraw_fee_numerator = bps;
+        self.owner_withdraw_fee_denominator = BPS_DENOMINATOR;
+        self
+    }
+
+    /// Set the host fee to `bps` basis points of the owner trade fee
+    pub fn with_host_fee_bps(mut self, bps: u64) -> Self {
+        self.host_fee_numerator = bps;
+        self.host_fee_denominator = BPS_DENOMINATOR;
+        self
+    }
+
     /// Calculates the withdraw fee in pool tokens
     pub fn owner_withdraw_fee(&self, pool_tokens: u128) -> Option<u128> {
         calculate_fee(
             pool_tokens,
-            u128::try_from(self.owner_withdraw_fee_numerator).ok()?,
-            u128::try_from(self.owner_withdraw_fee_denominator).ok()?,
+            u128::from(self.owner_withdraw_fee_numerator),
+            u128::from(self.owner_withdraw_fee_denominator),
         )
     }
 
@@ -80,8 +233,8 @@ impl Fees {
     pub fn trading_fee(&self, trading_tokens: u128) -> Option<u128> {
         calculate_fee(
             trading_tokens,
-            u128::try_from(self.trade_fee_numerator).ok()?,
-            u128::try_from(self.trade_fee_denominator).ok()?,
+            u128::from(self.trade_fee_numerator),
+            u128::from(self.trade_fee_denominator),
         )
     }
 
@@ -89,8 +242,109 @@ impl Fees {
     pub fn owner_trading_fee(&self, trading_tokens: u128) -> Option<u128> {
         calculate_fee(
             trading_tokens,
-            u128::try_from(self.owner_trade_fee_numerator).ok()?,
-            u128::try_from(self.owner_trade_fee_denominator).ok()?,
+            u128::from(self.owner_trade_fee_numerator),
+            u128::from(self.owner_trade_fee_denominator),
+        )
+    }
+
+    /// Calculate the trading and owner trading fees of a single token deposit
+    /// of `source_amount`, charged on the half of it which is effectively
+    /// swapped for the other token
+    pub fn single_deposit_fees(&self, source_amount: u128) -> Option<(u128, u128)> {
+        let half_source_amount = std::cmp::max(1, source_amount / 2);
+        Some((
+            self.trading_fee(half_source_amount)?,
+            self.owner_trading_fee(half_source_amount)?,
+        ))
+    }
+
+    /// Check if the pool charges owner trading fees at all. Pools with a zero
+    /// owner fee fraction, such as governance-owned pools, mint no owner fee
+    /// pool tokens on swaps
+    pub fn owner_fees_enabled(&self) -> bool {
+        self.owner_trade_fee_numerator != 0 || self.owner_trade_fee_denominator != 0
+    }
+
+    /// Check if the owner trading fee applies to a trade in the given direction
+    pub fn charges_owner_fee(&self, trade_direction: TradeDirection) -> bool {
+        match self.owner_fee_direction {
+            OwnerFeeDirection::All => true,
+            OwnerFeeDirection::AtoB => trade_direction == TradeDirection::AtoB,
+            OwnerFeeDirection::BtoA => trade_direction == TradeDirection::BtoA,
+        }
+    }
+
+    /// Combined trading and owner trading fee rate, as the fraction of the
+    /// source amount retained by the pool and the owner on a swap.
+    ///
+    /// The fraction ignores the minimum fee of one token charged by each
+    /// enabled fee, and saturates for denominators too large to combine.
+    pub fn total_fee_numerator_denominator(&self) -> (u128, u128) {
+        let trade_fee_numerator = u128::from(self.trade_fee_numerator);
+        let trade_fee_denominator = u128::from(self.trade_fee_denominator);
+        let owner_trade_fee_numerator = u128::from(self.owner_trade_fee_numerator);
+        let owner_trade_fee_denominator = u128::from(self.owner_trade_fee_denominator);
+
+        match (
+            trade_fee_numerator == 0 || trade_fee_denominator == 0,
+            owner_trade_fee_numerator == 0 || owner_trade_fee_denominator == 0,
+        ) {
+            (true, true) => (0, 1),
+            (false, true) => (trade_fee_numerator, trade_fee_denominator),
+            (true, false) => (owner_trade_fee_numerator, owner_trade_fee_denominator),
+            (false, false) if trade_fee_denominator == owner_trade_fee_denominator => (
+                trade_fee_numerator + owner_trade_fee_numerator,
+                trade_fee_denominator,
+            ),
+            (false, false) => (
+                (trade_fee_numerator * owner_trade_fee_denominator)
+                    .saturating_add(owner_trade_fee_numerator * trade_fee_denominator),
+                trade_fee_denominator * owner_trade_fee_denominator,
+            ),
+        }
+    }
+
+    /// Calculate how much source token is needed so that at least
+    /// `post_fee_amount` is left after the trading and owner trading fees.
+    ///
+    /// Since every enabled fee charges a minimum of one token, this is a
+    /// conservative estimate, and can be slightly larger than required.
+    pub fn pre_trading_fee_amount(&self, post_fee_amount: u128) -> Option<u128> {
+        let trade_fee_numerator = u128::from(self.trade_fee_numerator);
+        let trade_fee_denominator = u128::from(self.trade_fee_denominator);
+        let owner_trade_fee_numerator = u128::from(self.owner_trade_fee_numerator);
+        let owner_trade_fee_denominator = u128::from(self.owner_trade_fee_denominator);
+
+        let mut minimum_fees = 0;
+        let (fee_numerator, fee_denominator) = match (
+            trade_fee_numerator == 0 || trade_fee_denominator == 0,
+            owner_trade_fee_numerator == 0 || owner_trade_fee_denominator == 0,
+        ) {
+            (true, true) => (0, 0),
+            (false, true) => {
+                minimum_fees += 1;
+                (trade_fee_numerator, trade_fee_denominator)
+            }
+            (true, false) => {
+                minimum_fees += 1;
+                (owner_trade_fee_numerator, owner_trade_fee_denominator)
+            }
+            (false, false) => {
+                minimum_fees += 2;
+                (
+                    trade_fee_numerator
+                        .checked_mul(owner_trade_fee_denominator)?
+                        .checked_add(
+                            owner_trade_fee_numerator.checked_mul(trade_fee_denominator)?,
+                        )?,
+                    trade_fee_denominator.checked_mul(owner_trade_fee_denominator)?,
+                )
+            }
+        };
+        pre_fee_amount(
+            post_fee_amount.checked_add(minimum_fees)?,
+            fee_numerator,
+            fee_denominator,
         )
     }
 
@@ -99,11 +353,21 @@ impl Fees {
     pub fn host_fee(&self, owner_fee: u128) -> Option<u128> {
         calculate_fee(
             owner_fee,
-            u128::try_from(self.host_fee_numerator).ok()?,
-            u128::try_from(self.host_fee_denominator).ok()?,
+            u128::from(self.host_fee_numerator),
+            u128::from(self.host_fee_denominator),
         )
     }
 
+    /// Calculate the host fee based on the owner fee, returning 0 instead of
+    /// `None` on overflow or a zero denominator.
+    ///
+    /// The host fee is optional, so prefer this when a failed host fee
+    /// calculation should simply skip the host payout rather than fail the
+    /// whole swap. Use `host_fee` whenever the result must be exact.
+    pub fn host_fee_saturating(&self, owner_fee: u128) -> u128 {
+        self.host_fee(owner_fee).unwrap_or(0)
+    }
+
     /// Validate that the fees are reasonable
     pub fn validate(&self) -> Result<(), SwapError> {
         validate_fraction(self.trade_fee_numerator, self.trade_fee_denominator)?;
@@ -121,6 +385,51 @@ impl Fees {
     }
 }
 
+/// Largest `DynamicFees::imbalance_multiplier_bps`, at which a pool holding
+/// only one token charges triple the base trade fee
+pub const MAX_IMBALANCE_MULTIPLIER_BPS: u16 = 20_000;
+
+/// Fees whose trade fee rises with the imbalance of the pool, to discourage
+/// draining one side of a stable pool
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DynamicFees {
+    /// Fees charged by a balanced pool
+    pub base: Fees,
+    /// Increase of the trade fee for a pool holding only one token, in basis
+    /// points of the base trade fee
+    pub imbalance_multiplier_bps: u16,
+}
+
+impl DynamicFees {
+    /// Fees to charge given the reserves of the pool.
+    ///
+    /// The trade fee is scaled by
+    /// `1 + imbalance_multiplier_bps / 10_000 * |a - b| / (a + b)`, rounding up
+    /// in favor of the pool, and kept below the whole amount. The other fees
+    /// are unchanged.
+    pub fn effective_fees(&self, reserve_a: u128, reserve_b: u128) -> Fees {
+        let numerator = U256::from(self.base.trade_fee_numerator);
+        let imbalance = U256::from(reserve_a.abs_diff(reserve_b));
+        let total = U256::from(reserve_a) + U256::from(reserve_b);
+        if numerator.is_zero() || total.is_zero() {
+            return self.base.clone();
+        }
+        let denominator = total * U256::from(BPS_DENOMINATOR);
+        let increase = (numerator * U256::from(self.imbalance_multiplier_bps) * imbalance
+            + denominator
+            - 1)
+            / denominator;
+        let max_numerator = self.base.trade_fee_denominator.saturating_sub(1);
+        let trade_fee_numerator = (numerator + increase)
+            .min(U256::from(max_numerator))
+            .as_u64();
+        Fees {
+            trade_fee_numerator,
+            ..self.base.clone()
+        }
+    }
+}
+
 /// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
 impl IsInitialized for Fees {
     fn is_initialized(&self) -> bool {
@@ -131,11 +440,11 @@ impl IsInitialized for Fees {
 impl Sealed for Fees {}
 
 impl Pack for Fees {
-   const LEN: usize = 64;
+   const LEN: usize = 65;
 
    fn pack_into_slice(&self, output: &mut [u8]) {
 
-       let output = array_mut_ref![output, 0, 64];
+       let output = array_mut_ref![output, 0, 65];
        let (
            trade_fee_numerator,
            trade_fee_denominator,
@@ -145,7 +454,8 @@ impl Pack for Fees {
            owner_withdraw_fee_denominator,
            host_fee_numerator,
            host_fee_denominator,
-       ) = mut_array_refs![output, 8, 8, 8, 8, 8, 8, 8, 8];
+           owner_fee_direction,
+       ) = mut_array_refs![output, 8, 8, 8, 8, 8, 8, 8, 8, 1];
         *trade_fee_numerator = self.trade_fee_numerator.to_le_bytes();
         *trade_fee_denominator = self.trade_fee_denominator.to_le_bytes();
         *owner_trade_fee_numerator = self.owner_trade_fee_numerator.to_le_bytes();
@@ -154,10 +464,11 @@ impl Pack for Fees {
         *owner_withdraw_fee_denominator= self.owner_withdraw_fee_denominator.to_le_bytes();
         *host_fee_numerator = self.host_fee_numerator.to_le_bytes();
         *host_fee_denominator = self.host_fee_denominator.to_le_bytes();
+        owner_fee_direction[0] = self.owner_fee_direction as u8;
    }
 
    fn unpack_from_slice(input: &[u8]) -> Result<Fees, ProgramError> {
-       let input = array_ref![input, 0, 64];
+       let input = array_ref![input, 0, 65];
        #[allow(clippy::ptr_offset_with_cast)]
        let (
             trade_fee_numerator,
@@ -168,7 +479,8 @@ impl Pack for Fees {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
-        ) = array_refs![input, 8, 8, 8, 8, 8, 8, 8, 8];
+            owner_fee_direction,
+        ) = array_refs![input, 8, 8, 8, 8, 8, 8, 8, 8, 1];
        Ok(Self {
              trade_fee_numerator: u64::from_le_bytes(*trade_fee_numerator),
              trade_fee_denominator: u64::from_le_bytes(*trade_fee_denominator),
@@ -178,10 +490,26 @@ impl Pack for Fees {
              owner_withdraw_fee_denominator: u64::from_le_bytes(*owner_withdraw_fee_denominator),
              host_fee_numerator: u64::from_le_bytes(*host_fee_numerator),
              host_fee_denominator: u64::from_le_bytes(*host_fee_denominator),
+             owner_fee_direction: OwnerFeeDirection::from_u8(owner_fee_direction[0])
+                 .ok_or(ProgramError::InvalidAccountData)?,
        })
    }
 }
 
+/// Decode fees packed as by `Pack`, eg. as received from a client, failing
+/// with `InvalidInput` on a slice of any other length than `Fees::LEN` or an
+/// invalid owner fee direction
+impl TryFrom<&[u8]> for Fees {
+    type Error = SwapError;
+
+    fn try_from(input: &[u8]) -> Result<Self, Self::Error> {
+        if input.len() != Fees::LEN {
+            return Err(SwapError::InvalidInput);
+        }
+        Fees::unpack_from_slice(input).map_err(|_| SwapError::InvalidInput)
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -197,6 +525,7 @@ mod tests {
         let owner_withdraw_fee_denominator = 10;
         let host_fee_numerator = 7;
         let host_fee_denominator = 100;
+        let owner_fee_direction = OwnerFeeDirection::BtoA;
         let fees = Fees {
             trade_fee_numerator,
             trade_fee_denominator,
@@ -206,6 +535,7 @@ mod tests {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
+            owner_fee_direction,
         };
 
         let mut packed = [0u8; Fees::LEN];
@@ -222,8 +552,362 @@ mod tests {
         packed.extend_from_slice(&owner_withdraw_fee_denominator.to_le_bytes());
         packed.extend_from_slice(&host_fee_numerator.to_le_bytes());
         packed.extend_from_slice(&host_fee_denominator.to_le_bytes());
+        packed.push(2);
         let unpacked = Fees::unpack_from_slice(&packed).unwrap();
         assert_eq!(fees, unpacked);
+
+        // Borsh agrees with `Pack`, whatever the owner fee direction
+        for owner_fee_direction in [
+            OwnerFeeDirection::All,
+            OwnerFeeDirection::AtoB,
+            OwnerFeeDirection::BtoA,
+        ] {
+            let fees = Fees {
+                owner_fee_direction,
+                ..fees.clone()
+            };
+            let mut packed = [0u8; Fees::LEN];
+            Pack::pack_into_slice(&fees, &mut packed[..]);
+            assert_eq!(fees.try_to_vec().unwrap(), packed);
+        }
     }
 
+    #[test]
+    fn mul_div() {
+        assert_eq!(super::mul_div(10, 3, 4), Some(7));
+        assert_eq!(super::mul_div(10, 3, 0), None);
+
+        // the product overflows a u128, but not the result
+        let a = u128::MAX / 2;
+        assert_eq!(a.checked_mul(4), None);
+        assert_eq!(super::mul_div(a, 4, 8), Some(a / 2));
+        assert_eq!(calculate_fee(a, 4, 8), Some(a / 2));
+        assert_eq!(
+            super::mul_div(u128::MAX, u128::MAX, u128::MAX),
+            Some(u128::MAX)
+        );
+
+        // the result doesn't fit
+        assert_eq!(super::mul_div(a, 4, 1), None);
+    }
+
+    #[test]
+    fn unpack_invalid_owner_fee_direction() {
+        let mut packed = [0u8; Fees::LEN];
+        packed[Fees::LEN - 1] = 3;
+        assert_eq!(
+            Fees::unpack_from_slice(&packed),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn try_from_slice() {
+        let fees = Fees {
+            owner_fee_direction: OwnerFeeDirection::AtoB,
+            ..Fees::default()
+                .with_trade_fee_bps(25)
+                .with_owner_trade_fee_bps(5)
+        };
+        let mut packed = [0u8; Fees::LEN];
+        Pack::pack_into_slice(&fees, &mut packed[..]);
+        assert_eq!(Fees::try_from(&packed[..]), Ok(fees));
+
+        assert_eq!(
+            Fees::try_from(&packed[..Fees::LEN - 1]),
+            Err(SwapError::InvalidInput)
+        );
+        let mut longer = packed.to_vec();
+        longer.push(0);
+        assert_eq!(Fees::try_from(&longer[..]), Err(SwapError::InvalidInput));
+        packed[Fees::LEN - 1] = 3;
+        assert_eq!(Fees::try_from(&packed[..]), Err(SwapError::InvalidInput));
+    }
+
+    #[test]
+    fn dynamic_fees() {
+        let base = Fees::default()
+            .with_trade_fee_bps(30)
+            .with_owner_trade_fee_bps(5);
+        let fees = DynamicFees {
+            base: base.clone(),
+            imbalance_multiplier_bps: 10_000,
+        };
+
+        // balanced pools pay the base fee
+        assert_eq!(fees.effective_fees(1_000_000, 1_000_000), base);
+        assert_eq!(fees.effective_fees(0, 0), base);
+
+        // 80% imbalance raises the fee by 80%, either way around
+        let imbalanced = fees.effective_fees(900_000, 100_000);
+        assert_eq!(imbalanced.trade_fee_numerator, 54);
+        assert_eq!(imbalanced.trade_fee_denominator, 10_000);
+        assert_eq!(imbalanced.owner_trade_fee_numerator, 5);
+        assert_eq!(fees.effective_fees(100_000, 900_000), imbalanced);
+        // a one-sided pool doubles the fee
+        assert_eq!(fees.effective_fees(1_000_000, 0).trade_fee_numerator, 60);
+        // the increase rounds up
+        assert_eq!(
+            fees.effective_fees(1_000_001, 1_000_000)
+                .trade_fee_numerator,
+            31
+        );
+
+        // the fee stays below the whole amount
+        let fees = DynamicFees {
+            base: Fees::default().with_trade_fee_bps(6_000),
+            imbalance_multiplier_bps: u16::MAX,
+        };
+        let one_sided = fees.effective_fees(u128::MAX, 0);
+        assert_eq!(one_sided.trade_fee_numerator, 9_999);
+        assert_eq!(one_sided.validate(), Ok(()));
+
+        // no trade fee to scale
+        let fees = DynamicFees {
+            base: Fees::default(),
+            imbalance_multiplier_bps: 10_000,
+        };
+        assert_eq!(fees.effective_fees(1_000_000, 0), Fees::default());
+    }
+
+    #[test]
+    fn unpack_from_swap_state() {
+        use anchor_lang::{prelude::Pubkey, AccountSerialize};
+
+        for owner_fee_direction in [OwnerFeeDirection::All, OwnerFeeDirection::AtoB] {
+            let state = SwapState {
+                is_initialized: true,
+                pool_mint: Pubkey::new_unique(),
+                fees: Fees {
+                    owner_fee_direction,
+                    ..Fees::default()
+                        .with_trade_fee_bps(25)
+                        .with_owner_trade_fee_bps(5)
+                        .with_owner_withdraw_fee_bps(10)
+                        .with_host_fee_bps(2_000)
+                },
+                curve_type: CurveType::Stable,
+                token_a_reserve: u64::MAX,
+                ..SwapState::default()
+            };
+            let mut data = vec![];
+            state.try_serialize(&mut data).unwrap();
+            assert_eq!(Fees::unpack_from_swap_state(&data), Ok(state.fees));
+
+            assert_eq!(
+                Fees::unpack_from_swap_state(&data[..SwapState::FEES_OFFSET + 8]),
+                Err(SwapError::InvalidInput)
+            );
+            assert_eq!(
+                Fees::unpack_from_swap_state(&data[8..]),
+                Err(SwapError::InvalidInput)
+            );
+        }
+    }
+
+    #[test]
+    fn bps_builder() {
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5)
+            .with_owner_withdraw_fee_bps(10)
+            .with_host_fee_bps(2_000);
+        assert_eq!(fees.validate(), Ok(()));
+        assert_eq!(
+            fees,
+            Fees {
+                trade_fee_numerator: 25,
+                trade_fee_denominator: 10_000,
+                owner_trade_fee_numerator: 5,
+                owner_trade_fee_denominator: 10_000,
+                owner_withdraw_fee_numerator: 10,
+                owner_withdraw_fee_denominator: 10_000,
+                host_fee_numerator: 2_000,
+                host_fee_denominator: 10_000,
+                owner_fee_direction: OwnerFeeDirection::All,
+            }
+        );
+        assert_eq!(fees.total_fee_numerator_denominator(), (30, 10_000));
+
+        // a fee of 100% or more is still rejected
+        assert_eq!(
+            fees.with_trade_fee_bps(BPS_DENOMINATOR).validate(),
+            Err(SwapError::InvalidFee)
+        );
+    }
+
+    #[test]
+    fn recommended_for() {
+        for curve_type in [
+            CurveType::ConstantProduct,
+            CurveType::ConstantPrice,
+            CurveType::Stable,
+            CurveType::Offset,
+            CurveType::Concentrated,
+        ] {
+            let fees = Fees::recommended_for(curve_type);
+            assert_eq!(fees.validate(), Ok(()));
+            assert!(fees.trade_fee_numerator > 0);
+        }
+        assert_eq!(
+            Fees::recommended_for(CurveType::ConstantProduct).trade_fee_numerator,
+            30
+        );
+        assert_eq!(
+            Fees::recommended_for(CurveType::Stable).trade_fee_numerator,
+            4
+        );
+    }
+
+    #[test]
+    fn total_fee_numerator_denominator() {
+        let fees = Fees {
+            trade_fee_numerator: 30,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 5,
+            owner_trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        assert_eq!(fees.total_fee_numerator_denominator(), (35, 10_000));
+
+        // 0.3% and 0.05% over different denominators is still 35bps
+        let fees = Fees {
+            trade_fee_numerator: 3,
+            trade_fee_denominator: 1_000,
+            owner_trade_fee_numerator: 5,
+            owner_trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        let (numerator, denominator) = fees.total_fee_numerator_denominator();
+        assert_eq!(numerator * 10_000, 35 * denominator);
+
+        let fees = Fees {
+            owner_trade_fee_numerator: 5,
+            owner_trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        assert_eq!(fees.total_fee_numerator_denominator(), (5, 10_000));
+        assert_eq!(Fees::default().total_fee_numerator_denominator(), (0, 1));
+    }
+
+    #[test]
+    fn effective_fee_bps_for_amount() {
+        let fees = Fees::default().with_trade_fee_bps(30);
+        // the minimum fee of one token is a full percent of 100 tokens
+        assert_eq!(super::effective_fee_bps_for_amount(&fees, 100), 100);
+        assert_eq!(super::effective_fee_bps_for_amount(&fees, 10), 1_000);
+        assert_eq!(super::effective_fee_bps_for_amount(&fees, 1), 10_000);
+        // large enough amounts pay the configured rate
+        assert_eq!(super::effective_fee_bps_for_amount(&fees, 1_000_000), 30);
+
+        // each enabled fee charges its own minimum
+        let fees = fees.with_owner_trade_fee_bps(5);
+        assert_eq!(super::effective_fee_bps_for_amount(&fees, 100), 200);
+        assert_eq!(super::effective_fee_bps_for_amount(&fees, 1_000_000), 35);
+
+        assert_eq!(super::effective_fee_bps_for_amount(&fees, 0), 0);
+        assert_eq!(
+            super::effective_fee_bps_for_amount(&Fees::default(), 100),
+            0
+        );
+    }
+
+    #[test]
+    fn validate_fraction() {
+        assert_eq!(super::validate_fraction(0, 0), Ok(()));
+        assert_eq!(super::validate_fraction(1, 0), Err(SwapError::InvalidFee));
+        assert_eq!(super::validate_fraction(0, 100), Ok(()));
+        assert_eq!(super::validate_fraction(1, 100), Ok(()));
+        assert_eq!(
+            super::validate_fraction(100, 100),
+            Err(SwapError::InvalidFee)
+        );
+        assert_eq!(
+            super::validate_fraction(101, 100),
+            Err(SwapError::InvalidFee)
+        );
+    }
+
+    #[test]
+    fn owner_fees_enabled() {
+        assert!(!Fees::default().owner_fees_enabled());
+        assert!(!Fees::default().with_trade_fee_bps(30).owner_fees_enabled());
+        assert!(Fees::default()
+            .with_owner_trade_fee_bps(5)
+            .owner_fees_enabled());
+    }
+
+    #[test]
+    fn charges_owner_fee() {
+        let fees = Fees::default();
+        assert!(fees.charges_owner_fee(TradeDirection::AtoB));
+        assert!(fees.charges_owner_fee(TradeDirection::BtoA));
+
+        let fees = Fees {
+            owner_fee_direction: OwnerFeeDirection::AtoB,
+            ..Fees::default()
+        };
+        assert!(fees.charges_owner_fee(TradeDirection::AtoB));
+        assert!(!fees.charges_owner_fee(TradeDirection::BtoA));
+    }
+
+    #[test]
+    fn pre_trading_fee_amount() {
+        let fees = Fees::default();
+        assert_eq!(fees.pre_trading_fee_amount(100), Some(100));
+
+        let fees = Fees {
+            trade_fee_numerator: 25,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 5,
+            owner_trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        for post_fee_amount in [1, 2, 100, 1_000_000] {
+            let amount = fees.pre_trading_fee_amount(post_fee_amount).unwrap();
+            let fee = fees.trading_fee(amount).unwrap() + fees.owner_trading_fee(amount).unwrap();
+            assert!(amount - fee >= post_fee_amount);
+        }
+        // each fee is at least one token
+        assert_eq!(fees.pre_trading_fee_amount(1), Some(4));
+
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1,
+            ..Fees::default()
+        };
+        assert_eq!(fees.pre_trading_fee_amount(1), None);
+    }
+
+    #[test]
+    fn host_fee_saturating() {
+        let fees = Fees {
+            host_fee_numerator: 20,
+            host_fee_denominator: 100,
+            ..Fees::default()
+        };
+        assert_eq!(fees.host_fee_saturating(1_000), 200);
+        assert_eq!(fees.host_fee_saturating(1_000), fees.host_fee(1_000).unwrap());
+
+        // the intermediate product doesn't overflow
+        assert_eq!(fees.host_fee(u128::MAX), Some(u128::MAX / 5));
+
+        // overflow, only possible with a fee above the owner fee
+        let fees = Fees {
+            host_fee_numerator: 200,
+            host_fee_denominator: 100,
+            ..Fees::default()
+        };
+        assert_eq!(fees.host_fee(u128::MAX), None);
+        assert_eq!(fees.host_fee_saturating(u128::MAX), 0);
+
+        // zero denominator
+        let fees = Fees {
+            host_fee_numerator: 1,
+            host_fee_denominator: 0,
+            ..Fees::default()
+        };
+        assert_eq!(fees.host_fee(1_000), None);
+        assert_eq!(fees.host_fee_saturating(1_000), 0);
+    }
 }
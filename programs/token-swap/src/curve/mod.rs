@@ -1,11 +1,16 @@
+pub mod base;
 pub mod calculator;
+pub mod config;
+pub mod concentrated;
 pub mod constant_price;
 pub mod constant_product;
 pub mod fees;
 pub mod offset;
 pub mod stable;
 
+pub use base::*;
 pub use calculator::*;
+pub use concentrated::*;
 pub use constant_price::*;
 pub use constant_product::*;
 pub use fees::*;
@@ -2,7 +2,7 @@ use {
     crate::{
         curve::{
             calculator::{
-                CurveCalculator, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+                CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
                 TradingTokenResult,
             },
             constant_product::{
@@ -12,18 +12,34 @@ use {
         },
         errors::SwapError,
     },
+    anchor_lang::solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
     arrayref::{array_mut_ref, array_ref},
     spl_math::precise_number::PreciseNumber,
 };
 
 /// Offset curve, uses ConstantProduct under the hood, but adds an offset to 
 /// one side on swap calculations
+///
+/// Prefer `Offset::new`, which validates the offset, over building the
+/// struct directly.
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Offset {
     /// Amount to offset the token B liquidity account
     pub token_b_offset: u64,
 }
 
+impl Offset {
+    /// Create an offset curve, rejecting a zero offset
+    pub fn new(token_b_offset: u64) -> Result<Self, SwapError> {
+        let curve = Self { token_b_offset };
+        curve.validate()?;
+        Ok(curve)
+    }
+}
+
 impl CurveCalculator for Offset {
     /// Constant product swap ensures token a * (token b + offset) = constant
     /// This is quaranteed to work for all values such that:
@@ -31,7 +47,6 @@ impl CurveCalculator for Offset {
     /// -1 <= (swap_source_amount * (swap_destination_amount + token_b_offset)) <= u128::MAX
     /// If the offset and token B are both close to u64::MAX, there can be 
     /// overflow errors with the invariant.
-
     fn swap_without_fees(
         &self, 
         source_amount: u128, 
@@ -48,11 +63,18 @@ impl CurveCalculator for Offset {
             TradeDirection::AtoB => swap_destination_amount.checked_add(token_b_offset)?,
             TradeDirection::BtoA => swap_destination_amount,
         };
+        // Bail out early if the offset invariant can't be represented, rather
+        // than relying on the checks inside the constant product swap
+        swap_source_amount.checked_mul(swap_destination_amount)?;
         swap(source_amount, swap_source_amount, swap_destination_amount)
     }
 
-    /// The conversion for the offset curve needs to take into account the
-    /// offset
+    /// The conversion for the offset curve only uses the real reserves, since
+    /// the offset token B liquidity can never be paid out.
+    ///
+    /// As a guard against draining the real token B, a withdrawal, rounded
+    /// down, which would pay out more than the real token B reserve fails,
+    /// and any other conversion is capped at that reserve.
     fn pool_tokens_to_trading_tokens(
         &self, 
         pool_tokens: u128, 
@@ -61,38 +83,55 @@ impl CurveCalculator for Offset {
         swap_token_b_amount: u128, 
         round_direction: RoundDirection
     ) -> Option<TradingTokenResult> {
-        
-        let token_b_offset = self.token_b_offset as u128;
-        pool_tokens_to_trading_tokens(
+        let mut result = pool_tokens_to_trading_tokens(
             pool_tokens, 
             pool_token_supply, 
             swap_token_a_amount, 
             swap_token_b_amount, 
             round_direction
-        )
+        )?;
+        if result.token_b_amount > swap_token_b_amount {
+            match round_direction {
+                RoundDirection::Floor => return None,
+                RoundDirection::Ceiling => result.token_b_amount = swap_token_b_amount,
+            }
+        }
+        Some(result)
     }
 
-    /// Get the amount of pool tokens for the given amount of token A and B, 
-    /// taking into account the offset
+    /// Get the amount of pool tokens for a deposit of token A or B, priced
+    /// against the effective reserves, ie. including the offset.
+    ///
+    /// Outside deposits are disabled, see `allows_deposits`, but this is
+    /// still used to value the owner trading fee. A token B deposit is only a
+    /// small part of the effective token B liquidity, so it mints fewer pool
+    /// tokens than against the real reserve alone, which errs in favor of
+    /// the pool. Token A has no offset, so its deposits are unaffected.
     fn deposit_single_token_type(
-        &self, 
-        source_amount: u128, 
-        swap_token_a_amount: u128, 
-        swap_token_b_amount: u128, 
-        pool_supply: u128, 
-        trade_direction: TradeDirection
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
     ) -> Option<u128> {
-      let token_b_offset = self.token_b_offset as u128;
-      deposit_single_token_type(
-          source_amount, 
-          swap_token_a_amount, 
-          swap_token_b_amount, 
-          pool_supply, 
-          trade_direction,
-          RoundDirection::Floor,
-      )
+        let (swap_token_a_amount, swap_token_b_amount) =
+            self.effective_reserves(swap_token_a_amount, swap_token_b_amount);
+        deposit_single_token_type(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+            RoundDirection::Floor,
+        )
     }
 
+    /// Get the amount of pool tokens to burn for a withdrawal of token A or B.
+    ///
+    /// Unlike deposits, this uses the real reserves: pricing token B against
+    /// the effective reserve would burn fewer pool tokens for the same real
+    /// token B, letting withdrawals drain it cheaply.
     fn withdraw_single_token_type_exact_out(
         &self, 
         source_amount: u128, 
@@ -101,7 +140,6 @@ impl CurveCalculator for Offset {
         pool_supply: u128, 
         trade_direction: TradeDirection
     ) -> Option<u128> {
-        let token_b_offset = self.token_b_offset as u128;
         withdraw_single_token_type_exact_out(
             source_amount, 
             swap_token_a_amount, 
@@ -112,19 +150,34 @@ impl CurveCalculator for Offset {
         )
     }
 
+    fn clone_box(&self) -> Box<dyn CurveCalculator + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    /// A zero offset is rejected even though the curve then behaves exactly
+    /// like the constant product curve: pools without an offset should use
+    /// `CurveType::ConstantProduct`, which also allows deposits
     fn validate(&self) -> Result<(), SwapError> {
        if self.token_b_offset == 0 {
-            Err(SwapError::InvalidCurve.into())
+            Err(SwapError::InvalidCurve)
        } else {
             Ok(())
        }
     }
 
-    fn validate_supply(&self, token_a_amount: u64, token_b_amount: u64) -> Result<(), SwapError> {
-        if token_a_amount == 0 {
-            return Err(SwapError::EmptySupply);
-        }
-        Ok(())
+    /// The offset stands in for token B, so the pool may start without any
+    fn allows_empty_side(&self) -> Option<TradeDirection> {
+        Some(TradeDirection::AtoB)
+    }
+
+    /// Token A is priced on the constant product of the real reserve, which
+    /// never pays out its last token
+    fn minimum_initial_reserves(&self) -> (u64, u64) {
+        (2, 0)
     }
 
     /// Offset curves can cause arbitrage opportunities if outside users are
@@ -136,6 +189,30 @@ impl CurveCalculator for Offset {
         false
     }
 
+    /// The offset is virtual token B liquidity
+    fn effective_reserves(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> (u128, u128) {
+        (
+            swap_token_a_amount,
+            swap_token_b_amount.saturating_add(u128::from(self.token_b_offset)),
+        )
+    }
+
+    /// The spot price of the offset curve includes the offset on the token B
+    /// side
+    fn spot_price(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let token_b_offset = self.token_b_offset as u128;
+        PreciseNumber::new(swap_token_b_amount.checked_add(token_b_offset)?)?
+            .checked_div(&PreciseNumber::new(swap_token_a_amount)?)
+    }
+
     /// The normalized_value of the offset curve simply needs to add the offset to 
     /// the token B side before calculating
     fn normalized_value(
@@ -151,4 +228,376 @@ impl CurveCalculator for Offset {
     }
 }
 
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for Offset {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+
+impl Sealed for Offset {}
+
+impl Pack for Offset {
+    const LEN: usize = 8;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Offset, ProgramError> {
+        let token_b_offset = array_ref![input, 0, 8];
+        Ok(Self {
+            token_b_offset: u64::from_le_bytes(*token_b_offset),
+        })
+    }
+}
 
+impl DynPack for Offset {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let token_b_offset = array_mut_ref![output, 0, 8];
+        *token_b_offset = self.token_b_offset.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{
+        calculator::test::{check_swap_against_ideal, impl_curve_tests},
+        constant_product::ConstantProductCurve,
+    };
+    use proptest::prelude::*;
+
+    impl_curve_tests!(
+        Offset {
+            token_b_offset: 1_000_000_000
+        },
+        roundtrip_only
+    );
+
+    #[test]
+    fn deposit_single_token_type_uses_effective_reserves() {
+        let token_b_offset = 1_000_000;
+        let curve = Offset { token_b_offset };
+        let constant_product = ConstantProductCurve {};
+        let (swap_token_a_amount, swap_token_b_amount, pool_supply) =
+            (1_000_000, 10_000, 1_000_000);
+
+        // token A has no offset
+        assert_eq!(
+            curve.deposit_single_token_type(
+                10_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            ),
+            constant_product.deposit_single_token_type(
+                10_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            )
+        );
+
+        // token B is priced against the real reserve plus the offset
+        let offset_pool_tokens = curve
+            .deposit_single_token_type(
+                10_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(
+            Some(offset_pool_tokens),
+            constant_product.deposit_single_token_type(
+                10_000,
+                swap_token_a_amount,
+                swap_token_b_amount + token_b_offset as u128,
+                pool_supply,
+                TradeDirection::BtoA,
+            )
+        );
+        let real_pool_tokens = constant_product
+            .deposit_single_token_type(
+                10_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert!(offset_pool_tokens < real_pool_tokens);
+    }
+
+    #[test]
+    fn effective_reserves() {
+        let curve = Offset { token_b_offset: 1_000 };
+        assert_eq!(curve.effective_reserves(500, 0), (500, 1_000));
+        assert_eq!(curve.effective_reserves(500, 200), (500, 1_200));
+        // the spot price is the ratio of the effective reserves
+        assert_eq!(
+            curve.spot_price(500, 200),
+            ConstantProductCurve {}.spot_price(500, 1_200)
+        );
+    }
+
+    #[test]
+    fn allows_empty_token_b() {
+        let curve = Offset { token_b_offset: 1_000 };
+        assert_eq!(curve.allows_empty_side(), Some(TradeDirection::AtoB));
+        assert_eq!(curve.validate_supply(1_000, 0), Ok(()));
+        assert_eq!(curve.validate_supply(0, 1_000), Err(SwapError::EmptySupply));
+    }
+
+    #[test]
+    fn minimum_initial_reserves() {
+        let curve = Offset { token_b_offset: 1_000 };
+        assert_eq!(curve.minimum_initial_reserves(), (2, 0));
+        assert_eq!(curve.validate_supply(1, 0), Err(SwapError::EmptySupply));
+        assert_eq!(curve.validate_supply(2, 0), Ok(()));
+
+        // the offset doesn't help buy the last token A
+        let swap = |swap_token_a_amount| {
+            curve
+                .swap_without_fees(1_000, 0, swap_token_a_amount, TradeDirection::BtoA)
+                .map(|result| result.destination_amount_swapped)
+        };
+        assert_eq!(swap(1), None);
+        assert_eq!(swap(2), Some(1));
+    }
+
+    #[test]
+    fn does_not_support_exact_out() {
+        let curve = Offset { token_b_offset: 1_000 };
+        assert!(!curve.supports_exact_out());
+        assert!(curve
+            .swap_without_fees_exact_out(10, 1_000, 1_000, TradeDirection::AtoB)
+            .is_none());
+    }
+
+    #[test]
+    fn new_validates_offset() {
+        assert_eq!(Offset::new(0), Err(SwapError::InvalidCurve));
+        assert_eq!(
+            Offset::new(1_000),
+            Ok(Offset {
+                token_b_offset: 1_000
+            })
+        );
+    }
+
+    #[test]
+    fn withdraw_cannot_drain_real_token_b() {
+        let curve = Offset {
+            token_b_offset: u64::MAX,
+        };
+        let swap_token_a_amount = 1_000;
+        let swap_token_b_amount = 10;
+        let pool_supply = 1_000;
+        // withdrawing the whole supply pays out only the real reserves
+        assert_eq!(
+            curve.pool_tokens_to_trading_tokens(
+                pool_supply,
+                pool_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                RoundDirection::Floor
+            ),
+            Some(TradingTokenResult {
+                token_a_amount: 1_000,
+                token_b_amount: 10,
+            })
+        );
+        // a withdrawal beyond the real token B reserve fails
+        assert_eq!(
+            curve.pool_tokens_to_trading_tokens(
+                2 * pool_supply,
+                pool_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                RoundDirection::Floor
+            ),
+            None
+        );
+        assert_eq!(
+            curve
+                .pool_tokens_to_trading_tokens(
+                    2 * pool_supply,
+                    pool_supply,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    RoundDirection::Ceiling
+                )
+                .unwrap()
+                .token_b_amount,
+            swap_token_b_amount
+        );
+    }
+
+    #[test]
+    fn swap_invariant_overflow() {
+        let curve = Offset {
+            token_b_offset: u64::MAX,
+        };
+        let swap_token_a_amount = u64::MAX as u128;
+        let swap_token_b_amount = u64::MAX as u128;
+        for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+            assert_eq!(
+                curve.swap_without_fees(
+                    1_000,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    trade_direction
+                ),
+                None
+            );
+        }
+
+        // the same reserves work without the offset pushing over the limit
+        let curve = Offset { token_b_offset: 1 };
+        assert!(curve
+            .swap_without_fees(
+                1_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB
+            )
+            .is_some());
+    }
+
+    proptest! {
+        #[test]
+        fn swap_bounded_by_ideal(
+            source_token_amount in 1..u64::MAX,
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u64::MAX,
+            token_b_offset in 1..u64::MAX,
+        ) {
+            let curve = Offset { token_b_offset };
+            let source_token_amount = source_token_amount as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+
+            // the swap can fail when calculating with the offset
+            prop_assume!(curve
+                .swap_without_fees(
+                    source_token_amount,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    TradeDirection::AtoB
+                )
+                .is_some());
+            check_swap_against_ideal(
+                &curve,
+                source_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            );
+            check_swap_against_ideal(
+                &curve,
+                source_token_amount,
+                swap_token_b_amount,
+                swap_token_a_amount,
+                TradeDirection::BtoA,
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn zero_offset_matches_constant_product(
+            source_token_amount in 1..u64::MAX,
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u64::MAX,
+            pool_supply in 1..u64::MAX,
+        ) {
+            // built directly, since `validate` rejects a zero offset
+            let offset = Offset { token_b_offset: 0 };
+            let constant_product = ConstantProductCurve {};
+            let source_token_amount = source_token_amount as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            let pool_supply = pool_supply as u128;
+
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                prop_assert_eq!(
+                    offset.swap_without_fees(
+                        source_token_amount,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        trade_direction
+                    ),
+                    constant_product.swap_without_fees(
+                        source_token_amount,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        trade_direction
+                    )
+                );
+                prop_assert_eq!(
+                    offset.deposit_single_token_type(
+                        source_token_amount,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        pool_supply,
+                        trade_direction
+                    ),
+                    constant_product.deposit_single_token_type(
+                        source_token_amount,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        pool_supply,
+                        trade_direction
+                    )
+                );
+                prop_assert_eq!(
+                    offset.withdraw_single_token_type_exact_out(
+                        source_token_amount,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        pool_supply,
+                        trade_direction
+                    ),
+                    constant_product.withdraw_single_token_type_exact_out(
+                        source_token_amount,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        pool_supply,
+                        trade_direction
+                    )
+                );
+            }
+            // the offset curve refuses to pay out more than the real reserves,
+            // so only conversions of at most the whole supply can match
+            let pool_tokens = std::cmp::min(source_token_amount, pool_supply);
+            for round_direction in [RoundDirection::Floor, RoundDirection::Ceiling] {
+                prop_assert_eq!(
+                    offset.pool_tokens_to_trading_tokens(
+                        pool_tokens,
+                        pool_supply,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        round_direction
+                    ),
+                    constant_product.pool_tokens_to_trading_tokens(
+                        pool_tokens,
+                        pool_supply,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        round_direction
+                    )
+                );
+            }
+            prop_assert_eq!(
+                offset.validate(),
+                Err(SwapError::InvalidCurve)
+            );
+            prop_assert_eq!(constant_product.validate(), Ok(()));
+        }
+    }
+}
@@ -2,8 +2,8 @@
 use {
     crate::{
         curve::calculator::{
-            CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
-            TradingTokenResult,
+            precise_to_u128, CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult,
+            TradeDirection, TradingTokenResult, TOKENS_IN_POOL,
         },
         errors::SwapError,
     },
@@ -12,13 +12,18 @@ use {
         program_pack::{IsInitialized, Pack, Sealed},
     },
     arrayref::{array_mut_ref, array_ref},
-    spl_math::{checked_ceil_div::CheckedCeilDiv, precise_number::PreciseNumber, uint::U256},
+    spl_math::{
+        precise_number::{PreciseNumber, ONE as PRECISE_ONE},
+        uint::U256,
+    },
     std::convert::TryFrom,
 };
 
-const N_COINS: u8 = 2;
-const N_COINS_SQUARED: u8 = 4;
-const ITERATIONS: u8 = 32;
+/// Number of tokens in the invariant, always the number of tokens in a pool
+const N_COINS: u8 = TOKENS_IN_POOL as u8;
+const N_COINS_SQUARED: u8 = N_COINS * N_COINS;
+const _: () = assert!(N_COINS as u128 == TOKENS_IN_POOL);
+pub(crate) const ITERATIONS: u8 = 32;
 
 /// Calculaous A for deriving D
 ///
@@ -33,6 +38,58 @@ fn compute_a(amp: u64) -> Option<u64> {
     amp.checked_mul(N_COINS as u64)
 }
 
+/// Maximum amplifier, as in the Curve and Saber stable swaps.
+///
+/// The largest product in the Newton iteration for D is
+/// `amp * N_COINS * sum_x * d`, which for reserves held in token accounts is
+/// below `2 ^ 20 * 2 ^ 65 * 2 ^ 66`, well within `U256`.
+pub const MAX_AMP: u64 = 1_000_000;
+
+/// Maximum factor by which a single ramp may raise or lower the amplifier
+pub const MAX_AMP_CHANGE: u64 = 10;
+
+/// Shortest ramp of the amplifier, one day in seconds, as in the Curve stable
+/// swap, so that a ramp can't be used to move the price within a few blocks
+pub const MIN_RAMP_DURATION: i64 = 86_400;
+
+/// Calculates the amplifier at `now` while ramping linearly from `start_amp`
+/// at `start_ts` to `target_amp` at `stop_ts`, holding the start and target
+/// amplifiers outside of the ramp
+pub fn compute_ramp_amp(
+    start_amp: u64,
+    target_amp: u64,
+    start_ts: i64,
+    stop_ts: i64,
+    now: i64,
+) -> Option<u64> {
+    if now >= stop_ts {
+        return Some(target_amp);
+    }
+    if now <= start_ts {
+        return Some(start_amp);
+    }
+    let elapsed = i128::from(now).checked_sub(i128::from(start_ts))?;
+    let duration = i128::from(stop_ts).checked_sub(i128::from(start_ts))?;
+    let change = i128::from(target_amp).checked_sub(i128::from(start_amp))?;
+    let amp =
+        i128::from(start_amp).checked_add(change.checked_mul(elapsed)?.checked_div(duration)?)?;
+    u64::try_from(amp).ok()
+}
+
+/// Check that a ramp from `current_amp` to `target_amp` stays within
+/// `MAX_AMP_CHANGE` in either direction, and within `MAX_AMP`
+pub fn validate_amp_change(current_amp: u64, target_amp: u64) -> Result<(), SwapError> {
+    if target_amp == 0 || target_amp > MAX_AMP {
+        return Err(SwapError::InvalidCurve);
+    }
+    let max_amp = current_amp.saturating_mul(MAX_AMP_CHANGE);
+    let min_amp = current_amp / MAX_AMP_CHANGE;
+    if target_amp > max_amp || target_amp < min_amp {
+        return Err(SwapError::InvalidCurve);
+    }
+    Ok(())
+}
+
 /// Returns self to the power of b
 fn checked_u8_power(a: &U256, b: u8) -> Option<U256> {
     let mut result = *a;
@@ -52,15 +109,88 @@ fn checked_u8_mul(a: &U256, b: u8) -> Option<U256> {
 }
 
 /// StableCurve struct implementing CurveCalculator
-
+///
+/// Prefer `StableCurve::new`, which validates the amplifier, over building
+/// the struct directly.
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct StableCurve {
     /// Amplifier constant
     pub amp: u64,
 }
 
+impl StableCurve {
+    /// Create a stable curve, rejecting an amplifier of zero or above
+    /// `MAX_AMP`
+    pub fn new(amp: u64) -> Result<Self, SwapError> {
+        let curve = Self { amp };
+        curve.validate()?;
+        Ok(curve)
+    }
+
+    /// Swap `source_amount` in `chunks` consecutive swaps of equal size, the
+    /// last one taking the remainder, each against the reserves left by the
+    /// previous one, and sum the results.
+    ///
+    /// Smaller swaps keep the Newton iteration of each one closer to its
+    /// starting point, but every swap rounds its output down by one token in
+    /// favor of the pool, so chunking trades up to `chunks` tokens of output
+    /// for that accuracy. The curve is symmetric, so the direction of the
+    /// trade doesn't matter. Returns `None` for zero chunks, or if any chunk
+    /// fails.
+    pub fn swap_chunked(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        chunks: u8,
+    ) -> Option<SwapWithoutFeesResult> {
+        let chunks = u128::from(chunks);
+        let chunk_amount = source_amount.checked_div(chunks)?;
+        let (mut swap_source_amount, mut swap_destination_amount) =
+            (swap_source_amount, swap_destination_amount);
+        let (mut source_amount_swapped, mut destination_amount_swapped) = (0u128, 0u128);
+        for chunk in 0..chunks {
+            let amount = if chunk == chunks - 1 {
+                source_amount.checked_sub(chunk_amount.checked_mul(chunks - 1)?)?
+            } else {
+                chunk_amount
+            };
+            if amount == 0 {
+                continue;
+            }
+            let result = self.swap_without_fees(
+                amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )?;
+            swap_source_amount = swap_source_amount.checked_add(result.source_amount_swapped)?;
+            source_amount_swapped =
+                source_amount_swapped.checked_add(result.source_amount_swapped)?;
+            swap_destination_amount =
+                swap_destination_amount.checked_sub(result.destination_amount_swapped)?;
+            destination_amount_swapped =
+                destination_amount_swapped.checked_add(result.destination_amount_swapped)?;
+        }
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        })
+    }
+}
+
 /// d = (leverage * sum_x + d_product * n_coins) * initial_d / ((leverage - 1) * initial_d + (n_coins + 1) * d_product)
 fn calculate_step(initial_d: &U256, leverage: u64, sum_x: u128, d_product: &U256) -> Option<U256> {
+    #[cfg(feature = "arithmetic-audit")]
+    debug_assert!(
+        U256::from(leverage)
+            .checked_mul(sum_x.into())
+            .zip(checked_u8_mul(d_product, N_COINS.checked_add(1)?))
+            .and_then(|(leverage_mul, d_p_mul)| leverage_mul.checked_add(d_p_mul))
+            .and_then(|l_val| l_val.checked_mul(*initial_d))
+            .is_some(),
+        "arithmetic audit: stable curve Newton step overflows U256"
+    );
     let leverage_mul = U256::from(leverage).checked_mul(sum_x.into())?;
     let d_p_mul = checked_u8_mul(d_product, N_COINS)?;
 
@@ -77,7 +207,18 @@ fn calculate_step(initial_d: &U256, leverage: u64, sum_x: u128, d_product: &U256
 /// Compute stable swap invariant (D)
 /// Equation
 /// A * sum(x_i) * n**n + D = A * D * n**n + D**(n + 1)  / (n**n * prod(x_i))
-fn compute_d(leverage: u64, amount_a: u128, amount_b: u128) -> Option<u128> {
+///
+/// `leverage` is the amplifier scaled by `compute_a`. Public so that the
+/// Newton iteration can be benchmarked on its own.
+pub fn compute_d(leverage: u64, amount_a: u128, amount_b: u128) -> Option<u128> {
+    // D is symmetric in the reserves, but the truncating divisions of the
+    // Newton iteration are not, so always divide in the same order
+    compute_d_in_order(leverage, amount_a.min(amount_b), amount_a.max(amount_b))
+}
+
+/// Newton iteration for D, dividing by `amount_a` before `amount_b`, see
+/// `compute_d`
+fn compute_d_in_order(leverage: u64, amount_a: u128, amount_b: u128) -> Option<u128> {
     let amount_a_times_coins =
         checked_u8_mul(&U256::from(amount_a), N_COINS)?.checked_add(U256::one())?;
     let amount_b_times_coins =
@@ -91,6 +232,13 @@ fn compute_d(leverage: u64, amount_a: u128, amount_b: u128) -> Option<u128> {
 
         // Newton's methos to approximate D
         for _ in 0..ITERATIONS {
+            #[cfg(feature = "arithmetic-audit")]
+            debug_assert!(
+                d.checked_mul(d)
+                    .and_then(|d_squared| d_squared.checked_mul(d))
+                    .is_some(),
+                "arithmetic audit: stable curve D product overflows U256"
+            );
             let mut d_product = d;
             d_product = d_product
                 .checked_mul(d)?
@@ -109,3 +257,637 @@ fn compute_d(leverage: u64, amount_a: u128, amount_b: u128) -> Option<u128> {
         u128::try_from(d).ok()
     }
 }
+
+/// Compute swap amount `y` in proportion to `x`
+/// Solve for y:
+/// y**2 + y * (sum' - (A*n**n - 1) * D / (A * n**n)) = D ** (n + 1) / (n ** (2 * n) * prod' * A)
+/// y**2 + b*y = c
+fn compute_new_destination_amount(
+    leverage: u64,
+    new_source_amount: u128,
+    d_val: u128,
+) -> Option<u128> {
+    // Upscale to U256
+    let leverage: U256 = leverage.into();
+    let new_source_amount: U256 = new_source_amount.into();
+    let d_val: U256 = d_val.into();
+
+    // sum' = prod' = x
+    // c =  D ** (n + 1) / (n ** (2 * n) * prod' * A)
+    let c = checked_u8_power(&d_val, N_COINS.checked_add(1)?)?
+        .checked_div(checked_u8_mul(&new_source_amount, N_COINS_SQUARED)?.checked_mul(leverage)?)?;
+
+    // b = sum' - (A*n**n - 1) * D / (A * n**n)
+    let b = new_source_amount.checked_add(d_val.checked_div(leverage)?)?;
+
+    // Solve for y by approximating: y**2 + b*y = c
+    let mut y_prev: U256;
+    let mut y = d_val;
+    for _ in 0..ITERATIONS {
+        y_prev = y;
+        y = (checked_u8_power(&y, 2)?.checked_add(c)?)
+            .checked_div(checked_u8_mul(&y, 2)?.checked_add(b)?.checked_sub(d_val)?)?;
+        if y == y_prev {
+            break;
+        }
+    }
+    u128::try_from(y).ok()
+}
+
+impl CurveCalculator for StableCurve {
+    /// Stable curve
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        _trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        if source_amount == 0 {
+            return Some(SwapWithoutFeesResult {
+                source_amount_swapped: 0,
+                destination_amount_swapped: 0,
+            });
+        }
+        let leverage = compute_a(self.amp)?;
+
+        let new_source_amount = swap_source_amount.checked_add(source_amount)?;
+        let new_destination_amount = compute_new_destination_amount(
+            leverage,
+            new_source_amount,
+            compute_d(leverage, swap_source_amount, swap_destination_amount)?,
+        )?;
+
+        // The approximation of the new destination amount can be off by one
+        // in either direction, so always round it up in favor of the pool,
+        // otherwise swapping back and forth could extract value.
+        let amount_swapped = swap_destination_amount
+            .checked_sub(new_destination_amount)?
+            .saturating_sub(1);
+
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped: amount_swapped,
+        })
+    }
+
+    /// Re-centering the curve is not necessary for withdrawals, so the
+    /// stable curve uses the same ratio calculation as constant product
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        let mut token_a_amount = pool_tokens
+            .checked_mul(swap_token_a_amount)?
+            .checked_div(pool_token_supply)?;
+        let mut token_b_amount = pool_tokens
+            .checked_mul(swap_token_b_amount)?
+            .checked_div(pool_token_supply)?;
+        let (token_a_amount, token_b_amount) = match round_direction {
+            RoundDirection::Floor => (token_a_amount, token_b_amount),
+            RoundDirection::Ceiling => {
+                let token_a_remainder = pool_tokens
+                    .checked_mul(swap_token_a_amount)?
+                    .checked_rem(pool_token_supply)?;
+                // Also check for 0 token A and B amount to avoid taking too much
+                // for tiny amounts of pool tokens. For example, if someone asks
+                // for 1 pool token, which is worth 0.01 token A, we avoid the
+                // ceiling of taking 1 token A and instead return 0, for it to be
+                // rejected later in processing.
+                if token_a_remainder > 0 && token_a_amount > 0 {
+                    token_a_amount += 1;
+                }
+                let token_b_remainder = pool_tokens
+                    .checked_mul(swap_token_b_amount)?
+                    .checked_rem(pool_token_supply)?;
+                if token_b_remainder > 0 && token_b_amount > 0 {
+                    token_b_amount += 1;
+                }
+                (token_a_amount, token_b_amount)
+            }
+        };
+        Some(TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        })
+    }
+
+    /// Get the amount of pool tokens for the given amount of token A or B.
+    ///
+    /// The pool tokens minted are proportional to the growth of the invariant
+    /// D after the deposit
+    fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let leverage = compute_a(self.amp)?;
+        let d0 = PreciseNumber::new(compute_d(
+            leverage,
+            swap_token_a_amount,
+            swap_token_b_amount,
+        )?)?;
+        let (deposit_token_amount, other_token_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_token_a_amount, swap_token_b_amount),
+            TradeDirection::BtoA => (swap_token_b_amount, swap_token_a_amount),
+        };
+        let updated_deposit_token_amount = deposit_token_amount.checked_add(source_amount)?;
+        let d1 = PreciseNumber::new(compute_d(
+            leverage,
+            updated_deposit_token_amount,
+            other_token_amount,
+        )?)?;
+        let diff = d1.checked_sub(&d0)?;
+        let final_amount =
+            (diff.checked_mul(&PreciseNumber::new(pool_supply)?))?.checked_div(&d0)?;
+        precise_to_u128(&final_amount.floor()?)
+    }
+
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let leverage = compute_a(self.amp)?;
+        let d0 = PreciseNumber::new(compute_d(
+            leverage,
+            swap_token_a_amount,
+            swap_token_b_amount,
+        )?)?;
+        let (withdraw_token_amount, other_token_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_token_a_amount, swap_token_b_amount),
+            TradeDirection::BtoA => (swap_token_b_amount, swap_token_a_amount),
+        };
+        let updated_withdraw_token_amount = withdraw_token_amount.checked_sub(source_amount)?;
+        let d1 = PreciseNumber::new(compute_d(
+            leverage,
+            updated_withdraw_token_amount,
+            other_token_amount,
+        )?)?;
+        let diff = d0.checked_sub(&d1)?;
+        let final_amount =
+            (diff.checked_mul(&PreciseNumber::new(pool_supply)?))?.checked_div(&d0)?;
+        precise_to_u128(&final_amount.ceiling()?)
+    }
+
+    /// The spot price is the ratio of the partial derivatives of the invariant
+    /// `F(x, y) = A * n**n * (x + y) + D - A * D * n**n - D**3 / (n**n * x * y)`,
+    /// which after multiplying both by `n**n * x * y` becomes:
+    /// `(A * n**n * n**n * x * y + D**3 / x) / (A * n**n * n**n * x * y + D**3 / y)`
+    fn spot_price(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let leverage = compute_a(self.amp)?;
+        let d_val = U256::from(compute_d(
+            leverage,
+            swap_token_a_amount,
+            swap_token_b_amount,
+        )?);
+        let d_cubed = checked_u8_power(&d_val, N_COINS.checked_add(1)?)?;
+        let amount_a = U256::from(swap_token_a_amount);
+        let amount_b = U256::from(swap_token_b_amount);
+        let leverage_term = checked_u8_mul(
+            &U256::from(leverage)
+                .checked_mul(amount_a)?
+                .checked_mul(amount_b)?,
+            N_COINS_SQUARED,
+        )?;
+        let numerator = leverage_term.checked_add(d_cubed.checked_div(amount_a)?)?;
+        let denominator = leverage_term.checked_add(d_cubed.checked_div(amount_b)?)?;
+        Some(PreciseNumber {
+            value: numerator
+                .checked_mul(U256::from(PRECISE_ONE))?
+                .checked_div(denominator)?,
+        })
+    }
+
+    /// The invariant D already has the dimension `tokens ^ 1`, so it can be
+    /// used directly as the normalized value
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let leverage = compute_a(self.amp)?;
+        PreciseNumber::new(compute_d(
+            leverage,
+            swap_token_a_amount,
+            swap_token_b_amount,
+        )?)
+    }
+
+    fn clone_box(&self) -> Box<dyn CurveCalculator + Sync + Send> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.amp == 0 || self.amp > MAX_AMP {
+            Err(SwapError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Swaps pay out one token less than the approximated invariant, so a
+    /// pool needs at least two of each token to pay out anything
+    fn minimum_initial_reserves(&self) -> (u64, u64) {
+        (2, 2)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for StableCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+
+impl Sealed for StableCurve {}
+
+impl Pack for StableCurve {
+    const LEN: usize = 8;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<StableCurve, ProgramError> {
+        let amp = array_ref![input, 0, 8];
+        Ok(Self {
+            amp: u64::from_le_bytes(*amp),
+        })
+    }
+}
+
+impl DynPack for StableCurve {
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let amp = array_mut_ref![dst, 0, 8];
+        *amp = self.amp.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::calculator::{
+        test::{check_max_output, check_swap_roundtrip},
+        INITIAL_SWAP_POOL_AMOUNT,
+    };
+    use proptest::prelude::*;
+
+    #[test]
+    fn ramp_amp() {
+        let (start_ts, stop_ts) = (1_000, 2_000);
+        // ramping up
+        for (now, expected) in [
+            (0, 100),
+            (1_000, 100),
+            (1_001, 100),
+            (1_250, 325),
+            (1_500, 550),
+            (1_999, 999),
+            (2_000, 1_000),
+            (5_000, 1_000),
+        ] {
+            assert_eq!(
+                compute_ramp_amp(100, 1_000, start_ts, stop_ts, now),
+                Some(expected)
+            );
+        }
+        // ramping down
+        for (now, expected) in [(1_000, 1_000), (1_250, 775), (1_500, 550), (2_000, 100)] {
+            assert_eq!(
+                compute_ramp_amp(1_000, 100, start_ts, stop_ts, now),
+                Some(expected)
+            );
+        }
+        // an immediate change
+        assert_eq!(compute_ramp_amp(100, 200, 1_000, 1_000, 1_000), Some(200));
+    }
+
+    #[test]
+    fn amp_change_limits() {
+        assert_eq!(validate_amp_change(100, 1_000), Ok(()));
+        assert_eq!(validate_amp_change(100, 10), Ok(()));
+        assert_eq!(
+            validate_amp_change(100, 1_001),
+            Err(SwapError::InvalidCurve)
+        );
+        assert_eq!(validate_amp_change(100, 9), Err(SwapError::InvalidCurve));
+        assert_eq!(validate_amp_change(100, 0), Err(SwapError::InvalidCurve));
+    }
+
+    #[test]
+    fn requires_both_sides() {
+        let curve = StableCurve { amp: 100 };
+        assert_eq!(curve.allows_empty_side(), None);
+        assert_eq!(curve.validate_supply(1_000, 0), Err(SwapError::EmptySupply));
+        assert_eq!(curve.validate_supply(0, 1_000), Err(SwapError::EmptySupply));
+    }
+
+    #[test]
+    fn minimum_initial_reserves() {
+        let curve = StableCurve { amp: 100 };
+        assert_eq!(curve.minimum_initial_reserves(), (2, 2));
+        assert_eq!(curve.validate_supply(1, 1_000), Err(SwapError::EmptySupply));
+        assert_eq!(curve.validate_supply(1_000, 1), Err(SwapError::EmptySupply));
+        assert_eq!(curve.validate_supply(2, 2), Ok(()));
+
+        let swap = |swap_destination_amount| {
+            curve
+                .swap_without_fees(1_000, 2, swap_destination_amount, TradeDirection::AtoB)
+                .unwrap()
+                .destination_amount_swapped
+        };
+        assert_eq!(swap(1), 0);
+        assert_eq!(swap(2), 1);
+    }
+
+    #[test]
+    fn n_coins_matches_tokens_in_pool() {
+        assert_eq!(u128::from(N_COINS), TOKENS_IN_POOL);
+        assert_eq!(N_COINS_SQUARED, 4);
+    }
+
+    #[test]
+    fn does_not_support_exact_out() {
+        let curve = StableCurve { amp: 100 };
+        assert!(!curve.supports_exact_out());
+        assert!(curve
+            .swap_without_fees_exact_out(10, 1_000, 1_000, TradeDirection::AtoB)
+            .is_none());
+    }
+
+    #[test]
+    fn initial_pool_amount() {
+        let amp = 1;
+        let calculator = StableCurve { amp };
+        assert_eq!(calculator.new_pool_supply(), INITIAL_SWAP_POOL_AMOUNT);
+    }
+
+    #[test]
+    fn pack_curve() {
+        let amp = 1;
+        let curve = StableCurve { amp };
+
+        let mut packed = [0u8; StableCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = StableCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&amp.to_le_bytes());
+        let unpacked = StableCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    #[test]
+    fn swap_balanced_pool_is_near_one_to_one() {
+        let curve = StableCurve { amp: 100 };
+        let swap_amount = 1_000_000;
+        let source_amount = 1_000;
+        let result = curve
+            .swap_without_fees(
+                source_amount,
+                swap_amount,
+                swap_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, source_amount);
+        assert!(result.destination_amount_swapped <= source_amount);
+        assert!(result.destination_amount_swapped >= source_amount * 99 / 100);
+    }
+
+    #[test]
+    fn spot_price() {
+        let curve = StableCurve { amp: 100 };
+        assert_eq!(curve.spot_price(1_000_000, 1_000_000), PreciseNumber::new(1));
+
+        // token A is cheaper when there is more of it in the pool, but the
+        // amplification keeps the price much closer to 1 than constant product
+        let price = curve.spot_price_decimal(2_000_000, 1_000_000, 6).unwrap();
+        assert!(price < 1_000_000);
+        assert!(price > 500_000);
+        let price = curve.spot_price_decimal(1_000_000, 2_000_000, 6).unwrap();
+        assert!(price > 1_000_000);
+        assert!(price < 2_000_000);
+
+        // a higher amplification flattens the curve further
+        let flatter_curve = StableCurve { amp: 1_000 };
+        assert!(
+            flatter_curve.spot_price_decimal(2_000_000, 1_000_000, 6).unwrap()
+                > curve.spot_price_decimal(2_000_000, 1_000_000, 6).unwrap()
+        );
+    }
+
+    #[test]
+    fn new_validates_amp() {
+        assert_eq!(StableCurve::new(0), Err(SwapError::InvalidCurve));
+        assert_eq!(StableCurve::new(100), Ok(StableCurve { amp: 100 }));
+    }
+
+    #[test]
+    fn validate_rejects_zero_amp() {
+        let curve = StableCurve { amp: 0 };
+        assert_eq!(curve.validate(), Err(SwapError::InvalidCurve));
+        let curve = StableCurve { amp: 1 };
+        assert_eq!(curve.validate(), Ok(()));
+    }
+
+    #[test]
+    fn swap_chunked() {
+        let curve = StableCurve { amp: 100 };
+        let (swap_source_amount, swap_destination_amount) = (1_000_000_000_000, 1_000_000_000_000);
+        let source_amount = 500_000_000_000;
+        let single = curve
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        for chunks in [1, 2, 10, 255] {
+            let chunked = curve
+                .swap_chunked(
+                    source_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    chunks,
+                )
+                .unwrap();
+            assert_eq!(chunked.source_amount_swapped, source_amount);
+            // each chunk rounds its output down by up to one token
+            let difference = single
+                .destination_amount_swapped
+                .abs_diff(chunked.destination_amount_swapped);
+            assert!(
+                difference <= u128::from(chunks),
+                "{} chunks: {}",
+                chunks,
+                difference
+            );
+        }
+
+        // chunks smaller than a token are skipped
+        let chunked = curve
+            .swap_chunked(3, swap_source_amount, swap_destination_amount, 10)
+            .unwrap();
+        assert_eq!(chunked.source_amount_swapped, 3);
+        assert!(curve
+            .swap_chunked(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                0
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn arithmetic_audit_representative_inputs() {
+        // only checks anything with the `arithmetic-audit` feature in debug
+        for amp in [1, 100, MAX_AMP] {
+            let curve = StableCurve { amp };
+            for (swap_source_amount, swap_destination_amount) in [
+                (1_000, 1_000),
+                (1_000_000_000, 1),
+                (u64::MAX as u128, u64::MAX as u128),
+            ] {
+                curve.swap_without_fees(
+                    1_000,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    TradeDirection::AtoB,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn downcast_ref() {
+        fn amp_of(curve: &dyn CurveCalculator) -> Option<u64> {
+            curve.downcast_ref::<StableCurve>().map(|curve| curve.amp)
+        }
+        let curve: Box<dyn CurveCalculator> = Box::new(StableCurve { amp: 85 });
+        assert_eq!(amp_of(curve.as_ref()), Some(85));
+        let curve: Box<dyn CurveCalculator> =
+            Box::new(crate::curve::constant_product::ConstantProductCurve {});
+        assert_eq!(amp_of(curve.as_ref()), None);
+
+        let curve: Box<dyn CurveCalculator + Sync + Send> = Box::new(StableCurve { amp: 85 });
+        assert_eq!(
+            curve.downcast_ref::<StableCurve>(),
+            Some(&StableCurve { amp: 85 })
+        );
+    }
+
+    #[test]
+    fn validate_max_amp() {
+        let curve = StableCurve { amp: MAX_AMP };
+        assert_eq!(curve.validate(), Ok(()));
+        let curve = StableCurve { amp: MAX_AMP + 1 };
+        assert_eq!(curve.validate(), Err(SwapError::InvalidCurve));
+        assert_eq!(StableCurve::new(MAX_AMP + 1), Err(SwapError::InvalidCurve));
+
+        assert_eq!(validate_amp_change(MAX_AMP / 2, MAX_AMP), Ok(()));
+        assert_eq!(
+            validate_amp_change(MAX_AMP / 2, MAX_AMP + 1),
+            Err(SwapError::InvalidCurve)
+        );
+
+        // the invariant is still computed for the largest reserves
+        let leverage = compute_a(MAX_AMP).unwrap();
+        let reserve = u64::MAX as u128;
+        assert!(compute_d(leverage, reserve, reserve).is_some());
+        assert!(compute_d(leverage, reserve, 1).is_some());
+    }
+
+    proptest! {
+        #[test]
+        fn swap_roundtrip_does_not_profit(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            amp in 1..1_000u64,
+        ) {
+            let curve = StableCurve { amp };
+            check_swap_roundtrip(
+                &curve,
+                source_token_amount as u128,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                TradeDirection::AtoB
+            );
+            check_swap_roundtrip(
+                &curve,
+                source_token_amount as u128,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                TradeDirection::BtoA
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn swap_does_not_exceed_max_output(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            amp in 1..1_000u64,
+        ) {
+            let curve = StableCurve { amp };
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_max_output(
+                    &curve,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    trade_direction,
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn compute_d_order_only_rounds(
+            amount_a in 0..u64::MAX,
+            amount_b in 0..u64::MAX,
+            amp in 1..MAX_AMP,
+        ) {
+            // dividing in the other order only changes the rounding of D
+            let leverage = compute_a(amp).unwrap();
+            let (amount_a, amount_b) = (u128::from(amount_a), u128::from(amount_b));
+            let d = compute_d(leverage, amount_a, amount_b);
+            let d_reversed =
+                compute_d_in_order(leverage, amount_a.max(amount_b), amount_a.min(amount_b));
+            prop_assume!(d.is_some() && d_reversed.is_some());
+            prop_assert!(d.unwrap().abs_diff(d_reversed.unwrap()) <= 1);
+        }
+    }
+}
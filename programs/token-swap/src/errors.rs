@@ -124,18 +124,92 @@ pub enum SwapError {
     /// The operation cannot be performed on the given curve
     #[error("The operation cannot be performed on the given curve")]
     UnsupportedCurveOperation,
+
+    /// The packed curve parameters could not be decoded into a valid curve
+    #[error("The packed curve parameters could not be decoded into a valid curve")]
+    InvalidCurveParameters,
+
+    /// A calculation divided by zero
+    #[error("Calculation failed on a division by zero")]
+    DivisionByZero,
+
+    // 30.
+    /// A square root calculation did not converge
+    #[error("Square root calculation failed")]
+    SquareRootFailure,
 }
 
+/// Each error is returned as `ProgramError::Custom` with its position in
+/// `SwapError` as the code, matching SPL token-swap, so that non-Anchor
+/// clients can decode it. The codes are part of the program's interface: new
+/// errors must be appended, never inserted or reordered.
 impl From<SwapError> for ProgramError {
     fn from(e: SwapError) -> Self {
         ProgramError::Custom(e as u32)
     }
 }
 
+impl From<SwapError> for anchor_lang::error::Error {
+    fn from(e: SwapError) -> Self {
+        ProgramError::from(e).into()
+    }
+}
+
 impl<T> DecodeError<T> for SwapError {
     fn type_of() -> &'static str {
         "Swap Error"
     }
 }
 
-
+#[cfg(test)]
+mod tests {
+    use {super::*, num_traits::FromPrimitive};
+
+    #[test]
+    fn stable_error_codes() {
+        let codes = [
+            (SwapError::AlreadyInUse, 0),
+            (SwapError::InvalidProgramAddress, 1),
+            (SwapError::InvalidOwner, 2),
+            (SwapError::InvalidOutputOwner, 3),
+            (SwapError::ExpectedMint, 4),
+            (SwapError::ExpectedAccount, 5),
+            (SwapError::EmptySupply, 6),
+            (SwapError::InvalidSupply, 7),
+            (SwapError::InvalidDelegate, 8),
+            (SwapError::InvalidInput, 9),
+            (SwapError::IncorrectSwapAccount, 10),
+            (SwapError::IncorrectPoolMint, 11),
+            (SwapError::InvalidOutput, 12),
+            (SwapError::CalculationFailure, 13),
+            (SwapError::InvalidInstruction, 14),
+            (SwapError::RepeatedMint, 15),
+            (SwapError::ExceededSlippage, 16),
+            (SwapError::InvalidCloseAuthority, 17),
+            (SwapError::InvalidFreezeAuthority, 18),
+            (SwapError::IncorrectFeeAccount, 19),
+            (SwapError::ZeroTradingTokens, 20),
+            (SwapError::FeeCalculationFailure, 21),
+            (SwapError::CoversionFailure, 22),
+            (SwapError::InvalidFee, 23),
+            (SwapError::IncorrectTokenProgramId, 24),
+            (SwapError::UnsupportedCurveType, 25),
+            (SwapError::InvalidCurve, 26),
+            (SwapError::UnsupportedCurveOperation, 27),
+            (SwapError::InvalidCurveParameters, 28),
+            (SwapError::DivisionByZero, 29),
+            (SwapError::SquareRootFailure, 30),
+        ];
+        for (i, (error, code)) in codes.iter().enumerate() {
+            assert_eq!(
+                ProgramError::from(error.clone()),
+                ProgramError::Custom(*code)
+            );
+            assert_eq!(SwapError::from_u32(*code).as_ref(), Some(error));
+            // every code is unique
+            assert!(codes[..i].iter().all(|(_, other)| other != code));
+        }
+        // every error is listed
+        assert_eq!(SwapError::from_u32(codes.len() as u32), None);
+    }
+}
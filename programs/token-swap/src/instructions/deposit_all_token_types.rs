@@ -0,0 +1,184 @@
+//! Deposit both types of tokens into the pool
+
+use {
+    crate::{
+        curve::calculator::RoundDirection,
+        errors::SwapError,
+        instructions::{to_u64, token_mint_to, token_transfer},
+        state::SwapState,
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::{Mint, Token, TokenAccount},
+};
+
+#[derive(Accounts)]
+pub struct DepositAllTokenTypes<'info> {
+    #[account(mut)]
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: program derived authority over the pool's token accounts and
+    /// pool mint, never read
+    #[account(seeds = [swap.key().as_ref()], bump = swap.bump_seed)]
+    pub authority: UncheckedAccount<'info>,
+    /// Owner or delegate of both source accounts
+    pub user_transfer_authority: Signer<'info>,
+    /// User's token A account
+    #[account(mut)]
+    pub source_a: Account<'info, TokenAccount>,
+    /// User's token B account
+    #[account(mut)]
+    pub source_b: Account<'info, TokenAccount>,
+    /// Pool token A account
+    #[account(mut)]
+    pub token_a: Account<'info, TokenAccount>,
+    /// Pool token B account
+    #[account(mut)]
+    pub token_b: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub pool_mint: Account<'info, Mint>,
+    /// User's pool token account receiving the new pool tokens
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> DepositAllTokenTypes<'info> {
+    /// Check the given accounts against the pool's state
+    pub fn validate(&self) -> Result<()> {
+        let swap = &self.swap;
+        if self.token_a.key() != swap.token_a || self.token_b.key() != swap.token_b {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if self.source_a.key() == self.token_a.key() || self.source_b.key() == self.token_b.key() {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if self.pool_mint.key() != swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.token_program.key() != swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        Ok(())
+    }
+}
+
+pub fn process_deposit_all_token_types(
+    ctx: Context<DepositAllTokenTypes>,
+    pool_token_amount: u64,
+    maximum_token_a_amount: u64,
+    maximum_token_b_amount: u64,
+) -> Result<()> {
+    ctx.accounts.validate()?;
+    let now = Clock::get()?.unix_timestamp;
+    let swap_curve = ctx.accounts.swap.swap_curve_at(now)?;
+    if !swap_curve.calculator.allows_deposits() {
+        return Err(SwapError::UnsupportedCurveOperation.into());
+    }
+    ctx.accounts.swap.lock()?;
+    ctx.accounts.swap.exit(ctx.program_id)?;
+
+    let results = swap_curve.pool_tokens_to_trading_tokens(
+        u128::from(pool_token_amount),
+        u128::from(ctx.accounts.pool_mint.supply),
+        u128::from(ctx.accounts.token_a.amount),
+        u128::from(ctx.accounts.token_b.amount),
+        RoundDirection::Ceiling,
+    )?;
+    let token_a_amount = to_u64(results.token_a_amount)?;
+    if token_a_amount > maximum_token_a_amount {
+        return Err(SwapError::ExceededSlippage.into());
+    }
+    if token_a_amount == 0 {
+        return Err(SwapError::ZeroTradingTokens.into());
+    }
+    let token_b_amount = to_u64(results.token_b_amount)?;
+    if token_b_amount > maximum_token_b_amount {
+        return Err(SwapError::ExceededSlippage.into());
+    }
+    if token_b_amount == 0 {
+        return Err(SwapError::ZeroTradingTokens.into());
+    }
+
+    let swap = &ctx.accounts.swap;
+    let swap_key = swap.key();
+    let seeds: &[&[u8]] = &[swap_key.as_ref(), &[swap.bump_seed]];
+    let token_program = ctx.accounts.token_program.to_account_info();
+    let user_transfer_authority = ctx.accounts.user_transfer_authority.to_account_info();
+
+    let balances_before = (ctx.accounts.token_a.amount, ctx.accounts.token_b.amount);
+    token_transfer(
+        token_program.clone(),
+        ctx.accounts.source_a.to_account_info(),
+        ctx.accounts.token_a.to_account_info(),
+        user_transfer_authority.clone(),
+        &[],
+        token_a_amount,
+    )?;
+    token_transfer(
+        token_program.clone(),
+        ctx.accounts.source_b.to_account_info(),
+        ctx.accounts.token_b.to_account_info(),
+        user_transfer_authority,
+        &[],
+        token_b_amount,
+    )?;
+    token_mint_to(
+        token_program,
+        ctx.accounts.pool_mint.to_account_info(),
+        ctx.accounts.destination.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        &[seeds],
+        pool_token_amount,
+    )?;
+
+    ctx.accounts.token_a.reload()?;
+    ctx.accounts.token_b.reload()?;
+    let balances_after = (ctx.accounts.token_a.amount, ctx.accounts.token_b.amount);
+    ctx.accounts.swap.update_price_oracle(&swap_curve, now);
+    ctx.accounts.swap.apply_deposit(
+        token_a_amount,
+        token_b_amount,
+        balances_before,
+        balances_after,
+    )?;
+    ctx.accounts.swap.unlock();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            curve::fees::Fees,
+            instructions::test::{process, TestPool},
+        },
+    };
+
+    #[test]
+    fn deposit_all_token_types_reentrancy_guard() {
+        let mut pool = TestPool::new(Fees::default().with_trade_fee_bps(25));
+        let deposit_all_token_types = |pool: &mut TestPool| {
+            let account_infos = [
+                pool.swap.info(),
+                pool.authority.info(),
+                pool.user.info(),
+                pool.user_token_a.info(),
+                pool.user_token_b.info(),
+                pool.token_a.info(),
+                pool.token_b.info(),
+                pool.pool_mint.info(),
+                pool.user_pool_token.info(),
+                pool.token_program.info(),
+            ];
+            process(&account_infos, |ctx| {
+                process_deposit_all_token_types(ctx, 1_000, 2_000, 2_000)
+            })
+        };
+        deposit_all_token_types(&mut pool).unwrap();
+        assert!(!pool.state().in_progress);
+
+        pool.set_state(|state| state.in_progress = true);
+        let error = deposit_all_token_types(&mut pool).unwrap_err();
+        assert_eq!(ProgramError::from(error), SwapError::InvalidInput.into());
+    }
+}
@@ -0,0 +1,312 @@
+//! Deposit one type of token into the pool
+
+use {
+    crate::{
+        curve::calculator::TradeDirection,
+        errors::SwapError,
+        instructions::{owner_fee_mint_amounts, to_u64, token_mint_to, token_transfer},
+        state::SwapState,
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::{Mint, Token, TokenAccount},
+};
+
+#[derive(Accounts)]
+pub struct DepositSingleTokenTypeExactAmountIn<'info> {
+    #[account(mut)]
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: program derived authority over the pool's token accounts and
+    /// pool mint, never read
+    #[account(seeds = [swap.key().as_ref()], bump = swap.bump_seed)]
+    pub authority: UncheckedAccount<'info>,
+    /// Owner or delegate of the source account
+    pub user_transfer_authority: Signer<'info>,
+    /// User's token A or B account
+    #[account(mut)]
+    pub source: Account<'info, TokenAccount>,
+    /// Pool token A account
+    #[account(mut)]
+    pub token_a: Account<'info, TokenAccount>,
+    /// Pool token B account
+    #[account(mut)]
+    pub token_b: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub pool_mint: Account<'info, Mint>,
+    /// User's pool token account receiving the new pool tokens
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    /// Pool token account receiving the owner trading fee
+    #[account(mut)]
+    pub pool_fee_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> DepositSingleTokenTypeExactAmountIn<'info> {
+    /// Check the given accounts against the pool's state, returning the
+    /// direction of the deposit, from the deposited token to the other one
+    pub fn validate(&self) -> Result<TradeDirection> {
+        let swap = &self.swap;
+        if self.token_a.key() != swap.token_a || self.token_b.key() != swap.token_b {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        let trade_direction = if self.source.mint == swap.token_a_mint {
+            TradeDirection::AtoB
+        } else if self.source.mint == swap.token_b_mint {
+            TradeDirection::BtoA
+        } else {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        };
+        if self.source.key() == self.token_a.key() || self.source.key() == self.token_b.key() {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if self.pool_mint.key() != swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.pool_fee_account.key() != swap.pool_fee_account {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+        if self.pool_fee_account.mint != swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.token_program.key() != swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        Ok(trade_direction)
+    }
+}
+
+pub fn process_deposit_single_token_type_exact_amount_in(
+    ctx: Context<DepositSingleTokenTypeExactAmountIn>,
+    source_token_amount: u64,
+    minimum_pool_token_amount: u64,
+) -> Result<()> {
+    let trade_direction = ctx.accounts.validate()?;
+    let now = Clock::get()?.unix_timestamp;
+    let swap_curve = ctx.accounts.swap.swap_curve_at(now)?;
+    if !swap_curve.calculator.allows_deposits() {
+        return Err(SwapError::UnsupportedCurveOperation.into());
+    }
+    ctx.accounts.swap.lock()?;
+    ctx.accounts.swap.exit(ctx.program_id)?;
+
+    let pool_token_amount = swap_curve.deposit_single_token_type(
+        u128::from(source_token_amount),
+        u128::from(ctx.accounts.token_a.amount),
+        u128::from(ctx.accounts.token_b.amount),
+        u128::from(ctx.accounts.pool_mint.supply),
+        trade_direction,
+        &ctx.accounts.swap.fees,
+    )?;
+    let pool_token_amount = to_u64(pool_token_amount)?;
+    if pool_token_amount < minimum_pool_token_amount {
+        return Err(SwapError::ExceededSlippage.into());
+    }
+    if pool_token_amount == 0 {
+        return Err(SwapError::ZeroTradingTokens.into());
+    }
+
+    // the owner fee is left in the pool, like the trade fee, and minted to the
+    // pool fee account as pool tokens valued after the deposit
+    let swap = &ctx.accounts.swap;
+    let (trade_fee, owner_fee) = swap
+        .fees
+        .single_deposit_fees(u128::from(source_token_amount))
+        .ok_or(SwapError::FeeCalculationFailure)?;
+    let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+        TradeDirection::AtoB => (
+            u128::from(ctx.accounts.token_a.amount) + u128::from(source_token_amount),
+            u128::from(ctx.accounts.token_b.amount),
+        ),
+        TradeDirection::BtoA => (
+            u128::from(ctx.accounts.token_a.amount),
+            u128::from(ctx.accounts.token_b.amount) + u128::from(source_token_amount),
+        ),
+    };
+    let (owner_fee_pool_tokens, _) = owner_fee_mint_amounts(
+        &swap_curve,
+        &swap.fees,
+        owner_fee,
+        swap_token_a_amount,
+        swap_token_b_amount,
+        u128::from(ctx.accounts.pool_mint.supply) + u128::from(pool_token_amount),
+        trade_direction,
+    )?;
+
+    let swap_key = swap.key();
+    let seeds: &[&[u8]] = &[swap_key.as_ref(), &[swap.bump_seed]];
+    let token_program = ctx.accounts.token_program.to_account_info();
+    let swap_source = match trade_direction {
+        TradeDirection::AtoB => ctx.accounts.token_a.to_account_info(),
+        TradeDirection::BtoA => ctx.accounts.token_b.to_account_info(),
+    };
+
+    let balances_before = (ctx.accounts.token_a.amount, ctx.accounts.token_b.amount);
+    token_transfer(
+        token_program.clone(),
+        ctx.accounts.source.to_account_info(),
+        swap_source,
+        ctx.accounts.user_transfer_authority.to_account_info(),
+        &[],
+        source_token_amount,
+    )?;
+    token_mint_to(
+        token_program.clone(),
+        ctx.accounts.pool_mint.to_account_info(),
+        ctx.accounts.destination.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        &[seeds],
+        pool_token_amount,
+    )?;
+    if owner_fee_pool_tokens > 0 {
+        token_mint_to(
+            token_program,
+            ctx.accounts.pool_mint.to_account_info(),
+            ctx.accounts.pool_fee_account.to_account_info(),
+            ctx.accounts.authority.to_account_info(),
+            &[seeds],
+            to_u64(owner_fee_pool_tokens)?,
+        )?;
+    }
+
+    let (token_a_amount, token_b_amount) = match trade_direction {
+        TradeDirection::AtoB => (source_token_amount, 0),
+        TradeDirection::BtoA => (0, source_token_amount),
+    };
+    ctx.accounts.token_a.reload()?;
+    ctx.accounts.token_b.reload()?;
+    let balances_after = (ctx.accounts.token_a.amount, ctx.accounts.token_b.amount);
+    ctx.accounts
+        .swap
+        .accrue_fees(trade_fee, trade_direction, owner_fee_pool_tokens)?;
+    ctx.accounts.swap.update_price_oracle(&swap_curve, now);
+    ctx.accounts.swap.apply_deposit(
+        token_a_amount,
+        token_b_amount,
+        balances_before,
+        balances_after,
+    )?;
+    ctx.accounts.swap.unlock();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            curve::{base::SwapCurve, fees::Fees},
+            instructions::test::{process, TestPool, NOW},
+            state::{twap, PriceSnapshot},
+        },
+    };
+
+    /// Deposit `source_token_amount` token A from the user's account
+    fn deposit_single_token_type_exact_amount_in(
+        pool: &mut TestPool,
+        source_token_amount: u64,
+    ) -> Result<()> {
+        let account_infos = [
+            pool.swap.info(),
+            pool.authority.info(),
+            pool.user.info(),
+            pool.user_token_a.info(),
+            pool.token_a.info(),
+            pool.token_b.info(),
+            pool.pool_mint.info(),
+            pool.user_pool_token.info(),
+            pool.pool_fee_account.info(),
+            pool.token_program.info(),
+        ];
+        process(&account_infos, |ctx| {
+            process_deposit_single_token_type_exact_amount_in(ctx, source_token_amount, 1)
+        })
+    }
+
+    #[test]
+    fn deposit_single_token_type_exact_amount_in_reentrancy_guard() {
+        let mut pool = TestPool::new(Fees::default().with_trade_fee_bps(25));
+        deposit_single_token_type_exact_amount_in(&mut pool, 1_000).unwrap();
+        assert!(!pool.state().in_progress);
+
+        pool.set_state(|state| state.in_progress = true);
+        let error = deposit_single_token_type_exact_amount_in(&mut pool, 1_000).unwrap_err();
+        assert_eq!(ProgramError::from(error), SwapError::InvalidInput.into());
+    }
+
+    #[test]
+    fn deposit_mints_owner_fee() {
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let mut pool = TestPool::new(fees.clone());
+        deposit_single_token_type_exact_amount_in(&mut pool, 100_000).unwrap();
+
+        // the depositor gets pool tokens for the deposit less its fees, and
+        // the owner fee is minted to the pool fee account
+        let (trade_fee, owner_fee) = fees.single_deposit_fees(100_000).unwrap();
+        let depositor_pool_tokens = SwapCurve::default()
+            .calculator
+            .deposit_single_token_type(
+                100_000 - trade_fee - owner_fee,
+                u128::from(TestPool::RESERVE),
+                u128::from(TestPool::RESERVE),
+                u128::from(TestPool::RESERVE),
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(
+            u128::from(pool.user_pool_token.token_amount() - TestPool::RESERVE),
+            depositor_pool_tokens
+        );
+        let owner_fee_pool_tokens = pool.pool_fee_account.token_amount();
+        assert!(owner_fee_pool_tokens > 0);
+        assert_eq!(
+            u128::from(pool.pool_mint.mint_supply()),
+            u128::from(TestPool::RESERVE)
+                + depositor_pool_tokens
+                + u128::from(owner_fee_pool_tokens)
+        );
+        let state = pool.state();
+        assert_eq!(
+            state.cumulative_fees().owner_fee,
+            u128::from(owner_fee_pool_tokens)
+        );
+        assert_eq!(state.cumulative_fees().trade_fee_a, trade_fee);
+        assert_eq!(state.token_a_reserve, TestPool::RESERVE + 100_000);
+
+        // pools without owner fees mint nothing to the fee account
+        let mut pool = TestPool::new(Fees::default().with_trade_fee_bps(25));
+        deposit_single_token_type_exact_amount_in(&mut pool, 100_000).unwrap();
+        assert_eq!(pool.pool_fee_account.token_amount(), 0);
+    }
+
+    #[test]
+    fn deposit_updates_price_oracle() {
+        let swap_curve = SwapCurve::default();
+        let mut pool = TestPool::new(Fees::default().with_trade_fee_bps(25));
+        let start = PriceSnapshot {
+            cumulative_price_a: 0,
+            timestamp: NOW - 100,
+        };
+        pool.set_state(|state| state.last_update_ts = start.timestamp);
+        let price_before = swap_curve
+            .spot_price_q64(u128::from(TestPool::RESERVE), u128::from(TestPool::RESERVE))
+            .unwrap();
+
+        // the price before the deposit is held until it, and the price after
+        // it from then on
+        deposit_single_token_type_exact_amount_in(&mut pool, 100_000).unwrap();
+        let state = pool.state();
+        assert_eq!(state.last_update_ts, NOW);
+        assert_eq!(state.cumulative_price_a, 100 * price_before);
+        let price_after = swap_curve
+            .spot_price_q64(
+                u128::from(state.token_a_reserve),
+                u128::from(state.token_b_reserve),
+            )
+            .unwrap();
+        assert_ne!(price_after, price_before);
+        let end = state.price_snapshot(&swap_curve, NOW + 100);
+        assert_eq!(twap(&start, &end), Some((price_before + price_after) / 2));
+    }
+}
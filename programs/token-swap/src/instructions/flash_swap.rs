@@ -0,0 +1,479 @@
+//! Lend tokens from the pool for the duration of a callback, to be repaid in
+//! the other token
+
+use {
+    crate::{
+        curve::{base::SwapCurve, calculator::TradeDirection, fees::Fees},
+        errors::SwapError,
+        instructions::{owner_fee_mint_amounts, to_u64, token_mint_to, token_transfer},
+        state::SwapState,
+    },
+    anchor_lang::{
+        prelude::*,
+        solana_program::{
+            instruction::{AccountMeta, Instruction},
+            program::invoke,
+        },
+    },
+    anchor_spl::token::{Mint, Token, TokenAccount},
+};
+
+#[derive(Accounts)]
+pub struct FlashSwap<'info> {
+    #[account(mut)]
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: program derived authority over the pool's token accounts and
+    /// pool mint, never read
+    #[account(seeds = [swap.key().as_ref()], bump = swap.bump_seed)]
+    pub authority: UncheckedAccount<'info>,
+    /// Pool account to be repaid by the callback, either token A or B
+    #[account(mut)]
+    pub swap_source: Account<'info, TokenAccount>,
+    /// Pool account lending out the destination tokens, the other of token A
+    /// or B
+    #[account(mut)]
+    pub swap_destination: Account<'info, TokenAccount>,
+    /// Borrower's account receiving the lent tokens
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    /// Pool token mint, to generate the owner trading fee
+    #[account(mut)]
+    pub pool_mint: Account<'info, Mint>,
+    /// Pool token account receiving the owner trading fee. Flash swaps don't
+    /// take a host fee account, so it receives the whole fee.
+    #[account(mut)]
+    pub pool_fee_account: Account<'info, TokenAccount>,
+    /// CHECK: program invoked once the tokens are lent, which must repay the
+    /// pool, only checked to be executable
+    #[account(executable)]
+    pub callback_program: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    // The accounts needed by the callback program, including the swap source
+    // account to repay, are passed as remaining accounts
+}
+
+impl<'info> FlashSwap<'info> {
+    /// Check the given accounts against the pool's state, returning the
+    /// direction of the trade
+    pub fn validate(&self) -> Result<TradeDirection> {
+        let swap = &self.swap;
+        let trade_direction = if self.swap_source.key() == swap.token_a
+            && self.swap_destination.key() == swap.token_b
+        {
+            TradeDirection::AtoB
+        } else if self.swap_source.key() == swap.token_b
+            && self.swap_destination.key() == swap.token_a
+        {
+            TradeDirection::BtoA
+        } else {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        };
+        if self.destination.key() == self.swap_destination.key() {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if self.callback_program.key() == crate::ID {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if self.pool_mint.key() != swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.pool_fee_account.key() != swap.pool_fee_account {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+        if self.pool_fee_account.mint != swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.token_program.key() != swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        Ok(trade_direction)
+    }
+}
+
+/// Fees charged on a flash swap, in the destination token lent
+#[derive(Debug, PartialEq)]
+pub struct FlashSwapFees {
+    /// Amount of destination tokens going to pool holders
+    pub trade_fee: u128,
+    /// Amount of destination tokens going to the owner
+    pub owner_fee: u128,
+}
+
+/// Check that the pool was repaid for a flash swap of `amount_out`, ie. that
+/// the destination tokens returned in kind, plus the destination tokens
+/// bought by the source tokens received when swapped against the reserves
+/// from before the loan, cover the amount lent and its fees.
+///
+/// Fees are charged on the whole `amount_out`, whatever part of it is
+/// returned in kind. The balances of the pool's token accounts are given as
+/// `(swap_source, swap_destination)`, before the tokens were lent and after
+/// the callback returned.
+pub fn verify_flash_repayment(
+    swap_curve: &SwapCurve,
+    fees: &Fees,
+    trade_direction: TradeDirection,
+    amount_out: u64,
+    balances_before: (u64, u64),
+    balances_after: (u64, u64),
+) -> std::result::Result<FlashSwapFees, SwapError> {
+    let (swap_source_before, swap_destination_before) = balances_before;
+    let (swap_source_after, swap_destination_after) = balances_after;
+    let amount_out = u128::from(amount_out);
+    let trade_fee = fees
+        .trading_fee(amount_out)
+        .ok_or(SwapError::FeeCalculationFailure)?;
+    let owner_fee = if fees.charges_owner_fee(trade_direction) {
+        fees.owner_trading_fee(amount_out)
+            .ok_or(SwapError::FeeCalculationFailure)?
+    } else {
+        0
+    };
+    let amount_owed = amount_out
+        .checked_add(trade_fee)
+        .and_then(|amount_owed| amount_owed.checked_add(owner_fee))
+        .ok_or(SwapError::FeeCalculationFailure)?;
+    let returned_in_kind = (u128::from(swap_destination_after) + amount_out)
+        .checked_sub(u128::from(swap_destination_before))
+        .ok_or(SwapError::CalculationFailure)?;
+    let shortfall = amount_owed.saturating_sub(returned_in_kind);
+    if shortfall > 0 {
+        let amount_in = swap_source_after
+            .checked_sub(swap_source_before)
+            .filter(|amount_in| *amount_in > 0)
+            .ok_or(SwapError::CalculationFailure)?;
+        // the fees are already charged on the amount lent
+        let result = swap_curve
+            .calculator
+            .swap_without_fees(
+                u128::from(amount_in),
+                u128::from(swap_source_before),
+                u128::from(swap_destination_before),
+                trade_direction,
+            )
+            .ok_or(SwapError::CalculationFailure)?;
+        if result.destination_amount_swapped < shortfall {
+            return Err(SwapError::CalculationFailure);
+        }
+    }
+    Ok(FlashSwapFees {
+        trade_fee,
+        owner_fee,
+    })
+}
+
+pub fn process_flash_swap<'info>(
+    ctx: Context<'_, '_, '_, 'info, FlashSwap<'info>>,
+    amount_out: u64,
+) -> Result<()> {
+    let trade_direction = ctx.accounts.validate()?;
+    if amount_out == 0 {
+        return Err(SwapError::ZeroTradingTokens.into());
+    }
+    // the lock also stops the callback from using the pool while the loan is
+    // outstanding
+    ctx.accounts.swap.lock()?;
+    ctx.accounts.swap.exit(ctx.program_id)?;
+
+    let swap = &ctx.accounts.swap;
+    let now = Clock::get()?.unix_timestamp;
+    let swap_curve = swap.swap_curve_at(now)?;
+    let swap_source_before = ctx.accounts.swap_source.amount;
+    let swap_destination_before = ctx.accounts.swap_destination.amount;
+
+    let swap_key = swap.key();
+    let seeds: &[&[u8]] = &[swap_key.as_ref(), &[swap.bump_seed]];
+    token_transfer(
+        ctx.accounts.token_program.to_account_info(),
+        ctx.accounts.swap_destination.to_account_info(),
+        ctx.accounts.destination.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        &[seeds],
+        amount_out,
+    )?;
+
+    let callback = Instruction {
+        program_id: ctx.accounts.callback_program.key(),
+        accounts: ctx
+            .remaining_accounts
+            .iter()
+            .map(|account| AccountMeta {
+                pubkey: account.key(),
+                is_signer: account.is_signer,
+                is_writable: account.is_writable,
+            })
+            .collect(),
+        data: amount_out.to_le_bytes().to_vec(),
+    };
+    let mut callback_accounts = ctx.remaining_accounts.to_vec();
+    callback_accounts.push(ctx.accounts.callback_program.to_account_info());
+    invoke(&callback, &callback_accounts)?;
+
+    ctx.accounts.swap_source.reload()?;
+    ctx.accounts.swap_destination.reload()?;
+    let swap_source_after = ctx.accounts.swap_source.amount;
+    let swap_destination_after = ctx.accounts.swap_destination.amount;
+    // dynamic fees are raised by the imbalance left by the flash swap
+    let fees = match trade_direction {
+        TradeDirection::AtoB => ctx.accounts.swap.fees_for_reserves(
+            u128::from(swap_source_after),
+            u128::from(swap_destination_after),
+        ),
+        TradeDirection::BtoA => ctx.accounts.swap.fees_for_reserves(
+            u128::from(swap_destination_after),
+            u128::from(swap_source_after),
+        ),
+    };
+    let flash_swap_fees = verify_flash_repayment(
+        &swap_curve,
+        &fees,
+        trade_direction,
+        amount_out,
+        (swap_source_before, swap_destination_before),
+        (swap_source_after, swap_destination_after),
+    )?;
+    ctx.accounts
+        .swap
+        .check_reserve_floor(u128::from(swap_destination_after), trade_direction)?;
+    let (token_a_reserve, token_b_reserve) = match trade_direction {
+        TradeDirection::AtoB => (swap_source_after, swap_destination_after),
+        TradeDirection::BtoA => (swap_destination_after, swap_source_after),
+    };
+
+    // the fees are held in the destination token, so the owner fee is charged
+    // as on a swap from it
+    let fee_direction = trade_direction.opposite();
+    let (pool_token_amount, _) = owner_fee_mint_amounts(
+        &swap_curve,
+        &fees,
+        flash_swap_fees.owner_fee,
+        u128::from(token_a_reserve),
+        u128::from(token_b_reserve),
+        u128::from(ctx.accounts.pool_mint.supply),
+        fee_direction,
+    )?;
+    if pool_token_amount > 0 {
+        token_mint_to(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.pool_mint.to_account_info(),
+            ctx.accounts.pool_fee_account.to_account_info(),
+            ctx.accounts.authority.to_account_info(),
+            &[seeds],
+            to_u64(pool_token_amount)?,
+        )?;
+    }
+    ctx.accounts
+        .swap
+        .accrue_fees(flash_swap_fees.trade_fee, fee_direction, pool_token_amount)?;
+
+    let swap = &mut ctx.accounts.swap;
+    swap.update_price_oracle(&swap_curve, now);
+    swap.token_a_reserve = token_a_reserve;
+    swap.token_b_reserve = token_b_reserve;
+    swap.unlock();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::instructions::test::{
+            process, TestAccount, TestPool, NO_REPAY_CALLBACK_ID, REPAY_CALLBACK_ID,
+            RETURN_CALLBACK_ID,
+        },
+    };
+
+    /// Borrow `amount_out` token B from the pool, with the callback program
+    /// repaying it from the user's account, in token A or in kind
+    fn flash_swap(
+        pool: &mut TestPool,
+        callback_program_id: Pubkey,
+        amount_out: u64,
+        in_kind: bool,
+    ) -> Result<()> {
+        let mut callback_program = TestAccount::program(callback_program_id);
+        let user = pool.user.info();
+        let token_a = pool.token_a.info();
+        let token_b = pool.token_b.info();
+        let user_token_b = pool.user_token_b.info();
+        let (repay_source, repay_destination) = if in_kind {
+            (user_token_b.clone(), token_b.clone())
+        } else {
+            (pool.user_token_a.info(), token_a.clone())
+        };
+        let account_infos = [
+            pool.swap.info(),
+            pool.authority.info(),
+            token_a,
+            token_b,
+            user_token_b,
+            pool.pool_mint.info(),
+            pool.pool_fee_account.info(),
+            callback_program.info(),
+            pool.token_program.info(),
+            // accounts of the callback
+            repay_source,
+            repay_destination,
+            user,
+        ];
+        process(&account_infos, |ctx| process_flash_swap(ctx, amount_out))
+    }
+
+    #[test]
+    fn flash_swap_repaid() {
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let mut pool = TestPool::new(fees.clone());
+        flash_swap(&mut pool, REPAY_CALLBACK_ID, 100_000, false).unwrap();
+
+        assert_eq!(
+            pool.user_token_b.token_amount(),
+            TestPool::RESERVE + 100_000
+        );
+        assert_eq!(
+            pool.user_token_a.token_amount(),
+            TestPool::RESERVE - 200_000
+        );
+        let state = pool.state();
+        assert!(!state.in_progress);
+        assert_eq!(state.token_a_reserve, pool.token_a.token_amount());
+        assert_eq!(state.token_b_reserve, pool.token_b.token_amount());
+        assert_eq!(state.token_b_reserve, TestPool::RESERVE - 100_000);
+        // the fees on the amount lent are charged, including the owner fee
+        // minted to the pool fee account
+        let owner_fee = pool.pool_fee_account.token_amount();
+        assert!(owner_fee > 0);
+        assert_eq!(pool.pool_mint.mint_supply(), TestPool::RESERVE + owner_fee);
+        assert_eq!(state.cumulative_fees().owner_fee, u128::from(owner_fee));
+        assert_eq!(
+            state.cumulative_fees().trade_fee_b,
+            fees.trading_fee(100_000).unwrap()
+        );
+    }
+
+    #[test]
+    fn flash_swap_repaid_in_kind() {
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let mut pool = TestPool::new(fees.clone());
+        flash_swap(&mut pool, REPAY_CALLBACK_ID, 100_000, true).unwrap();
+
+        assert_eq!(
+            pool.user_token_b.token_amount(),
+            TestPool::RESERVE - 100_000
+        );
+        let state = pool.state();
+        assert!(!state.in_progress);
+        assert_eq!(state.token_a_reserve, TestPool::RESERVE);
+        assert_eq!(state.token_b_reserve, TestPool::RESERVE + 100_000);
+        assert_eq!(state.token_b_reserve, pool.token_b.token_amount());
+        // returning the lent tokens themselves still pays the fees
+        let owner_fee = pool.pool_fee_account.token_amount();
+        assert!(owner_fee > 0);
+        assert_eq!(pool.pool_mint.mint_supply(), TestPool::RESERVE + owner_fee);
+        assert_eq!(state.cumulative_fees().owner_fee, u128::from(owner_fee));
+        assert_eq!(
+            state.cumulative_fees().trade_fee_b,
+            fees.trading_fee(100_000).unwrap()
+        );
+    }
+
+    #[test]
+    fn flash_swap_returned_without_fees() {
+        let mut pool = TestPool::new(Fees::default().with_trade_fee_bps(25));
+        let error = flash_swap(&mut pool, RETURN_CALLBACK_ID, 100_000, true).unwrap_err();
+        assert_eq!(
+            ProgramError::from(error),
+            SwapError::CalculationFailure.into()
+        );
+    }
+
+    #[test]
+    fn flash_swap_not_repaid() {
+        let mut pool = TestPool::new(Fees::default().with_trade_fee_bps(25));
+        let error = flash_swap(&mut pool, NO_REPAY_CALLBACK_ID, 1_000, false).unwrap_err();
+        assert_eq!(
+            ProgramError::from(error),
+            SwapError::CalculationFailure.into()
+        );
+    }
+
+    #[test]
+    fn verify_flash_repayment() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let before = (1_000_000u64, 1_000_000u64);
+        let amount_out = 1_000;
+        let expected = FlashSwapFees {
+            trade_fee: fees.trading_fee(u128::from(amount_out)).unwrap(),
+            owner_fee: fees.owner_trading_fee(u128::from(amount_out)).unwrap(),
+        };
+        let amount_owed = amount_out + (expected.trade_fee + expected.owner_fee) as u64;
+        let verify = |after| {
+            super::verify_flash_repayment(
+                &swap_curve,
+                &fees,
+                TradeDirection::AtoB,
+                amount_out,
+                before,
+                after,
+            )
+        };
+
+        // the source amount needed to buy the amount owed with a swap
+        let amount_in = (amount_out..)
+            .find(|amount_in| {
+                swap_curve
+                    .calculator
+                    .swap_without_fees(
+                        u128::from(*amount_in),
+                        u128::from(before.0),
+                        u128::from(before.1),
+                        TradeDirection::AtoB,
+                    )
+                    .unwrap()
+                    .destination_amount_swapped
+                    >= u128::from(amount_owed)
+            })
+            .unwrap();
+        assert_eq!(
+            verify((before.0 + amount_in, before.1 - amount_out)),
+            Ok(FlashSwapFees {
+                trade_fee: expected.trade_fee,
+                owner_fee: expected.owner_fee,
+            })
+        );
+
+        // one token short of the fees
+        assert_eq!(
+            verify((before.0 + amount_in - 1, before.1 - amount_out)),
+            Err(SwapError::CalculationFailure)
+        );
+
+        // no repayment at all
+        assert_eq!(
+            verify((before.0, before.1 - amount_out)),
+            Err(SwapError::CalculationFailure)
+        );
+
+        // returning only the lent tokens themselves misses the fees
+        assert_eq!(
+            verify((before.0, before.1)),
+            Err(SwapError::CalculationFailure)
+        );
+        assert_eq!(
+            verify((before.0, before.1 - amount_out + amount_owed - 1)),
+            Err(SwapError::CalculationFailure)
+        );
+
+        // returning them with the fees is fine, and charged the same fees
+        assert_eq!(
+            verify((before.0, before.1 - amount_out + amount_owed)),
+            Ok(expected)
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Initialize a new swap pool
+
+use {
+    crate::{
+        curve::{
+            base::{validate_swap_config, CurveType, SwapCurve},
+            calculator::MINIMUM_LIQUIDITY,
+            fees::Fees,
+        },
+        errors::SwapError,
+        instructions::{to_u64, token_mint_to},
+        state::SwapState,
+    },
+    anchor_lang::{prelude::*, solana_program::program_option::COption},
+    anchor_spl::token::{Mint, Token, TokenAccount},
+};
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    /// New swap state account
+    #[account(init, payer = payer, space = 8 + SwapState::LEN)]
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: program derived authority over the pool's token accounts and
+    /// pool mint, never read
+    #[account(seeds = [swap.key().as_ref()], bump)]
+    pub authority: UncheckedAccount<'info>,
+    /// Token A account owned by the swap authority, holding the initial deposit
+    pub token_a: Account<'info, TokenAccount>,
+    /// Token B account owned by the swap authority, holding the initial deposit
+    pub token_b: Account<'info, TokenAccount>,
+    /// Pool token mint, with the swap authority as mint authority
+    #[account(mut)]
+    pub pool_mint: Account<'info, Mint>,
+    /// Pool token account to receive trading and withdrawal fees
+    pub pool_fee_account: Account<'info, TokenAccount>,
+    /// Pool token account receiving the initial pool tokens
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    /// Pool token account owned by the swap authority, receiving the
+    /// `MINIMUM_LIQUIDITY` pool tokens locked forever in the pool
+    #[account(mut)]
+    pub locked_liquidity: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> Initialize<'info> {
+    /// Check that the pool and trading token mints are all distinct.
+    ///
+    /// A pool trading a mint against itself, or issuing its own trading
+    /// token as the pool token, can never be used correctly.
+    pub fn validate_mints(&self) -> Result<()> {
+        if self.token_a.mint == self.token_b.mint {
+            return Err(SwapError::RepeatedMint.into());
+        }
+        let pool_mint = self.pool_mint.key();
+        if pool_mint == self.token_a.mint || pool_mint == self.token_b.mint {
+            return Err(SwapError::RepeatedMint.into());
+        }
+        Ok(())
+    }
+
+    /// Check that the token accounts and pool mint are controlled by the swap
+    /// authority and in a usable state
+    pub fn validate_accounts(&self) -> Result<()> {
+        let authority = self.authority.key();
+        if self.token_a.owner != authority || self.token_b.owner != authority {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if self.destination.owner == authority {
+            return Err(SwapError::InvalidOutputOwner.into());
+        }
+        if self.locked_liquidity.owner != authority {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if self.locked_liquidity.mint != self.pool_mint.key() {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.pool_fee_account.mint != self.pool_mint.key() {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.token_a.delegate.is_some() || self.token_b.delegate.is_some() {
+            return Err(SwapError::InvalidDelegate.into());
+        }
+        if self.token_a.close_authority.is_some() || self.token_b.close_authority.is_some() {
+            return Err(SwapError::InvalidCloseAuthority.into());
+        }
+        if self.pool_mint.mint_authority != COption::Some(authority) {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if self.pool_mint.freeze_authority.is_some() {
+            return Err(SwapError::InvalidFreezeAuthority.into());
+        }
+        if self.pool_mint.supply != 0 {
+            return Err(SwapError::InvalidSupply.into());
+        }
+        Ok(())
+    }
+}
+
+/// Curve types which can be used by new pools. Conservative deployments can
+/// restrict this, eg. to only `CurveType::ConstantProduct`.
+pub const ALLOWED_CURVE_TYPES: &[CurveType] = &[
+    CurveType::ConstantProduct,
+    CurveType::ConstantPrice,
+    CurveType::Stable,
+    CurveType::Offset,
+    CurveType::Concentrated,
+];
+
+/// Check that `curve_type` is one of the `allowed` curve types
+pub fn validate_curve_type(
+    curve_type: CurveType,
+    allowed: &[CurveType],
+) -> std::result::Result<(), SwapError> {
+    if allowed.contains(&curve_type) {
+        Ok(())
+    } else {
+        Err(SwapError::InvalidCurve)
+    }
+}
+
+pub fn process_initialize(
+    ctx: Context<Initialize>,
+    fees: Fees,
+    curve_type: CurveType,
+    curve_parameters: [u8; SwapCurve::PARAMETERS_LEN],
+) -> Result<()> {
+    ctx.accounts.validate_mints()?;
+    ctx.accounts.validate_accounts()?;
+    validate_curve_type(curve_type, ALLOWED_CURVE_TYPES)?;
+
+    let swap_curve = SwapCurve::from_parameters(curve_type, &curve_parameters)?;
+    validate_swap_config(
+        swap_curve.calculator.as_ref(),
+        &fees,
+        ctx.accounts.token_a.amount,
+        ctx.accounts.token_b.amount,
+    )?;
+
+    let creator_amount = swap_curve
+        .creator_pool_tokens(
+            u128::from(ctx.accounts.token_a.amount),
+            u128::from(ctx.accounts.token_b.amount),
+        )
+        .ok_or(SwapError::CalculationFailure)?;
+
+    let bump_seed = *ctx.bumps.get("authority").unwrap();
+    let swap_key = ctx.accounts.swap.key();
+    let seeds: &[&[u8]] = &[swap_key.as_ref(), &[bump_seed]];
+    token_mint_to(
+        ctx.accounts.token_program.to_account_info(),
+        ctx.accounts.pool_mint.to_account_info(),
+        ctx.accounts.locked_liquidity.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        &[seeds],
+        to_u64(MINIMUM_LIQUIDITY)?,
+    )?;
+    token_mint_to(
+        ctx.accounts.token_program.to_account_info(),
+        ctx.accounts.pool_mint.to_account_info(),
+        ctx.accounts.destination.to_account_info(),
+        ctx.accounts.authority.to_account_info(),
+        &[seeds],
+        to_u64(creator_amount)?,
+    )?;
+
+    let token_program_id = ctx.accounts.token_program.key();
+    let token_a = ctx.accounts.token_a.key();
+    let token_b = ctx.accounts.token_b.key();
+    let pool_mint = ctx.accounts.pool_mint.key();
+    let token_a_mint = ctx.accounts.token_a.mint;
+    let token_b_mint = ctx.accounts.token_b.mint;
+    let pool_fee_account = ctx.accounts.pool_fee_account.key();
+    let token_a_reserve = ctx.accounts.token_a.amount;
+    let token_b_reserve = ctx.accounts.token_b.amount;
+    ctx.accounts.swap.set_inner(SwapState {
+        is_initialized: true,
+        in_progress: false,
+        bump_seed,
+        token_program_id,
+        token_a,
+        token_b,
+        pool_mint,
+        token_a_mint,
+        token_b_mint,
+        pool_fee_account,
+        fees,
+        curve_type,
+        curve_parameters,
+        target_amp: 0,
+        ramp_start_amp: 0,
+        ramp_start_ts: 0,
+        ramp_stop_ts: 0,
+        token_a_reserve,
+        token_b_reserve,
+        imbalance_multiplier_bps: 0,
+        cumulative_trade_fee_a: 0,
+        cumulative_trade_fee_b: 0,
+        cumulative_owner_fee: 0,
+        reserve_floor_a: 0,
+        reserve_floor_b: 0,
+        cumulative_price_a: 0,
+        last_update_ts: Clock::get()?.unix_timestamp,
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_curve_type() {
+        for curve_type in [
+            CurveType::ConstantProduct,
+            CurveType::ConstantPrice,
+            CurveType::Stable,
+            CurveType::Offset,
+            CurveType::Concentrated,
+        ] {
+            assert_eq!(
+                super::validate_curve_type(curve_type, ALLOWED_CURVE_TYPES),
+                Ok(())
+            );
+        }
+
+        let conservative = &[CurveType::ConstantProduct];
+        assert_eq!(
+            super::validate_curve_type(CurveType::ConstantProduct, conservative),
+            Ok(())
+        );
+        assert_eq!(
+            super::validate_curve_type(CurveType::Offset, conservative),
+            Err(SwapError::InvalidCurve)
+        );
+    }
+}
@@ -0,0 +1,118 @@
+pub mod deposit_all_token_types;
+pub mod deposit_single_token_type_exact_amount_in;
+pub mod flash_swap;
+pub mod initialize;
+pub mod set_amp;
+pub mod set_imbalance_fee;
+pub mod set_reserve_floors;
+pub mod swap;
+pub mod withdraw_all_token_types;
+
+#[cfg(test)]
+pub mod test;
+
+pub use deposit_all_token_types::*;
+pub use deposit_single_token_type_exact_amount_in::*;
+pub use flash_swap::*;
+pub use initialize::*;
+pub use set_amp::*;
+pub use set_imbalance_fee::*;
+pub use set_reserve_floors::*;
+pub use swap::*;
+pub use withdraw_all_token_types::*;
+
+use {
+    crate::{errors::SwapError, state::SwapState},
+    anchor_lang::prelude::*,
+    anchor_spl::token::{self, Burn, MintTo, TokenAccount, Transfer},
+};
+
+/// Convert a calculated amount back into a token amount
+pub fn to_u64(val: u128) -> Result<u64> {
+    u64::try_from(val).map_err(|_| SwapError::CoversionFailure.into())
+}
+
+/// Check that `owner` owns the pool, through the pool's fee account, for the
+/// instructions configuring the pool
+pub fn validate_pool_owner(
+    swap: &SwapState,
+    pool_fee_account: &Account<TokenAccount>,
+    owner: &Signer,
+) -> Result<()> {
+    if pool_fee_account.key() != swap.pool_fee_account {
+        return Err(SwapError::IncorrectFeeAccount.into());
+    }
+    if pool_fee_account.owner != owner.key() {
+        return Err(SwapError::InvalidOwner.into());
+    }
+    Ok(())
+}
+
+/// Issue a spl_token `Transfer` instruction, signed by the given seeds when
+/// moving tokens out of the pool
+pub fn token_transfer<'info>(
+    token_program: AccountInfo<'info>,
+    source: AccountInfo<'info>,
+    destination: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+) -> Result<()> {
+    token::transfer(
+        CpiContext::new_with_signer(
+            token_program,
+            Transfer {
+                from: source,
+                to: destination,
+                authority,
+            },
+            signer_seeds,
+        ),
+        amount,
+    )
+}
+
+/// Issue a spl_token `MintTo` instruction, signed by the swap authority
+pub fn token_mint_to<'info>(
+    token_program: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    destination: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+) -> Result<()> {
+    token::mint_to(
+        CpiContext::new_with_signer(
+            token_program,
+            MintTo {
+                mint,
+                to: destination,
+                authority,
+            },
+            signer_seeds,
+        ),
+        amount,
+    )
+}
+
+/// Issue a spl_token `Burn` instruction, signed by the owner of the burned
+/// pool tokens
+pub fn token_burn<'info>(
+    token_program: AccountInfo<'info>,
+    burn_account: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    token::burn(
+        CpiContext::new(
+            token_program,
+            Burn {
+                mint,
+                from: burn_account,
+                authority,
+            },
+        ),
+        amount,
+    )
+}
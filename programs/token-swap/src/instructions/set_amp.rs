@@ -0,0 +1,100 @@
+//! Ramp the amplifier of a stable curve pool
+
+use {
+    crate::{
+        curve::stable::{validate_amp_change, MIN_RAMP_DURATION},
+        errors::SwapError,
+        instructions::validate_pool_owner,
+        state::SwapState,
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::TokenAccount,
+};
+
+#[derive(Accounts)]
+pub struct SetAmp<'info> {
+    #[account(mut)]
+    pub swap: Account<'info, SwapState>,
+    /// Pool fee account, whose owner is the owner of the pool
+    pub pool_fee_account: Account<'info, TokenAccount>,
+    /// Owner of the pool
+    pub owner: Signer<'info>,
+}
+
+impl<'info> SetAmp<'info> {
+    /// Check that the signer owns the pool
+    pub fn validate(&self) -> Result<()> {
+        validate_pool_owner(&self.swap, &self.pool_fee_account, &self.owner)
+    }
+}
+
+/// Ramp the amplifier linearly to `target_amp` over `ramp_duration` seconds,
+/// which must be at least `MIN_RAMP_DURATION`
+pub fn process_set_amp(ctx: Context<SetAmp>, target_amp: u64, ramp_duration: i64) -> Result<()> {
+    ctx.accounts.validate()?;
+    if ramp_duration < MIN_RAMP_DURATION {
+        return Err(SwapError::InvalidInput.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    let swap = &mut ctx.accounts.swap;
+    let current_amp = swap.amp_at(now)?;
+    validate_amp_change(current_amp, target_amp)?;
+
+    swap.target_amp = target_amp;
+    swap.ramp_start_amp = current_amp;
+    swap.ramp_start_ts = now;
+    swap.ramp_stop_ts = now
+        .checked_add(ramp_duration)
+        .ok_or(SwapError::CalculationFailure)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            curve::{
+                base::{CurveType, SwapCurve},
+                fees::Fees,
+                stable::StableCurve,
+            },
+            instructions::test::{process, TestPool, NOW},
+        },
+        std::sync::Arc,
+    };
+
+    fn set_amp(pool: &mut TestPool, target_amp: u64, ramp_duration: i64) -> Result<()> {
+        let account_infos = [
+            pool.swap.info(),
+            pool.pool_fee_account.info(),
+            pool.user.info(),
+        ];
+        process(&account_infos, |ctx| {
+            process_set_amp(ctx, target_amp, ramp_duration)
+        })
+    }
+
+    #[test]
+    fn min_ramp_duration() {
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+            ..SwapCurve::default()
+        };
+        let mut pool = TestPool::with_curve(swap_curve, Fees::default());
+        for ramp_duration in [-1, 0, MIN_RAMP_DURATION - 1] {
+            let error = set_amp(&mut pool, 200, ramp_duration).unwrap_err();
+            assert_eq!(ProgramError::from(error), SwapError::InvalidInput.into());
+        }
+        assert_eq!(pool.state().target_amp, 0);
+
+        set_amp(&mut pool, 200, MIN_RAMP_DURATION).unwrap();
+        let state = pool.state();
+        assert_eq!(state.target_amp, 200);
+        assert_eq!(state.ramp_start_amp, 100);
+        assert_eq!(state.ramp_start_ts, NOW);
+        assert_eq!(state.ramp_stop_ts, NOW + MIN_RAMP_DURATION);
+    }
+}
@@ -0,0 +1,81 @@
+//! Configure the dynamic trade fee of a pool
+
+use {
+    crate::{
+        curve::fees::MAX_IMBALANCE_MULTIPLIER_BPS, errors::SwapError,
+        instructions::validate_pool_owner, state::SwapState,
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::TokenAccount,
+};
+
+#[derive(Accounts)]
+pub struct SetImbalanceFee<'info> {
+    #[account(mut)]
+    pub swap: Account<'info, SwapState>,
+    /// Pool fee account, whose owner is the owner of the pool
+    pub pool_fee_account: Account<'info, TokenAccount>,
+    /// Owner of the pool
+    pub owner: Signer<'info>,
+}
+
+impl<'info> SetImbalanceFee<'info> {
+    /// Check that the signer owns the pool
+    pub fn validate(&self) -> Result<()> {
+        validate_pool_owner(&self.swap, &self.pool_fee_account, &self.owner)
+    }
+}
+
+/// Raise the trade fee by up to `imbalance_multiplier_bps` of the base trade
+/// fee as the pool becomes imbalanced, or charge fixed fees again with zero.
+/// Multipliers above `MAX_IMBALANCE_MULTIPLIER_BPS` are rejected.
+pub fn process_set_imbalance_fee(
+    ctx: Context<SetImbalanceFee>,
+    imbalance_multiplier_bps: u16,
+) -> Result<()> {
+    ctx.accounts.validate()?;
+    if imbalance_multiplier_bps > MAX_IMBALANCE_MULTIPLIER_BPS {
+        return Err(SwapError::InvalidFee.into());
+    }
+    ctx.accounts.swap.imbalance_multiplier_bps = imbalance_multiplier_bps;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            curve::fees::Fees,
+            instructions::test::{process, TestPool},
+        },
+    };
+
+    fn set_imbalance_fee(pool: &mut TestPool, imbalance_multiplier_bps: u16) -> Result<()> {
+        let account_infos = [
+            pool.swap.info(),
+            pool.pool_fee_account.info(),
+            pool.user.info(),
+        ];
+        process(&account_infos, |ctx| {
+            process_set_imbalance_fee(ctx, imbalance_multiplier_bps)
+        })
+    }
+
+    #[test]
+    fn max_imbalance_multiplier() {
+        let mut pool = TestPool::new(Fees::default().with_trade_fee_bps(30));
+        set_imbalance_fee(&mut pool, MAX_IMBALANCE_MULTIPLIER_BPS).unwrap();
+        assert_eq!(
+            pool.state().imbalance_multiplier_bps,
+            MAX_IMBALANCE_MULTIPLIER_BPS
+        );
+
+        let error = set_imbalance_fee(&mut pool, MAX_IMBALANCE_MULTIPLIER_BPS + 1).unwrap_err();
+        assert_eq!(ProgramError::from(error), SwapError::InvalidFee.into());
+        assert_eq!(
+            pool.state().imbalance_multiplier_bps,
+            MAX_IMBALANCE_MULTIPLIER_BPS
+        );
+    }
+}
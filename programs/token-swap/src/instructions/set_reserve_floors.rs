@@ -0,0 +1,86 @@
+//! Configure the reserve floors of a pool
+
+use {
+    crate::{instructions::validate_pool_owner, state::SwapState},
+    anchor_lang::prelude::*,
+    anchor_spl::token::TokenAccount,
+};
+
+#[derive(Accounts)]
+pub struct SetReserveFloors<'info> {
+    #[account(mut)]
+    pub swap: Account<'info, SwapState>,
+    /// Pool fee account, whose owner is the owner of the pool
+    pub pool_fee_account: Account<'info, TokenAccount>,
+    /// Owner of the pool
+    pub owner: Signer<'info>,
+}
+
+impl<'info> SetReserveFloors<'info> {
+    /// Check that the signer owns the pool
+    pub fn validate(&self) -> Result<()> {
+        validate_pool_owner(&self.swap, &self.pool_fee_account, &self.owner)
+    }
+}
+
+/// Reject swaps leaving less than `reserve_floor_a` of token A or
+/// `reserve_floor_b` of token B in the pool. Zero removes a floor.
+pub fn process_set_reserve_floors(
+    ctx: Context<SetReserveFloors>,
+    reserve_floor_a: u64,
+    reserve_floor_b: u64,
+) -> Result<()> {
+    ctx.accounts.validate()?;
+    let swap = &mut ctx.accounts.swap;
+    swap.reserve_floor_a = reserve_floor_a;
+    swap.reserve_floor_b = reserve_floor_b;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            curve::fees::Fees,
+            errors::SwapError,
+            instructions::test::{process, TestAccount, TestPool},
+        },
+    };
+
+    #[test]
+    fn set_reserve_floors_owner_only() {
+        let mut pool = TestPool::new(Fees::default());
+        let mut other = TestAccount::signer();
+        let account_infos = [pool.swap.info(), pool.pool_fee_account.info(), other.info()];
+        let error = process(&account_infos, |ctx| {
+            process_set_reserve_floors(ctx, 1_000, 2_000)
+        })
+        .unwrap_err();
+        assert_eq!(ProgramError::from(error), SwapError::InvalidOwner.into());
+
+        let mut pool_fee_account = TestAccount::token_account(pool.pool_mint.key, pool.user.key, 0);
+        let account_infos = [pool.swap.info(), pool_fee_account.info(), pool.user.info()];
+        let error = process(&account_infos, |ctx| {
+            process_set_reserve_floors(ctx, 1_000, 2_000)
+        })
+        .unwrap_err();
+        assert_eq!(
+            ProgramError::from(error),
+            SwapError::IncorrectFeeAccount.into()
+        );
+
+        let account_infos = [
+            pool.swap.info(),
+            pool.pool_fee_account.info(),
+            pool.user.info(),
+        ];
+        process(&account_infos, |ctx| {
+            process_set_reserve_floors(ctx, 1_000, 2_000)
+        })
+        .unwrap();
+        let state = pool.state();
+        assert_eq!(state.reserve_floor_a, 1_000);
+        assert_eq!(state.reserve_floor_b, 2_000);
+    }
+}
@@ -0,0 +1,718 @@
+//! Swap tokens using the pool
+
+use {
+    crate::{
+        curve::{
+            base::{SwapCurve, SwapResult},
+            calculator::TradeDirection,
+            fees::{Fees, BPS_DENOMINATOR},
+        },
+        errors::SwapError,
+        instructions::{to_u64, token_mint_to, token_transfer},
+        state::{SwapStateRef, SwapStateRefMut},
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::{Mint, Token, TokenAccount},
+    spl_math::precise_number::PreciseNumber,
+};
+
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    /// CHECK: swap state, read and updated in place through
+    /// `SwapStateRefMut`, which checks its discriminator
+    #[account(mut, owner = crate::ID)]
+    pub swap: UncheckedAccount<'info>,
+    /// CHECK: program derived authority over the pool's token accounts and
+    /// pool mint, never read, checked against the bump seed of the swap
+    /// state by `validate`
+    pub authority: UncheckedAccount<'info>,
+    /// Owner or delegate of the source account
+    pub user_transfer_authority: Signer<'info>,
+    /// User's account to swap from
+    #[account(mut)]
+    pub source: Account<'info, TokenAccount>,
+    /// Pool account receiving the source tokens, either token A or B
+    #[account(mut)]
+    pub swap_source: Account<'info, TokenAccount>,
+    /// Pool account paying out the destination tokens, the other of token A
+    /// or B
+    #[account(mut)]
+    pub swap_destination: Account<'info, TokenAccount>,
+    /// User's account to receive the swapped tokens
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    /// Pool token mint, to generate the owner trading fee
+    #[account(mut)]
+    pub pool_mint: Account<'info, Mint>,
+    /// Pool token account receiving the owner trading fee
+    #[account(mut)]
+    pub pool_fee_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+    // An optional host fee account, for the pool mint, may be passed as the
+    // first remaining account to receive a portion of the owner fee
+}
+
+impl<'info> Swap<'info> {
+    /// Check the given accounts against the pool's state, returning the
+    /// direction of the trade
+    pub fn validate(&self, swap: &SwapStateRef) -> Result<TradeDirection> {
+        let authority = Pubkey::create_program_address(
+            &[self.swap.key.as_ref(), &[swap.bump_seed()]],
+            &crate::ID,
+        )
+        .map_err(|_| SwapError::InvalidProgramAddress)?;
+        if self.authority.key() != authority {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        let trade_direction = if self.swap_source.key() == swap.token_a()
+            && self.swap_destination.key() == swap.token_b()
+        {
+            TradeDirection::AtoB
+        } else if self.swap_source.key() == swap.token_b()
+            && self.swap_destination.key() == swap.token_a()
+        {
+            TradeDirection::BtoA
+        } else {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        };
+        if self.source.key() == self.swap_source.key()
+            || self.destination.key() == self.swap_destination.key()
+        {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if self.pool_mint.key() != swap.pool_mint() {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.pool_fee_account.key() != swap.pool_fee_account() {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+        // the fee account could have been closed and recreated for another
+        // mint, which would lose the fees minted to it
+        if self.pool_fee_account.mint != swap.pool_mint() {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.token_program.key() != swap.token_program_id() {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        Ok(trade_direction)
+    }
+}
+
+pub fn process_swap<'info>(
+    ctx: Context<'_, '_, '_, 'info, Swap<'info>>,
+    amount_in: u64,
+    minimum_amount_out: u64,
+) -> Result<()> {
+    let swap_info = ctx.accounts.swap.to_account_info();
+    let mut swap_data = swap_info.try_borrow_mut_data()?;
+    let mut swap = SwapStateRefMut::load(&mut swap_data)?;
+    let trade_direction = ctx.accounts.validate(&swap.to_ref())?;
+    check_amount_in(amount_in, ctx.accounts.swap_source.amount)?;
+    swap.lock()?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let swap_curve = swap.to_ref().swap_curve_at(now)?;
+    let swap_with_fees = |fees: &Fees| {
+        swap_curve
+            .swap(
+                u128::from(amount_in),
+                u128::from(ctx.accounts.swap_source.amount),
+                u128::from(ctx.accounts.swap_destination.amount),
+                trade_direction,
+                fees,
+            )
+            .ok_or(SwapError::ZeroTradingTokens)
+    };
+    let estimate = swap_with_fees(&swap.to_ref().fees())?;
+    let result = swap_with_fees(&swap_fees(&swap.to_ref(), trade_direction, &estimate))?;
+    if result.destination_amount_swapped < u128::from(minimum_amount_out) {
+        return Err(SwapError::ExceededSlippage.into());
+    }
+
+    settle_swap(ctx, &mut swap, &swap_curve, &result, trade_direction, now)
+}
+
+/// Swaps at most `maximum_amount_in` of the source token for exactly
+/// `amount_out` of the destination token, only available on curves which
+/// support exact-out calculations
+pub fn process_swap_exact_out<'info>(
+    ctx: Context<'_, '_, '_, 'info, Swap<'info>>,
+    amount_out: u64,
+    maximum_amount_in: u64,
+) -> Result<()> {
+    let swap_info = ctx.accounts.swap.to_account_info();
+    let mut swap_data = swap_info.try_borrow_mut_data()?;
+    let mut swap = SwapStateRefMut::load(&mut swap_data)?;
+    let trade_direction = ctx.accounts.validate(&swap.to_ref())?;
+    let now = Clock::get()?.unix_timestamp;
+    let swap_curve = swap.to_ref().swap_curve_at(now)?;
+    if !swap_curve.calculator.supports_exact_out() {
+        return Err(SwapError::UnsupportedCurveOperation.into());
+    }
+    swap.lock()?;
+
+    let swap_with_fees = |fees: &Fees| {
+        swap_curve
+            .swap_exact_out(
+                u128::from(amount_out),
+                u128::from(ctx.accounts.swap_source.amount),
+                u128::from(ctx.accounts.swap_destination.amount),
+                trade_direction,
+                fees,
+            )
+            .ok_or(SwapError::ZeroTradingTokens)
+    };
+    let estimate = swap_with_fees(&swap.to_ref().fees())?;
+    let result = swap_with_fees(&swap_fees(&swap.to_ref(), trade_direction, &estimate))?;
+    if result.source_amount_swapped > u128::from(maximum_amount_in) {
+        return Err(SwapError::ExceededSlippage.into());
+    }
+
+    settle_swap(ctx, &mut swap, &swap_curve, &result, trade_direction, now)
+}
+
+/// Fees for a swap in the given direction, depending on the balances of the
+/// pool after the swap for pools with dynamic fees. Those are the balances
+/// after `estimate`, the swap calculated with the base fees.
+fn swap_fees(swap: &SwapStateRef, trade_direction: TradeDirection, estimate: &SwapResult) -> Fees {
+    let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+        TradeDirection::AtoB => (
+            estimate.new_swap_source_amount,
+            estimate.new_swap_destination_amount,
+        ),
+        TradeDirection::BtoA => (
+            estimate.new_swap_destination_amount,
+            estimate.new_swap_source_amount,
+        ),
+    };
+    swap.fees_for_reserves(swap_token_a_amount, swap_token_b_amount)
+}
+
+/// Pool tokens to mint for the owner fee of a swap, shared with the host.
+/// Nothing is minted for pools with owner fees disabled.
+fn owner_fee_pool_tokens(
+    swap_curve: &SwapCurve,
+    fees: &Fees,
+    owner_fee: u128,
+    swap_token_a_amount: u128,
+    swap_token_b_amount: u128,
+    pool_supply: u128,
+    trade_direction: TradeDirection,
+) -> std::result::Result<u128, SwapError> {
+    if !fees.owner_fees_enabled() {
+        return Ok(0);
+    }
+    swap_curve
+        .owner_fee_to_pool_tokens(
+            owner_fee,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+        .ok_or(SwapError::FeeCalculationFailure)
+}
+
+/// Reject a swap of nothing, and one which the source reserve couldn't hold,
+/// before running the curve calculation
+fn check_amount_in(amount_in: u64, swap_source_amount: u64) -> std::result::Result<(), SwapError> {
+    if amount_in == 0 {
+        return Err(SwapError::ZeroTradingTokens);
+    }
+    if swap_source_amount.checked_add(amount_in).is_none() {
+        return Err(SwapError::InvalidInput);
+    }
+    Ok(())
+}
+
+/// Reject swaps where the fees take the whole source amount, which would
+/// charge the trader without paying out any destination tokens
+fn check_nonzero_output(result: &SwapResult) -> std::result::Result<(), SwapError> {
+    if result.destination_amount_swapped == 0 {
+        return Err(SwapError::ZeroTradingTokens);
+    }
+    Ok(())
+}
+
+/// Relative tolerance of the owner fee audit, in basis points of the fee,
+/// covering the difference between pricing a single-sided deposit on the
+/// curve and valuing its pool tokens at the spot price
+const OWNER_FEE_TOLERANCE_BPS: u128 = 100;
+
+/// Defensive check that the pool tokens minted for the owner fee aren't worth
+/// more than the fee itself, which would dilute liquidity providers.
+///
+/// The pool tokens are converted back into both trading tokens, with the
+/// minted tokens included in the supply, and valued in token A at the spot
+/// price, as is the owner fee. Reserves are given after the swap has been
+/// applied.
+fn check_owner_fee_value(
+    swap_curve: &SwapCurve,
+    owner_fee: u128,
+    pool_token_amount: u128,
+    swap_token_a_amount: u128,
+    swap_token_b_amount: u128,
+    pool_supply: u128,
+    trade_direction: TradeDirection,
+) -> std::result::Result<(), SwapError> {
+    let pool_tokens_value = pool_supply
+        .checked_add(pool_token_amount)
+        .and_then(|pool_supply| {
+            swap_curve.lp_value_in_token_a(
+                pool_token_amount,
+                pool_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            )
+        })
+        .ok_or(SwapError::CalculationFailure)?;
+    let owner_fee_value = match trade_direction {
+        TradeDirection::AtoB => Some(owner_fee),
+        TradeDirection::BtoA => swap_curve
+            .calculator
+            .spot_price(swap_token_a_amount, swap_token_b_amount)
+            .and_then(|spot_price| {
+                PreciseNumber::new(owner_fee)?
+                    .checked_div(&spot_price)?
+                    .ceiling()?
+                    .to_imprecise()
+            }),
+    }
+    .ok_or(SwapError::CalculationFailure)?;
+    let tolerance = owner_fee_value
+        .checked_mul(OWNER_FEE_TOLERANCE_BPS)
+        .map(|tolerance| tolerance / u128::from(BPS_DENOMINATOR) + 1)
+        .ok_or(SwapError::CalculationFailure)?;
+    if pool_tokens_value > owner_fee_value.saturating_add(tolerance) {
+        return Err(SwapError::CalculationFailure);
+    }
+    Ok(())
+}
+
+/// Pool tokens to mint for the owner fee of a swap, in the source token of
+/// `trade_direction`, checked against the value of the fee, and the host's
+/// share of them. Reserves are given after the swap has been applied.
+pub fn owner_fee_mint_amounts(
+    swap_curve: &SwapCurve,
+    fees: &Fees,
+    owner_fee: u128,
+    swap_token_a_amount: u128,
+    swap_token_b_amount: u128,
+    pool_supply: u128,
+    trade_direction: TradeDirection,
+) -> std::result::Result<(u128, u128), SwapError> {
+    let pool_token_amount = owner_fee_pool_tokens(
+        swap_curve,
+        fees,
+        owner_fee,
+        swap_token_a_amount,
+        swap_token_b_amount,
+        pool_supply,
+        trade_direction,
+    )?;
+    if pool_token_amount == 0 {
+        return Ok((0, 0));
+    }
+    check_owner_fee_value(
+        swap_curve,
+        owner_fee,
+        pool_token_amount,
+        swap_token_a_amount,
+        swap_token_b_amount,
+        pool_supply,
+        trade_direction,
+    )?;
+    let host_fee = fees
+        .host_fee(pool_token_amount)
+        .ok_or(SwapError::FeeCalculationFailure)?;
+    Ok((pool_token_amount, host_fee))
+}
+
+/// Moves the tokens for a calculated swap: the source tokens into the pool,
+/// the owner and host fees as pool tokens, and the destination tokens out,
+/// then releases the pool lock
+fn settle_swap<'info>(
+    ctx: Context<'_, '_, '_, 'info, Swap<'info>>,
+    swap: &mut SwapStateRefMut,
+    swap_curve: &SwapCurve,
+    result: &SwapResult,
+    trade_direction: TradeDirection,
+    now: i64,
+) -> Result<()> {
+    check_nonzero_output(result)?;
+    let swap_ref = swap.to_ref();
+    swap_ref.check_reserve_floor(result.new_swap_destination_amount, trade_direction)?;
+    let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+        TradeDirection::AtoB => (
+            result.new_swap_source_amount,
+            result.new_swap_destination_amount,
+        ),
+        TradeDirection::BtoA => (
+            result.new_swap_destination_amount,
+            result.new_swap_source_amount,
+        ),
+    };
+    let pool_supply = u128::from(ctx.accounts.pool_mint.supply);
+    let (mut pool_token_amount, host_fee) = owner_fee_mint_amounts(
+        swap_curve,
+        &swap_ref.fees(),
+        result.owner_fee,
+        swap_token_a_amount,
+        swap_token_b_amount,
+        pool_supply,
+        trade_direction,
+    )?;
+
+    let swap_key = ctx.accounts.swap.key();
+    let seeds: &[&[u8]] = &[swap_key.as_ref(), &[swap_ref.bump_seed()]];
+    let token_program = ctx.accounts.token_program.to_account_info();
+    let authority = ctx.accounts.authority.to_account_info();
+
+    let balances_before = (
+        ctx.accounts.swap_source.amount,
+        ctx.accounts.swap_destination.amount,
+    );
+    token_transfer(
+        token_program.clone(),
+        ctx.accounts.source.to_account_info(),
+        ctx.accounts.swap_source.to_account_info(),
+        ctx.accounts.user_transfer_authority.to_account_info(),
+        &[],
+        to_u64(result.source_amount_swapped)?,
+    )?;
+
+    if pool_token_amount > 0 {
+        if let Some(host_fee_account_info) = ctx.remaining_accounts.first() {
+            let host_fee_account = Account::<TokenAccount>::try_from(host_fee_account_info)?;
+            if host_fee_account.mint != ctx.accounts.pool_mint.key() {
+                return Err(SwapError::IncorrectPoolMint.into());
+            }
+            if host_fee > 0 {
+                pool_token_amount = pool_token_amount
+                    .checked_sub(host_fee)
+                    .ok_or(SwapError::FeeCalculationFailure)?;
+                token_mint_to(
+                    token_program.clone(),
+                    ctx.accounts.pool_mint.to_account_info(),
+                    host_fee_account_info.clone(),
+                    authority.clone(),
+                    &[seeds],
+                    to_u64(host_fee)?,
+                )?;
+            }
+        }
+        token_mint_to(
+            token_program.clone(),
+            ctx.accounts.pool_mint.to_account_info(),
+            ctx.accounts.pool_fee_account.to_account_info(),
+            authority.clone(),
+            &[seeds],
+            to_u64(pool_token_amount)?,
+        )?;
+    }
+
+    token_transfer(
+        token_program,
+        ctx.accounts.swap_destination.to_account_info(),
+        ctx.accounts.destination.to_account_info(),
+        authority,
+        &[seeds],
+        to_u64(result.destination_amount_swapped)?,
+    )?;
+
+    swap.accrue_fees(result.trade_fee, trade_direction, pool_token_amount)?;
+    swap.update_price_oracle(swap_curve, now);
+    ctx.accounts.swap_source.reload()?;
+    ctx.accounts.swap_destination.reload()?;
+    let balances_after = (
+        ctx.accounts.swap_source.amount,
+        ctx.accounts.swap_destination.amount,
+    );
+    let (balances_before, balances_after) = match trade_direction {
+        TradeDirection::AtoB => (balances_before, balances_after),
+        TradeDirection::BtoA => (
+            (balances_before.1, balances_before.0),
+            (balances_after.1, balances_after.0),
+        ),
+    };
+    swap.apply_swap(result, trade_direction, balances_before, balances_after)?;
+    swap.unlock();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        curve::{
+            base::{CurveType, InitialSupply},
+            constant_price::ConstantPriceCurve,
+            stable::StableCurve,
+        },
+        instructions::test::{process, TestPool},
+    };
+    use std::sync::Arc;
+
+    /// Swap token A from the user's account for token B
+    fn swap(pool: &mut TestPool, amount_in: u64, minimum_amount_out: u64) -> Result<()> {
+        let account_infos = [
+            pool.swap.info(),
+            pool.authority.info(),
+            pool.user.info(),
+            pool.user_token_a.info(),
+            pool.token_a.info(),
+            pool.token_b.info(),
+            pool.user_token_b.info(),
+            pool.pool_mint.info(),
+            pool.pool_fee_account.info(),
+            pool.token_program.info(),
+        ];
+        process(&account_infos, |ctx| {
+            process_swap(ctx, amount_in, minimum_amount_out)
+        })
+    }
+
+    #[test]
+    fn swap_reentrancy_guard() {
+        let mut pool = TestPool::new(Fees::default().with_trade_fee_bps(25));
+        swap(&mut pool, 1_000, 1).unwrap();
+        assert!(!pool.state().in_progress);
+
+        // a reentrant call, made while another operation holds the guard, is
+        // rejected before moving any tokens
+        pool.set_state(|state| state.in_progress = true);
+        let token_a_amount = pool.user_token_a.token_amount();
+        let error = swap(&mut pool, 1_000, 1).unwrap_err();
+        assert_eq!(ProgramError::from(error), SwapError::InvalidInput.into());
+        assert_eq!(pool.user_token_a.token_amount(), token_a_amount);
+    }
+
+    #[test]
+    fn failed_swap_keeps_reentrancy_guard() {
+        // an error after taking the guard doesn't release it, which is fine as
+        // the runtime discards every change made by a failed instruction
+        let mut pool = TestPool::new(Fees::default());
+        let error = swap(&mut pool, 1_000, 1_000).unwrap_err();
+        assert_eq!(
+            ProgramError::from(error),
+            SwapError::ExceededSlippage.into()
+        );
+        assert!(pool.state().in_progress);
+    }
+
+    #[test]
+    fn swap_exact_out_reentrancy_guard() {
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Arc::new(ConstantPriceCurve { token_b_price: 1 }),
+            ..SwapCurve::default()
+        };
+        let mut pool = TestPool::with_curve(swap_curve, Fees::default());
+        let swap_exact_out = |pool: &mut TestPool| {
+            let account_infos = [
+                pool.swap.info(),
+                pool.authority.info(),
+                pool.user.info(),
+                pool.user_token_a.info(),
+                pool.token_a.info(),
+                pool.token_b.info(),
+                pool.user_token_b.info(),
+                pool.pool_mint.info(),
+                pool.pool_fee_account.info(),
+                pool.token_program.info(),
+            ];
+            process(&account_infos, |ctx| {
+                process_swap_exact_out(ctx, 1_000, 1_000)
+            })
+        };
+        swap_exact_out(&mut pool).unwrap();
+        assert!(!pool.state().in_progress);
+        assert_eq!(pool.user_token_b.token_amount(), TestPool::RESERVE + 1_000);
+
+        pool.set_state(|state| state.in_progress = true);
+        let error = swap_exact_out(&mut pool).unwrap_err();
+        assert_eq!(ProgramError::from(error), SwapError::InvalidInput.into());
+    }
+
+    #[test]
+    fn dynamic_fees_scale_with_imbalance_left() {
+        let fees = Fees::default().with_trade_fee_bps(30);
+        // a trade draining a balanced pool
+        let mut draining = TestPool::new(fees.clone());
+        draining.set_state(|state| state.imbalance_multiplier_bps = 10_000);
+        swap(&mut draining, 100_000, 1).unwrap();
+        // and the same trade rebalancing a pool short of token A
+        let mut rebalancing = TestPool::new(fees.clone());
+        rebalancing.token_a.set_token_amount(900_000);
+        rebalancing.token_b.set_token_amount(1_100_000);
+        rebalancing.set_state(|state| {
+            state.imbalance_multiplier_bps = 10_000;
+            state.token_a_reserve = 900_000;
+            state.token_b_reserve = 1_100_000;
+        });
+        swap(&mut rebalancing, 100_000, 1).unwrap();
+
+        let base_fee = fees.trading_fee(100_000).unwrap();
+        let draining_fee = draining.state().cumulative_fees().trade_fee_a;
+        let rebalancing_fee = rebalancing.state().cumulative_fees().trade_fee_a;
+        assert!(draining_fee > rebalancing_fee);
+        assert!(draining_fee > base_fee);
+    }
+
+    #[test]
+    fn owner_fee_pool_tokens() {
+        let swap_curve = SwapCurve::default();
+        let (swap_source_amount, swap_destination_amount) = (1_000_000, 1_000_000);
+        let pool_supply = 1_000_000;
+        let swap = |fees: &Fees| {
+            let result = swap_curve
+                .swap(
+                    100_000,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    TradeDirection::AtoB,
+                    fees,
+                )
+                .unwrap();
+            super::owner_fee_pool_tokens(
+                &swap_curve,
+                fees,
+                result.owner_fee,
+                result.new_swap_source_amount,
+                result.new_swap_destination_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            )
+            .unwrap()
+        };
+
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        assert!(swap(&fees) > 0);
+
+        // zero owner fee mints nothing to the owner
+        let fees = Fees::default().with_trade_fee_bps(25);
+        assert!(!fees.owner_fees_enabled());
+        assert_eq!(swap(&fees), 0);
+    }
+
+    #[test]
+    fn check_amount_in() {
+        assert_eq!(
+            super::check_amount_in(0, 1_000_000),
+            Err(SwapError::ZeroTradingTokens)
+        );
+        assert_eq!(super::check_amount_in(1_000, 1_000_000), Ok(()));
+        // more than the reserve is fine, as long as the reserve can hold it
+        assert_eq!(
+            super::check_amount_in(u64::MAX - 1_000_000, 1_000_000),
+            Ok(())
+        );
+        assert_eq!(
+            super::check_amount_in(u64::MAX, 1_000_000),
+            Err(SwapError::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn check_nonzero_output() {
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+            initial_supply: InitialSupply::default(),
+        };
+        let fees = Fees::default()
+            .with_trade_fee_bps(5_000)
+            .with_owner_trade_fee_bps(1_000);
+        let swap = |source_amount| {
+            swap_curve
+                .swap(
+                    source_amount,
+                    1_000_000,
+                    1_000_000,
+                    TradeDirection::AtoB,
+                    &fees,
+                )
+                .unwrap()
+        };
+
+        // the minimum fees take the whole dust trade
+        let result = swap(2);
+        assert_eq!(result.source_amount_swapped, 2);
+        assert_eq!(result.destination_amount_swapped, 0);
+        assert_eq!(
+            super::check_nonzero_output(&result),
+            Err(SwapError::ZeroTradingTokens)
+        );
+
+        let result = swap(1_000);
+        assert_eq!(super::check_nonzero_output(&result), Ok(()));
+    }
+
+    #[test]
+    fn check_owner_fee_value() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let pool_supply = 1_000_000;
+        for (trade_direction, swap_token_a_amount, swap_token_b_amount) in [
+            (TradeDirection::AtoB, 1_000_000, 4_000_000),
+            (TradeDirection::BtoA, 4_000_000, 1_000_000),
+        ] {
+            let (swap_source_amount, swap_destination_amount) = match trade_direction {
+                TradeDirection::AtoB => (swap_token_a_amount, swap_token_b_amount),
+                TradeDirection::BtoA => (swap_token_b_amount, swap_token_a_amount),
+            };
+            let result = swap_curve
+                .swap(
+                    1_000_000,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    trade_direction,
+                    &fees,
+                )
+                .unwrap();
+            let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+                TradeDirection::AtoB => (
+                    result.new_swap_source_amount,
+                    result.new_swap_destination_amount,
+                ),
+                TradeDirection::BtoA => (
+                    result.new_swap_destination_amount,
+                    result.new_swap_source_amount,
+                ),
+            };
+            let pool_token_amount = super::owner_fee_pool_tokens(
+                &swap_curve,
+                &fees,
+                result.owner_fee,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                trade_direction,
+            )
+            .unwrap();
+            assert!(pool_token_amount > 0);
+            let check = |pool_token_amount| {
+                super::check_owner_fee_value(
+                    &swap_curve,
+                    result.owner_fee,
+                    pool_token_amount,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    pool_supply,
+                    trade_direction,
+                )
+            };
+            assert_eq!(check(pool_token_amount), Ok(()));
+
+            // minting twice the pool tokens would dilute liquidity providers
+            assert_eq!(
+                check(pool_token_amount * 2),
+                Err(SwapError::CalculationFailure)
+            );
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Helpers to run instructions in unit tests, with the token program and mock
+//! flash swap callbacks executed in process through syscall stubs
+
+use {
+    crate::{
+        curve::{base::SwapCurve, fees::Fees},
+        state::SwapState,
+    },
+    anchor_lang::{
+        prelude::*,
+        solana_program::{
+            entrypoint::{ProgramResult, SUCCESS},
+            instruction::Instruction,
+            program_option::COption,
+            program_pack::Pack,
+            program_stubs::{set_syscall_stubs, SyscallStubs},
+        },
+        AccountsExit,
+    },
+    anchor_spl::token::spl_token,
+    std::{
+        collections::BTreeMap,
+        sync::{Mutex, MutexGuard},
+    },
+};
+
+/// Mock flash swap callback program, repaying twice the lent amount from its
+/// first account to its second, authorized by its third
+pub const REPAY_CALLBACK_ID: Pubkey = Pubkey::new_from_array([1; 32]);
+
+/// Mock flash swap callback program, returning exactly the lent amount, as
+/// `REPAY_CALLBACK_ID` does
+pub const RETURN_CALLBACK_ID: Pubkey = Pubkey::new_from_array([3; 32]);
+
+/// Mock flash swap callback program which keeps the lent tokens
+pub const NO_REPAY_CALLBACK_ID: Pubkey = Pubkey::new_from_array([2; 32]);
+
+/// Unix timestamp of the clock seen by instructions
+pub const NOW: i64 = 1_000;
+
+/// Syscall stubs are global, so tests which install their own must hold this
+/// lock for as long as they are installed
+static SYSCALL_STUBS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Install the given syscall stubs until the returned guard is dropped
+pub fn install_syscall_stubs(stubs: Box<dyn SyscallStubs>) -> SyscallStubsGuard {
+    let lock = SYSCALL_STUBS_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let previous_stubs = Some(set_syscall_stubs(stubs));
+    SyscallStubsGuard {
+        previous_stubs,
+        _lock: lock,
+    }
+}
+
+/// Restores the previous syscall stubs when dropped
+pub struct SyscallStubsGuard {
+    previous_stubs: Option<Box<dyn SyscallStubs>>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Drop for SyscallStubsGuard {
+    fn drop(&mut self) {
+        if let Some(previous_stubs) = self.previous_stubs.take() {
+            set_syscall_stubs(previous_stubs);
+        }
+    }
+}
+
+/// Stubs executing invocations of the token program and the mock callbacks,
+/// with the clock at `NOW`
+struct TestSyscallStubs;
+
+impl SyscallStubs for TestSyscallStubs {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let accounts = instruction
+            .accounts
+            .iter()
+            .map(|meta| {
+                let mut account_info = account_infos
+                    .iter()
+                    .find(|account_info| *account_info.key == meta.pubkey)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?
+                    .clone();
+                // program derived addresses sign with the seeds of the caller
+                account_info.is_signer |= meta.is_signer
+                    && signers_seeds.iter().any(|seeds| {
+                        Pubkey::create_program_address(seeds, &crate::ID) == Ok(meta.pubkey)
+                    });
+                Ok(account_info)
+            })
+            .collect::<std::result::Result<Vec<_>, ProgramError>>()?;
+        match instruction.program_id {
+            program_id if program_id == spl_token::ID => {
+                spl_token::processor::Processor::process(&program_id, &accounts, &instruction.data)
+            }
+            REPAY_CALLBACK_ID | RETURN_CALLBACK_ID => {
+                let amount_out = u64::from_le_bytes(
+                    instruction.data[..8]
+                        .try_into()
+                        .map_err(|_| ProgramError::InvalidInstructionData)?,
+                );
+                let repay = spl_token::instruction::transfer(
+                    &spl_token::ID,
+                    accounts[0].key,
+                    accounts[1].key,
+                    accounts[2].key,
+                    &[],
+                    if instruction.program_id == REPAY_CALLBACK_ID {
+                        2 * amount_out
+                    } else {
+                        amount_out
+                    },
+                )?;
+                self.sol_invoke_signed(&repay, &accounts, &[])
+            }
+            NO_REPAY_CALLBACK_ID => Ok(()),
+            _ => Err(ProgramError::IncorrectProgramId),
+        }
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        // SAFETY: the runtime passes a pointer to a `Clock` to fill in
+        unsafe {
+            *(var_addr as *mut Clock) = Clock {
+                unix_timestamp: NOW,
+                ..Clock::default()
+            };
+        }
+        SUCCESS
+    }
+}
+
+/// Data, lamports and metadata of an account, to lend out as an
+/// `AccountInfo`
+pub struct TestAccount {
+    pub key: Pubkey,
+    pub owner: Pubkey,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub is_signer: bool,
+    pub executable: bool,
+}
+
+impl TestAccount {
+    fn new(owner: Pubkey, data: Vec<u8>) -> Self {
+        Self {
+            key: Pubkey::new_unique(),
+            owner,
+            lamports: 1_000_000_000,
+            data,
+            is_signer: false,
+            executable: false,
+        }
+    }
+
+    pub fn signer() -> Self {
+        Self {
+            is_signer: true,
+            ..Self::new(Pubkey::default(), vec![])
+        }
+    }
+
+    pub fn program(key: Pubkey) -> Self {
+        Self {
+            key,
+            executable: true,
+            ..Self::new(Pubkey::default(), vec![])
+        }
+    }
+
+    pub fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Self {
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account {
+            mint,
+            owner,
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..spl_token::state::Account::default()
+        }
+        .pack_into_slice(&mut data);
+        Self::new(spl_token::ID, data)
+    }
+
+    pub fn mint(mint_authority: Pubkey, supply: u64) -> Self {
+        let mut data = vec![0; spl_token::state::Mint::LEN];
+        spl_token::state::Mint {
+            mint_authority: COption::Some(mint_authority),
+            supply,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut data);
+        Self::new(spl_token::ID, data)
+    }
+
+    /// Lend out the account, writable
+    pub fn info(&mut self) -> AccountInfo<'_> {
+        AccountInfo::new(
+            &self.key,
+            self.is_signer,
+            true,
+            &mut self.lamports,
+            &mut self.data,
+            &self.owner,
+            self.executable,
+            0,
+        )
+    }
+
+    pub fn token_amount(&self) -> u64 {
+        spl_token::state::Account::unpack(&self.data)
+            .unwrap()
+            .amount
+    }
+
+    pub fn set_token_amount(&mut self, amount: u64) {
+        let mut account = spl_token::state::Account::unpack(&self.data).unwrap();
+        account.amount = amount;
+        account.pack_into_slice(&mut self.data);
+    }
+
+    pub fn mint_supply(&self) -> u64 {
+        spl_token::state::Mint::unpack(&self.data).unwrap().supply
+    }
+}
+
+/// Pool of 1,000,000 token A and B, on a constant product curve unless built
+/// `with_curve`, and a user holding as many of each token and all the pool
+/// tokens, who also owns the pool through its pool fee account
+pub struct TestPool {
+    pub swap: TestAccount,
+    pub authority: TestAccount,
+    pub token_a: TestAccount,
+    pub token_b: TestAccount,
+    pub pool_mint: TestAccount,
+    pub pool_fee_account: TestAccount,
+    pub user: TestAccount,
+    pub user_token_a: TestAccount,
+    pub user_token_b: TestAccount,
+    pub user_pool_token: TestAccount,
+    pub token_program: TestAccount,
+}
+
+impl TestPool {
+    pub const RESERVE: u64 = 1_000_000;
+
+    pub fn new(fees: Fees) -> Self {
+        Self::with_curve(SwapCurve::default(), fees)
+    }
+
+    pub fn with_curve(swap_curve: SwapCurve, fees: Fees) -> Self {
+        let mut swap = TestAccount::new(crate::ID, vec![]);
+        let (authority_key, bump_seed) =
+            Pubkey::find_program_address(&[swap.key.as_ref()], &crate::ID);
+        let authority = TestAccount {
+            key: authority_key,
+            ..TestAccount::new(Pubkey::default(), vec![])
+        };
+        let user = TestAccount::signer();
+        let (token_a_mint, token_b_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let token_a = TestAccount::token_account(token_a_mint, authority.key, Self::RESERVE);
+        let token_b = TestAccount::token_account(token_b_mint, authority.key, Self::RESERVE);
+        let pool_mint = TestAccount::mint(authority.key, Self::RESERVE);
+        let pool_fee_account = TestAccount::token_account(pool_mint.key, user.key, 0);
+
+        let state = SwapState {
+            is_initialized: true,
+            bump_seed,
+            token_program_id: spl_token::ID,
+            token_a: token_a.key,
+            token_b: token_b.key,
+            pool_mint: pool_mint.key,
+            token_a_mint,
+            token_b_mint,
+            pool_fee_account: pool_fee_account.key,
+            fees,
+            curve_type: swap_curve.curve_type,
+            curve_parameters: swap_curve.pack_parameters(),
+            token_a_reserve: Self::RESERVE,
+            token_b_reserve: Self::RESERVE,
+            last_update_ts: NOW,
+            ..SwapState::default()
+        };
+        state.try_serialize(&mut swap.data).unwrap();
+
+        Self {
+            user_token_a: TestAccount::token_account(token_a_mint, user.key, Self::RESERVE),
+            user_token_b: TestAccount::token_account(token_b_mint, user.key, Self::RESERVE),
+            user_pool_token: TestAccount::token_account(pool_mint.key, user.key, Self::RESERVE),
+            token_program: TestAccount::program(spl_token::ID),
+            swap,
+            authority,
+            token_a,
+            token_b,
+            pool_mint,
+            pool_fee_account,
+            user,
+        }
+    }
+
+    pub fn state(&self) -> SwapState {
+        SwapState::try_deserialize(&mut self.swap.data.as_slice()).unwrap()
+    }
+
+    pub fn set_state(&mut self, update: impl FnOnce(&mut SwapState)) {
+        let mut state = self.state();
+        update(&mut state);
+        let mut data = vec![];
+        state.try_serialize(&mut data).unwrap();
+        self.swap.data[..data.len()].copy_from_slice(&data);
+    }
+}
+
+/// Run an instruction on the given accounts as the program entrypoint would:
+/// deserialize and check the accounts, process the instruction, then persist
+/// the accounts. Accounts beyond those of `T` are given as remaining accounts.
+pub fn process<'info, T>(
+    account_infos: &[AccountInfo<'info>],
+    process: impl FnOnce(Context<'_, '_, '_, 'info, T>) -> Result<()>,
+) -> Result<()>
+where
+    T: Accounts<'info> + AccountsExit<'info>,
+{
+    let _stubs = install_syscall_stubs(Box::new(TestSyscallStubs));
+    let mut remaining_accounts = account_infos;
+    let mut bumps = BTreeMap::new();
+    let mut accounts = T::try_accounts(&crate::ID, &mut remaining_accounts, &[], &mut bumps)?;
+    process(Context::new(
+        &crate::ID,
+        &mut accounts,
+        remaining_accounts,
+        bumps,
+    ))?;
+    accounts.exit(&crate::ID)
+}
@@ -0,0 +1,286 @@
+//! Withdraw both types of tokens from the pool
+
+use {
+    crate::{
+        curve::{calculator::RoundDirection, fees::Fees},
+        errors::SwapError,
+        instructions::{to_u64, token_burn, token_transfer},
+        state::SwapState,
+    },
+    anchor_lang::prelude::*,
+    anchor_spl::token::{Mint, Token, TokenAccount},
+};
+
+#[derive(Accounts)]
+pub struct WithdrawAllTokenTypes<'info> {
+    #[account(mut)]
+    pub swap: Account<'info, SwapState>,
+    /// CHECK: program derived authority over the pool's token accounts and
+    /// pool mint, never read
+    #[account(seeds = [swap.key().as_ref()], bump = swap.bump_seed)]
+    pub authority: UncheckedAccount<'info>,
+    /// Owner or delegate of the pool token source account
+    pub user_transfer_authority: Signer<'info>,
+    #[account(mut)]
+    pub pool_mint: Account<'info, Mint>,
+    /// User's pool token account to burn from
+    #[account(mut)]
+    pub source: Account<'info, TokenAccount>,
+    /// Pool token A account
+    #[account(mut)]
+    pub token_a: Account<'info, TokenAccount>,
+    /// Pool token B account
+    #[account(mut)]
+    pub token_b: Account<'info, TokenAccount>,
+    /// User's token A account
+    #[account(mut)]
+    pub destination_a: Account<'info, TokenAccount>,
+    /// User's token B account
+    #[account(mut)]
+    pub destination_b: Account<'info, TokenAccount>,
+    /// Pool token account receiving the owner withdraw fee
+    #[account(mut)]
+    pub pool_fee_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> WithdrawAllTokenTypes<'info> {
+    /// Check the given accounts against the pool's state
+    pub fn validate(&self) -> Result<()> {
+        let swap = &self.swap;
+        if self.token_a.key() != swap.token_a || self.token_b.key() != swap.token_b {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if self.destination_a.key() == self.token_a.key()
+            || self.destination_b.key() == self.token_b.key()
+        {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if self.pool_mint.key() != swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.pool_fee_account.key() != swap.pool_fee_account {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+        if self.pool_fee_account.mint != swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if self.token_program.key() != swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        Ok(())
+    }
+}
+
+/// Owner withdraw fee, in pool tokens, taken from a withdrawal of
+/// `pool_token_amount`. Withdrawals from the fee account itself are free.
+fn owner_withdraw_fee(
+    fees: &Fees,
+    pool_token_amount: u64,
+    from_fee_account: bool,
+) -> std::result::Result<u64, SwapError> {
+    if from_fee_account {
+        return Ok(0);
+    }
+    let withdraw_fee = fees
+        .owner_withdraw_fee(u128::from(pool_token_amount))
+        .ok_or(SwapError::FeeCalculationFailure)?;
+    u64::try_from(withdraw_fee).map_err(|_| SwapError::FeeCalculationFailure)
+}
+
+pub fn process_withdraw_all_token_types(
+    ctx: Context<WithdrawAllTokenTypes>,
+    pool_token_amount: u64,
+    minimum_token_a_amount: u64,
+    minimum_token_b_amount: u64,
+) -> Result<()> {
+    ctx.accounts.validate()?;
+    ctx.accounts.swap.lock()?;
+    ctx.accounts.swap.exit(ctx.program_id)?;
+
+    let swap = &ctx.accounts.swap;
+    let now = Clock::get()?.unix_timestamp;
+    let swap_curve = swap.swap_curve_at(now)?;
+    let withdraw_fee = owner_withdraw_fee(
+        &swap.fees,
+        pool_token_amount,
+        ctx.accounts.source.key() == ctx.accounts.pool_fee_account.key(),
+    )?;
+    let pool_token_amount = pool_token_amount
+        .checked_sub(withdraw_fee)
+        .ok_or(SwapError::CalculationFailure)?;
+    let results = swap_curve.pool_tokens_to_trading_tokens(
+        u128::from(pool_token_amount),
+        u128::from(ctx.accounts.pool_mint.supply),
+        u128::from(ctx.accounts.token_a.amount),
+        u128::from(ctx.accounts.token_b.amount),
+        RoundDirection::Floor,
+    )?;
+    let token_a_amount =
+        std::cmp::min(ctx.accounts.token_a.amount, to_u64(results.token_a_amount)?);
+    if token_a_amount < minimum_token_a_amount {
+        return Err(SwapError::ExceededSlippage.into());
+    }
+    if token_a_amount == 0 && ctx.accounts.token_a.amount != 0 {
+        return Err(SwapError::ZeroTradingTokens.into());
+    }
+    let token_b_amount =
+        std::cmp::min(ctx.accounts.token_b.amount, to_u64(results.token_b_amount)?);
+    if token_b_amount < minimum_token_b_amount {
+        return Err(SwapError::ExceededSlippage.into());
+    }
+    if token_b_amount == 0 && ctx.accounts.token_b.amount != 0 {
+        return Err(SwapError::ZeroTradingTokens.into());
+    }
+
+    let swap_key = swap.key();
+    let seeds: &[&[u8]] = &[swap_key.as_ref(), &[swap.bump_seed]];
+    let token_program = ctx.accounts.token_program.to_account_info();
+    let authority = ctx.accounts.authority.to_account_info();
+
+    let balances_before = (ctx.accounts.token_a.amount, ctx.accounts.token_b.amount);
+    if withdraw_fee > 0 {
+        token_transfer(
+            token_program.clone(),
+            ctx.accounts.source.to_account_info(),
+            ctx.accounts.pool_fee_account.to_account_info(),
+            ctx.accounts.user_transfer_authority.to_account_info(),
+            &[],
+            withdraw_fee,
+        )?;
+    }
+    token_burn(
+        token_program.clone(),
+        ctx.accounts.source.to_account_info(),
+        ctx.accounts.pool_mint.to_account_info(),
+        ctx.accounts.user_transfer_authority.to_account_info(),
+        pool_token_amount,
+    )?;
+    if token_a_amount > 0 {
+        token_transfer(
+            token_program.clone(),
+            ctx.accounts.token_a.to_account_info(),
+            ctx.accounts.destination_a.to_account_info(),
+            authority.clone(),
+            &[seeds],
+            token_a_amount,
+        )?;
+    }
+    if token_b_amount > 0 {
+        token_transfer(
+            token_program,
+            ctx.accounts.token_b.to_account_info(),
+            ctx.accounts.destination_b.to_account_info(),
+            authority,
+            &[seeds],
+            token_b_amount,
+        )?;
+    }
+
+    ctx.accounts.token_a.reload()?;
+    ctx.accounts.token_b.reload()?;
+    let balances_after = (ctx.accounts.token_a.amount, ctx.accounts.token_b.amount);
+    ctx.accounts.swap.update_price_oracle(&swap_curve, now);
+    ctx.accounts.swap.apply_withdraw(
+        token_a_amount,
+        token_b_amount,
+        balances_before,
+        balances_after,
+    )?;
+    ctx.accounts.swap.unlock();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::instructions::test::{process, TestPool},
+    };
+
+    /// Withdraw `pool_token_amount` pool tokens from the user's account into
+    /// their token A and B accounts
+    fn withdraw_all_token_types(pool: &mut TestPool, pool_token_amount: u64) -> Result<()> {
+        let account_infos = [
+            pool.swap.info(),
+            pool.authority.info(),
+            pool.user.info(),
+            pool.pool_mint.info(),
+            pool.user_pool_token.info(),
+            pool.token_a.info(),
+            pool.token_b.info(),
+            pool.user_token_a.info(),
+            pool.user_token_b.info(),
+            pool.pool_fee_account.info(),
+            pool.token_program.info(),
+        ];
+        process(&account_infos, |ctx| {
+            process_withdraw_all_token_types(ctx, pool_token_amount, 1, 1)
+        })
+    }
+
+    #[test]
+    fn owner_withdraw_fee() {
+        let fees = Fees::default().with_owner_withdraw_fee_bps(50);
+        assert_eq!(super::owner_withdraw_fee(&fees, 10_000, false), Ok(50));
+        // minimum fee of one pool token
+        assert_eq!(super::owner_withdraw_fee(&fees, 10, false), Ok(1));
+        assert_eq!(super::owner_withdraw_fee(&fees, 10_000, true), Ok(0));
+        assert_eq!(
+            super::owner_withdraw_fee(&Fees::default(), 10_000, false),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn withdraw_with_owner_withdraw_fee() {
+        let mut pool = TestPool::new(Fees::default().with_owner_withdraw_fee_bps(50));
+        withdraw_all_token_types(&mut pool, 10_000).unwrap();
+
+        // the fee goes to the pool fee account, and only the rest is burned
+        // and paid out
+        assert_eq!(pool.pool_fee_account.token_amount(), 50);
+        assert_eq!(
+            pool.user_pool_token.token_amount(),
+            TestPool::RESERVE - 10_000
+        );
+        assert_eq!(pool.pool_mint.mint_supply(), TestPool::RESERVE - 9_950);
+        assert_eq!(pool.user_token_a.token_amount(), TestPool::RESERVE + 9_950);
+        assert_eq!(pool.user_token_b.token_amount(), TestPool::RESERVE + 9_950);
+        assert_eq!(pool.token_a.token_amount(), TestPool::RESERVE - 9_950);
+        assert_eq!(pool.token_b.token_amount(), TestPool::RESERVE - 9_950);
+    }
+
+    #[test]
+    fn withdraw_all_token_types_reentrancy_guard() {
+        let mut pool = TestPool::new(Fees::default().with_trade_fee_bps(25));
+        withdraw_all_token_types(&mut pool, 1_000).unwrap();
+        assert!(!pool.state().in_progress);
+
+        pool.set_state(|state| state.in_progress = true);
+        let error = withdraw_all_token_types(&mut pool, 1_000).unwrap_err();
+        assert_eq!(ProgramError::from(error), SwapError::InvalidInput.into());
+    }
+
+    #[test]
+    fn withdraw_donated_tokens() {
+        let mut pool = TestPool::new(Fees::default());
+        // tokens donated to the pool aren't in the recorded reserves
+        pool.token_a.set_token_amount(2 * TestPool::RESERVE);
+        withdraw_all_token_types(&mut pool, TestPool::RESERVE * 3 / 5).unwrap();
+
+        assert_eq!(
+            pool.user_token_a.token_amount(),
+            TestPool::RESERVE + 1_200_000
+        );
+        assert_eq!(
+            pool.user_token_b.token_amount(),
+            TestPool::RESERVE + 600_000
+        );
+        let state = pool.state();
+        assert_eq!(state.token_a_reserve, 800_000);
+        assert_eq!(state.token_b_reserve, 400_000);
+        assert_eq!(state.token_a_reserve, pool.token_a.token_amount());
+        assert_eq!(state.token_b_reserve, pool.token_b.token_amount());
+    }
+}
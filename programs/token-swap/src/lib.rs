@@ -2,6 +2,13 @@ use anchor_lang::prelude::*;
 
 pub mod curve;
 pub mod errors;
+pub mod instructions;
+pub mod state;
+
+use {
+    curve::{base::CurveType, base::SwapCurve, fees::Fees},
+    instructions::*,
+};
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 
@@ -9,11 +16,122 @@ declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
 pub mod token_swap {
     use super::*;
 
-    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
-        Ok(())
+    /// Initializes a new swap pool, minting the initial pool tokens to the
+    /// destination account
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        fees: Fees,
+        curve_type: CurveType,
+        curve_parameters: [u8; SwapCurve::PARAMETERS_LEN],
+    ) -> Result<()> {
+        instructions::initialize::process_initialize(ctx, fees, curve_type, curve_parameters)
+    }
+
+    /// Swaps `amount_in` of the source token for at least
+    /// `minimum_amount_out` of the destination token
+    pub fn swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, Swap<'info>>,
+        amount_in: u64,
+        minimum_amount_out: u64,
+    ) -> Result<()> {
+        instructions::swap::process_swap(ctx, amount_in, minimum_amount_out)
+    }
+
+    /// Swaps at most `maximum_amount_in` of the source token for exactly
+    /// `amount_out` of the destination token, failing on curves without
+    /// exact-out support
+    pub fn swap_exact_out<'info>(
+        ctx: Context<'_, '_, '_, 'info, Swap<'info>>,
+        amount_out: u64,
+        maximum_amount_in: u64,
+    ) -> Result<()> {
+        instructions::swap::process_swap_exact_out(ctx, amount_out, maximum_amount_in)
     }
-}
 
-#[derive(Accounts)]
-pub struct Initialize {}
+    /// Ramps the amplifier of a stable curve pool linearly to `target_amp`
+    /// over `ramp_duration` seconds, signed by the pool owner
+    pub fn set_amp(ctx: Context<SetAmp>, target_amp: u64, ramp_duration: i64) -> Result<()> {
+        instructions::set_amp::process_set_amp(ctx, target_amp, ramp_duration)
+    }
 
+    /// Raises the trade fee with the imbalance of the pool, by up to
+    /// `imbalance_multiplier_bps` of the base trade fee for a one-sided pool,
+    /// signed by the pool owner. Zero restores fixed fees.
+    pub fn set_imbalance_fee(
+        ctx: Context<SetImbalanceFee>,
+        imbalance_multiplier_bps: u16,
+    ) -> Result<()> {
+        instructions::set_imbalance_fee::process_set_imbalance_fee(ctx, imbalance_multiplier_bps)
+    }
+
+    /// Sets the smallest reserves of token A and B a swap may leave in the
+    /// pool, signed by the pool owner. Zero removes a floor.
+    pub fn set_reserve_floors(
+        ctx: Context<SetReserveFloors>,
+        reserve_floor_a: u64,
+        reserve_floor_b: u64,
+    ) -> Result<()> {
+        instructions::set_reserve_floors::process_set_reserve_floors(
+            ctx,
+            reserve_floor_a,
+            reserve_floor_b,
+        )
+    }
+
+    /// Lends `amount_out` of the destination token to the borrower and
+    /// invokes the callback program, which must repay the pool with enough
+    /// source token to cover the trade and its fees
+    pub fn flash_swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, FlashSwap<'info>>,
+        amount_out: u64,
+    ) -> Result<()> {
+        instructions::flash_swap::process_flash_swap(ctx, amount_out)
+    }
+
+    /// Deposits both trading tokens in exchange for exactly
+    /// `pool_token_amount` pool tokens
+    pub fn deposit_all_token_types(
+        ctx: Context<DepositAllTokenTypes>,
+        pool_token_amount: u64,
+        maximum_token_a_amount: u64,
+        maximum_token_b_amount: u64,
+    ) -> Result<()> {
+        instructions::deposit_all_token_types::process_deposit_all_token_types(
+            ctx,
+            pool_token_amount,
+            maximum_token_a_amount,
+            maximum_token_b_amount,
+        )
+    }
+
+    /// Deposits exactly `source_token_amount` of token A or B in exchange for
+    /// at least `minimum_pool_token_amount` pool tokens
+    pub fn deposit_single_token_type_exact_amount_in(
+        ctx: Context<DepositSingleTokenTypeExactAmountIn>,
+        source_token_amount: u64,
+        minimum_pool_token_amount: u64,
+    ) -> Result<()> {
+        instructions::deposit_single_token_type_exact_amount_in::process_deposit_single_token_type_exact_amount_in(
+            ctx,
+            source_token_amount,
+            minimum_pool_token_amount,
+        )
+    }
+
+    /// Takes `pool_token_amount` pool tokens, sending the owner withdraw fee
+    /// to the pool fee account and burning the rest in exchange for both
+    /// trading tokens
+    pub fn withdraw_all_token_types(
+        ctx: Context<WithdrawAllTokenTypes>,
+        pool_token_amount: u64,
+        minimum_token_a_amount: u64,
+        minimum_token_b_amount: u64,
+    ) -> Result<()> {
+        instructions::withdraw_all_token_types::process_withdraw_all_token_types(
+            ctx,
+            pool_token_amount,
+            minimum_token_a_amount,
+            minimum_token_b_amount,
+        )
+    }
+}
@@ -0,0 +1,1418 @@
+//! State transition types
+
+use {
+    crate::{
+        curve::{
+            base::{CurveType, SwapCurve, SwapResult},
+            calculator::TradeDirection,
+            fees::{DynamicFees, Fees, OwnerFeeDirection},
+            stable::{compute_ramp_amp, StableCurve},
+        },
+        errors::SwapError,
+    },
+    anchor_lang::{
+        error::ErrorCode, prelude::*, solana_program::program_pack::Pack, Discriminator,
+    },
+    std::sync::Arc,
+};
+
+/// Program state for a single swap pool
+#[account]
+#[derive(Debug, Default, PartialEq)]
+pub struct SwapState {
+    /// Initialized state
+    pub is_initialized: bool,
+    /// Set while a swap, deposit or withdraw is being processed, to reject
+    /// any reentrant call made from one of its token program invocations
+    pub in_progress: bool,
+    /// Bump seed used in program address.
+    /// The program address is created deterministically with the bump seed,
+    /// swap program id, and swap account pubkey. This program address has
+    /// authority over the swap's token A account, token B account, and pool
+    /// token mint.
+    pub bump_seed: u8,
+
+    /// Program ID of the tokens being exchanged.
+    pub token_program_id: Pubkey,
+
+    /// Token A
+    pub token_a: Pubkey,
+    /// Token B
+    pub token_b: Pubkey,
+
+    /// Pool tokens are issued when A or B tokens are deposited.
+    /// Pool tokens can be withdrawn back to the original A or B token.
+    pub pool_mint: Pubkey,
+
+    /// Mint information for token A
+    pub token_a_mint: Pubkey,
+    /// Mint information for token B
+    pub token_b_mint: Pubkey,
+
+    /// Pool token account to receive trading and / or withdrawal fees
+    pub pool_fee_account: Pubkey,
+
+    /// All fee information
+    pub fees: Fees,
+
+    /// Type of the swap curve
+    pub curve_type: CurveType,
+    /// Packed parameters of the swap curve, interpreted according to
+    /// `curve_type`
+    pub curve_parameters: [u8; SwapCurve::PARAMETERS_LEN],
+
+    /// Amplifier targeted by the latest ramp of a stable curve, or zero if
+    /// the amplifier was never ramped
+    pub target_amp: u64,
+    /// Amplifier at the start of the latest ramp
+    pub ramp_start_amp: u64,
+    /// Unix timestamp at the start of the latest ramp
+    pub ramp_start_ts: i64,
+    /// Unix timestamp at which the latest ramp reaches `target_amp`
+    pub ramp_stop_ts: i64,
+
+    /// Balance of the token A account, resynced after each of the program's
+    /// swaps, deposits and withdrawals
+    pub token_a_reserve: u64,
+    /// Balance of the token B account, resynced after each of the program's
+    /// swaps, deposits and withdrawals
+    pub token_b_reserve: u64,
+
+    /// Increase of the trade fee as the pool becomes imbalanced, in basis
+    /// points of the base trade fee, or zero for fixed fees. See
+    /// `DynamicFees`.
+    pub imbalance_multiplier_bps: u16,
+
+    /// Trade fees collected on swaps from token A, in token A
+    pub cumulative_trade_fee_a: u128,
+    /// Trade fees collected on swaps from token B, in token B
+    pub cumulative_trade_fee_b: u128,
+    /// Pool tokens minted to the pool fee account as owner trade fees, after
+    /// the host's share
+    pub cumulative_owner_fee: u128,
+
+    /// Smallest token A reserve a swap may leave in the pool
+    pub reserve_floor_a: u64,
+    /// Smallest token B reserve a swap may leave in the pool
+    pub reserve_floor_b: u64,
+
+    /// Spot price of token A in token B as Q64.64, summed over every second
+    /// since the pool was initialized, for time-weighted average prices.
+    /// Wraps on overflow, like the Uniswap v2 price accumulators.
+    pub cumulative_price_a: u128,
+    /// Time at which `cumulative_price_a` was last updated
+    pub last_update_ts: i64,
+}
+
+/// Reading of the price oracle of a pool at a point in time, see
+/// `SwapState::price_snapshot`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PriceSnapshot {
+    /// Cumulative spot price of token A in token B, as Q64.64
+    pub cumulative_price_a: u128,
+    /// Time of the reading
+    pub timestamp: i64,
+}
+
+impl PriceSnapshot {
+    /// Add the spot price of the given reserves, held since the reading, to
+    /// the cumulative price, and move the reading to `now`. The price is
+    /// skipped for empty reserves or a price which doesn't fit in Q64.64.
+    pub fn accumulate(
+        &self,
+        swap_curve: &SwapCurve,
+        token_a_reserve: u64,
+        token_b_reserve: u64,
+        now: i64,
+    ) -> Self {
+        let elapsed = now.saturating_sub(self.timestamp);
+        if elapsed <= 0 {
+            return *self;
+        }
+        let cumulative_price_a = match swap_curve
+            .spot_price_q64(u128::from(token_a_reserve), u128::from(token_b_reserve))
+        {
+            Some(spot_price) => self
+                .cumulative_price_a
+                .wrapping_add(spot_price.wrapping_mul(elapsed as u128)),
+            None => self.cumulative_price_a,
+        };
+        Self {
+            cumulative_price_a,
+            timestamp: now,
+        }
+    }
+}
+
+/// Time-weighted average spot price of token A in token B between two
+/// snapshots, as Q64.64, or `None` unless `end` is taken after `start`.
+///
+/// The accumulator wraps, so the average is correct as long as it didn't
+/// wrap more than once between the snapshots.
+pub fn twap(start: &PriceSnapshot, end: &PriceSnapshot) -> Option<u128> {
+    let elapsed = end.timestamp.checked_sub(start.timestamp)?;
+    if elapsed <= 0 {
+        return None;
+    }
+    let price_sum = end
+        .cumulative_price_a
+        .wrapping_sub(start.cumulative_price_a);
+    Some(price_sum / elapsed as u128)
+}
+
+/// Fees accrued by a pool over its lifetime, see `SwapState::cumulative_fees`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CumulativeFees {
+    /// Trade fees collected in token A
+    pub trade_fee_a: u128,
+    /// Trade fees collected in token B
+    pub trade_fee_b: u128,
+    /// Owner trade fees, in pool tokens
+    pub owner_fee: u128,
+}
+
+impl CumulativeFees {
+    /// Add the fees of a swap: its trade fee, in the source token of
+    /// `trade_direction`, and the pool tokens minted to the pool fee account.
+    /// Fails with `CalculationFailure` on overflow.
+    pub fn accrue(
+        &self,
+        trade_fee: u128,
+        trade_direction: TradeDirection,
+        owner_fee_pool_tokens: u128,
+    ) -> std::result::Result<Self, SwapError> {
+        let (trade_fee_a, trade_fee_b) = match trade_direction {
+            TradeDirection::AtoB => (
+                self.trade_fee_a.checked_add(trade_fee),
+                Some(self.trade_fee_b),
+            ),
+            TradeDirection::BtoA => (
+                Some(self.trade_fee_a),
+                self.trade_fee_b.checked_add(trade_fee),
+            ),
+        };
+        Ok(Self {
+            trade_fee_a: trade_fee_a.ok_or(SwapError::CalculationFailure)?,
+            trade_fee_b: trade_fee_b.ok_or(SwapError::CalculationFailure)?,
+            owner_fee: self
+                .owner_fee
+                .checked_add(owner_fee_pool_tokens)
+                .ok_or(SwapError::CalculationFailure)?,
+        })
+    }
+}
+
+impl SwapState {
+    /// Size of the serialized state, without the account discriminator
+    pub const LEN: usize = 1
+        + 1
+        + 1
+        + 32 * 7
+        + Fees::LEN
+        + 1
+        + SwapCurve::PARAMETERS_LEN
+        + 8 * 4
+        + 8 * 2
+        + 2
+        + 16 * 3
+        + 8 * 2
+        + 16
+        + 8;
+
+    /// Offset of the fees in the data of a swap account, including the account
+    /// discriminator
+    pub const FEES_OFFSET: usize = 8 + 1 + 1 + 1 + 32 * 7;
+
+    /// Mark the start of an operation on the pool, failing if another one is
+    /// already in progress.
+    ///
+    /// The state must be persisted after locking for the guard to be visible
+    /// to reentrant calls.
+    pub fn lock(&mut self) -> std::result::Result<(), SwapError> {
+        if self.in_progress {
+            return Err(SwapError::InvalidInput);
+        }
+        self.in_progress = true;
+        Ok(())
+    }
+
+    /// Mark the end of an operation on the pool
+    pub fn unlock(&mut self) {
+        self.in_progress = false;
+    }
+
+    /// Resync the reserves with the balances of the pool's token accounts
+    /// after a swap, given as `(token_a, token_b)` before and after its
+    /// transfers. Fails with `CalculationFailure` and leaves the reserves
+    /// untouched if the balances didn't move by the swapped amounts.
+    pub fn apply_swap(
+        &mut self,
+        result: &SwapResult,
+        trade_direction: TradeDirection,
+        balances_before: (u64, u64),
+        balances_after: (u64, u64),
+    ) -> std::result::Result<(), SwapError> {
+        let expected = reserves_after_swap(
+            balances_before.0,
+            balances_before.1,
+            result,
+            trade_direction,
+        )?;
+        let (token_a_reserve, token_b_reserve) = check_balances(expected, balances_after)?;
+        self.token_a_reserve = token_a_reserve;
+        self.token_b_reserve = token_b_reserve;
+        Ok(())
+    }
+
+    /// Add the fees of a swap to the cumulative fees: its trade fee, in the
+    /// source token of `trade_direction`, and the pool tokens minted to the
+    /// pool fee account. Fails with `CalculationFailure` and leaves the
+    /// counters untouched on overflow.
+    pub fn accrue_fees(
+        &mut self,
+        trade_fee: u128,
+        trade_direction: TradeDirection,
+        owner_fee_pool_tokens: u128,
+    ) -> std::result::Result<(), SwapError> {
+        let cumulative_fees =
+            self.cumulative_fees()
+                .accrue(trade_fee, trade_direction, owner_fee_pool_tokens)?;
+        self.cumulative_trade_fee_a = cumulative_fees.trade_fee_a;
+        self.cumulative_trade_fee_b = cumulative_fees.trade_fee_b;
+        self.cumulative_owner_fee = cumulative_fees.owner_fee;
+        Ok(())
+    }
+
+    /// Check that a swap in the given direction leaves at least the floor of
+    /// the destination token in its reserve, failing with `ExceededSlippage`
+    /// otherwise. The source reserve only grows, so it isn't checked.
+    pub fn check_reserve_floor(
+        &self,
+        new_swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> std::result::Result<(), SwapError> {
+        check_reserve_floor(
+            self.reserve_floor_a,
+            self.reserve_floor_b,
+            new_swap_destination_amount,
+            trade_direction,
+        )
+    }
+
+    /// Add the spot price of the current reserves, held since the last update,
+    /// to `cumulative_price_a`, and move the last update to `now`.
+    ///
+    /// Must be called before the reserves change, so that only the first
+    /// trade of a second is priced and trades can't move the oracle within
+    /// the same block. The price is skipped for empty reserves or a price
+    /// which doesn't fit in Q64.64.
+    pub fn update_price_oracle(&mut self, swap_curve: &SwapCurve, now: i64) {
+        let snapshot = PriceSnapshot {
+            cumulative_price_a: self.cumulative_price_a,
+            timestamp: self.last_update_ts,
+        }
+        .accumulate(swap_curve, self.token_a_reserve, self.token_b_reserve, now);
+        self.cumulative_price_a = snapshot.cumulative_price_a;
+        self.last_update_ts = snapshot.timestamp;
+    }
+
+    /// Read the price oracle at `now`, including the current spot price held
+    /// since the last update
+    pub fn price_snapshot(&self, swap_curve: &SwapCurve, now: i64) -> PriceSnapshot {
+        let mut state = self.clone();
+        state.update_price_oracle(swap_curve, now);
+        PriceSnapshot {
+            cumulative_price_a: state.cumulative_price_a,
+            timestamp: now.max(state.last_update_ts),
+        }
+    }
+
+    /// Fees accrued by the pool since it was initialized
+    pub fn cumulative_fees(&self) -> CumulativeFees {
+        CumulativeFees {
+            trade_fee_a: self.cumulative_trade_fee_a,
+            trade_fee_b: self.cumulative_trade_fee_b,
+            owner_fee: self.cumulative_owner_fee,
+        }
+    }
+
+    /// Resync the reserves with the balances of the pool's token accounts
+    /// after a deposit of trading tokens, as `apply_swap` does
+    pub fn apply_deposit(
+        &mut self,
+        token_a_amount: u64,
+        token_b_amount: u64,
+        balances_before: (u64, u64),
+        balances_after: (u64, u64),
+    ) -> std::result::Result<(), SwapError> {
+        let expected = balances_before
+            .0
+            .checked_add(token_a_amount)
+            .zip(balances_before.1.checked_add(token_b_amount))
+            .ok_or(SwapError::CalculationFailure)?;
+        let (token_a_reserve, token_b_reserve) = check_balances(expected, balances_after)?;
+        self.token_a_reserve = token_a_reserve;
+        self.token_b_reserve = token_b_reserve;
+        Ok(())
+    }
+
+    /// Resync the reserves with the balances of the pool's token accounts
+    /// after a withdrawal of trading tokens, as `apply_swap` does
+    pub fn apply_withdraw(
+        &mut self,
+        token_a_amount: u64,
+        token_b_amount: u64,
+        balances_before: (u64, u64),
+        balances_after: (u64, u64),
+    ) -> std::result::Result<(), SwapError> {
+        let expected = balances_before
+            .0
+            .checked_sub(token_a_amount)
+            .zip(balances_before.1.checked_sub(token_b_amount))
+            .ok_or(SwapError::CalculationFailure)?;
+        let (token_a_reserve, token_b_reserve) = check_balances(expected, balances_after)?;
+        self.token_a_reserve = token_a_reserve;
+        self.token_b_reserve = token_b_reserve;
+        Ok(())
+    }
+
+    /// Rebuild the swap curve from the stored type and parameters
+    pub fn swap_curve(&self) -> std::result::Result<SwapCurve, ProgramError> {
+        SwapCurve::from_parameters(self.curve_type, &self.curve_parameters)
+    }
+
+    /// Rebuild the swap curve as of the unix timestamp `now`, applying the
+    /// latest ramp of a stable curve amplifier
+    pub fn swap_curve_at(&self, now: i64) -> std::result::Result<SwapCurve, ProgramError> {
+        swap_curve_at(
+            self.curve_type,
+            &self.curve_parameters,
+            &self.amp_ramp(),
+            now,
+        )
+    }
+
+    /// Amplifier of a stable curve as of the unix timestamp `now`
+    pub fn amp_at(&self, now: i64) -> std::result::Result<u64, ProgramError> {
+        if self.curve_type != CurveType::Stable {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        if self.target_amp == 0 {
+            return Ok(StableCurve::unpack_from_slice(&self.curve_parameters)?.amp);
+        }
+        self.amp_ramp().amp_at(now)
+    }
+
+    fn amp_ramp(&self) -> AmpRamp {
+        AmpRamp {
+            start_amp: self.ramp_start_amp,
+            target_amp: self.target_amp,
+            start_ts: self.ramp_start_ts,
+            stop_ts: self.ramp_stop_ts,
+        }
+    }
+
+    /// Fees to charge on a swap given the token balances of the pool after
+    /// it, with the trade fee raised by the imbalance the swap leaves if the
+    /// pool is configured with dynamic fees, so that trades draining the pool
+    /// pay more than trades rebalancing it
+    pub fn fees_for_reserves(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Fees {
+        fees_for_reserves(
+            &self.fees,
+            self.imbalance_multiplier_bps,
+            swap_token_a_amount,
+            swap_token_b_amount,
+        )
+    }
+}
+
+/// Latest ramp of the amplifier of a stable curve
+#[derive(Clone, Copy, Debug)]
+struct AmpRamp {
+    start_amp: u64,
+    target_amp: u64,
+    start_ts: i64,
+    stop_ts: i64,
+}
+
+impl AmpRamp {
+    /// Amplifier as of the unix timestamp `now`
+    fn amp_at(&self, now: i64) -> std::result::Result<u64, ProgramError> {
+        compute_ramp_amp(
+            self.start_amp,
+            self.target_amp,
+            self.start_ts,
+            self.stop_ts,
+            now,
+        )
+        .ok_or_else(|| SwapError::CalculationFailure.into())
+    }
+}
+
+/// Rebuild a swap curve from its type and packed parameters as of the unix
+/// timestamp `now`, applying the latest ramp of a stable curve amplifier
+fn swap_curve_at(
+    curve_type: CurveType,
+    curve_parameters: &[u8],
+    amp_ramp: &AmpRamp,
+    now: i64,
+) -> std::result::Result<SwapCurve, ProgramError> {
+    let mut swap_curve = SwapCurve::from_parameters(curve_type, curve_parameters)?;
+    if curve_type == CurveType::Stable && amp_ramp.target_amp != 0 {
+        swap_curve.calculator = Arc::new(StableCurve {
+            amp: amp_ramp.amp_at(now)?,
+        });
+    }
+    Ok(swap_curve)
+}
+
+/// Fees to charge on a swap given the token balances of the pool after it, see
+/// `SwapState::fees_for_reserves`
+fn fees_for_reserves(
+    fees: &Fees,
+    imbalance_multiplier_bps: u16,
+    swap_token_a_amount: u128,
+    swap_token_b_amount: u128,
+) -> Fees {
+    if imbalance_multiplier_bps == 0 {
+        return fees.clone();
+    }
+    DynamicFees {
+        base: fees.clone(),
+        imbalance_multiplier_bps,
+    }
+    .effective_fees(swap_token_a_amount, swap_token_b_amount)
+}
+
+/// Reserves of token A and B after recording a swap, failing with
+/// `CalculationFailure` on overflow or underflow
+fn reserves_after_swap(
+    token_a_reserve: u64,
+    token_b_reserve: u64,
+    result: &SwapResult,
+    trade_direction: TradeDirection,
+) -> std::result::Result<(u64, u64), SwapError> {
+    let source_amount =
+        u64::try_from(result.source_amount_swapped).map_err(|_| SwapError::CalculationFailure)?;
+    let destination_amount = u64::try_from(result.destination_amount_swapped)
+        .map_err(|_| SwapError::CalculationFailure)?;
+    let (swap_source_reserve, swap_destination_reserve) = match trade_direction {
+        TradeDirection::AtoB => (token_a_reserve, token_b_reserve),
+        TradeDirection::BtoA => (token_b_reserve, token_a_reserve),
+    };
+    let new_swap_source_reserve = swap_source_reserve
+        .checked_add(source_amount)
+        .ok_or(SwapError::CalculationFailure)?;
+    let new_swap_destination_reserve = swap_destination_reserve
+        .checked_sub(destination_amount)
+        .ok_or(SwapError::CalculationFailure)?;
+    Ok(match trade_direction {
+        TradeDirection::AtoB => (new_swap_source_reserve, new_swap_destination_reserve),
+        TradeDirection::BtoA => (new_swap_destination_reserve, new_swap_source_reserve),
+    })
+}
+
+/// Check that the balances of the pool's token accounts after a transfer are
+/// the `expected` ones, returning them, so that the reserves follow the
+/// accounts, donations included, only when the transfer moved exactly the
+/// amounts computed for it
+fn check_balances(
+    expected: (u64, u64),
+    balances_after: (u64, u64),
+) -> std::result::Result<(u64, u64), SwapError> {
+    if balances_after != expected {
+        return Err(SwapError::CalculationFailure);
+    }
+    Ok(balances_after)
+}
+
+/// Check a swap against the reserve floors, see
+/// `SwapState::check_reserve_floor`
+fn check_reserve_floor(
+    reserve_floor_a: u64,
+    reserve_floor_b: u64,
+    new_swap_destination_amount: u128,
+    trade_direction: TradeDirection,
+) -> std::result::Result<(), SwapError> {
+    let reserve_floor = match trade_direction {
+        TradeDirection::AtoB => reserve_floor_b,
+        TradeDirection::BtoA => reserve_floor_a,
+    };
+    if new_swap_destination_amount < u128::from(reserve_floor) {
+        return Err(SwapError::ExceededSlippage);
+    }
+    Ok(())
+}
+
+pub use layout::SwapStateLayout;
+
+// The padding checks generated by the bytemuck derives are never called
+#[allow(dead_code)]
+mod layout {
+    use {
+        crate::curve::base::SwapCurve,
+        bytemuck::{Pod, Zeroable},
+    };
+
+    /// Fixed layout of a serialized `SwapState` account, which can be read in
+    /// place without deserializing the account. Numbers are little-endian, as
+    /// they aren't aligned in the account data.
+    #[derive(Clone, Copy, Pod, Zeroable)]
+    #[repr(C)]
+    pub struct SwapStateLayout {
+        pub discriminator: [u8; 8],
+        pub is_initialized: u8,
+        pub in_progress: u8,
+        pub bump_seed: u8,
+        pub token_program_id: [u8; 32],
+        pub token_a: [u8; 32],
+        pub token_b: [u8; 32],
+        pub pool_mint: [u8; 32],
+        pub token_a_mint: [u8; 32],
+        pub token_b_mint: [u8; 32],
+        pub pool_fee_account: [u8; 32],
+        /// Fee numerators and denominators, in `Fees` field order
+        pub fee_fractions: [[u8; 8]; 8],
+        pub owner_fee_direction: u8,
+        pub curve_type: u8,
+        pub curve_parameters: [u8; SwapCurve::PARAMETERS_LEN],
+        pub target_amp: [u8; 8],
+        pub ramp_start_amp: [u8; 8],
+        pub ramp_start_ts: [u8; 8],
+        pub ramp_stop_ts: [u8; 8],
+        pub token_a_reserve: [u8; 8],
+        pub token_b_reserve: [u8; 8],
+        pub imbalance_multiplier_bps: [u8; 2],
+        pub cumulative_trade_fee_a: [u8; 16],
+        pub cumulative_trade_fee_b: [u8; 16],
+        pub cumulative_owner_fee: [u8; 16],
+        pub reserve_floor_a: [u8; 8],
+        pub reserve_floor_b: [u8; 8],
+        pub cumulative_price_a: [u8; 16],
+        pub last_update_ts: [u8; 8],
+    }
+}
+
+/// Read-only view of a serialized `SwapState` account, reading the fields
+/// used for swaps in place rather than deserializing the whole account
+pub struct SwapStateRef<'a> {
+    data: &'a SwapStateLayout,
+    owner_fee_direction: OwnerFeeDirection,
+}
+
+impl<'a> SwapStateRef<'a> {
+    const LEN: usize = std::mem::size_of::<SwapStateLayout>();
+
+    /// Check the discriminator and length of the data of a swap account,
+    /// returning its owner fee direction
+    fn check(data: &[u8]) -> Result<OwnerFeeDirection> {
+        let layout: &SwapStateLayout = data
+            .get(..Self::LEN)
+            .map(bytemuck::from_bytes)
+            .ok_or(ErrorCode::AccountDidNotDeserialize)?;
+        if layout.discriminator != SwapState::discriminator() {
+            return Err(ErrorCode::AccountDiscriminatorMismatch.into());
+        }
+        Ok(OwnerFeeDirection::from_u8(layout.owner_fee_direction)
+            .ok_or(ErrorCode::AccountDidNotDeserialize)?)
+    }
+
+    /// Load the view from the data of a swap account, checking its
+    /// discriminator
+    pub fn load(data: &'a [u8]) -> Result<Self> {
+        let owner_fee_direction = Self::check(data)?;
+        Ok(Self {
+            data: bytemuck::from_bytes(&data[..Self::LEN]),
+            owner_fee_direction,
+        })
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.data.is_initialized != 0
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.data.in_progress != 0
+    }
+
+    pub fn bump_seed(&self) -> u8 {
+        self.data.bump_seed
+    }
+
+    pub fn token_program_id(&self) -> Pubkey {
+        Pubkey::new_from_array(self.data.token_program_id)
+    }
+
+    pub fn token_a(&self) -> Pubkey {
+        Pubkey::new_from_array(self.data.token_a)
+    }
+
+    pub fn token_b(&self) -> Pubkey {
+        Pubkey::new_from_array(self.data.token_b)
+    }
+
+    pub fn pool_mint(&self) -> Pubkey {
+        Pubkey::new_from_array(self.data.pool_mint)
+    }
+
+    pub fn pool_fee_account(&self) -> Pubkey {
+        Pubkey::new_from_array(self.data.pool_fee_account)
+    }
+
+    pub fn fees(&self) -> Fees {
+        let fraction = |i: usize| u64::from_le_bytes(self.data.fee_fractions[i]);
+        Fees {
+            trade_fee_numerator: fraction(0),
+            trade_fee_denominator: fraction(1),
+            owner_trade_fee_numerator: fraction(2),
+            owner_trade_fee_denominator: fraction(3),
+            owner_withdraw_fee_numerator: fraction(4),
+            owner_withdraw_fee_denominator: fraction(5),
+            host_fee_numerator: fraction(6),
+            host_fee_denominator: fraction(7),
+            owner_fee_direction: self.owner_fee_direction,
+        }
+    }
+
+    pub fn curve_type(&self) -> Result<CurveType> {
+        CurveType::try_from_slice(&[self.data.curve_type])
+            .map_err(|_| ErrorCode::AccountDidNotDeserialize.into())
+    }
+
+    pub fn curve_parameters(&self) -> &'a [u8] {
+        &self.data.curve_parameters
+    }
+
+    pub fn token_a_reserve(&self) -> u64 {
+        u64::from_le_bytes(self.data.token_a_reserve)
+    }
+
+    pub fn token_b_reserve(&self) -> u64 {
+        u64::from_le_bytes(self.data.token_b_reserve)
+    }
+
+    pub fn imbalance_multiplier_bps(&self) -> u16 {
+        u16::from_le_bytes(self.data.imbalance_multiplier_bps)
+    }
+
+    pub fn cumulative_fees(&self) -> CumulativeFees {
+        CumulativeFees {
+            trade_fee_a: u128::from_le_bytes(self.data.cumulative_trade_fee_a),
+            trade_fee_b: u128::from_le_bytes(self.data.cumulative_trade_fee_b),
+            owner_fee: u128::from_le_bytes(self.data.cumulative_owner_fee),
+        }
+    }
+
+    /// Price oracle as of its last update
+    pub fn last_price_snapshot(&self) -> PriceSnapshot {
+        PriceSnapshot {
+            cumulative_price_a: u128::from_le_bytes(self.data.cumulative_price_a),
+            timestamp: i64::from_le_bytes(self.data.last_update_ts),
+        }
+    }
+
+    /// See `SwapState::swap_curve_at`
+    pub fn swap_curve_at(&self, now: i64) -> Result<SwapCurve> {
+        let amp_ramp = AmpRamp {
+            start_amp: u64::from_le_bytes(self.data.ramp_start_amp),
+            target_amp: u64::from_le_bytes(self.data.target_amp),
+            start_ts: i64::from_le_bytes(self.data.ramp_start_ts),
+            stop_ts: i64::from_le_bytes(self.data.ramp_stop_ts),
+        };
+        Ok(swap_curve_at(
+            self.curve_type()?,
+            self.curve_parameters(),
+            &amp_ramp,
+            now,
+        )?)
+    }
+
+    /// See `SwapState::fees_for_reserves`
+    pub fn fees_for_reserves(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Fees {
+        fees_for_reserves(
+            &self.fees(),
+            self.imbalance_multiplier_bps(),
+            swap_token_a_amount,
+            swap_token_b_amount,
+        )
+    }
+
+    /// See `SwapState::check_reserve_floor`
+    pub fn check_reserve_floor(
+        &self,
+        new_swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> std::result::Result<(), SwapError> {
+        check_reserve_floor(
+            u64::from_le_bytes(self.data.reserve_floor_a),
+            u64::from_le_bytes(self.data.reserve_floor_b),
+            new_swap_destination_amount,
+            trade_direction,
+        )
+    }
+}
+
+/// Mutable view of a serialized `SwapState` account, recording a swap in
+/// place rather than deserializing and serializing the whole account.
+///
+/// Writes go straight to the account data, so unlike with `SwapState` the
+/// lock is visible to reentrant calls without persisting the state.
+pub struct SwapStateRefMut<'a> {
+    data: &'a mut SwapStateLayout,
+    owner_fee_direction: OwnerFeeDirection,
+}
+
+impl<'a> SwapStateRefMut<'a> {
+    /// Load the view from the data of a swap account, checking its
+    /// discriminator
+    pub fn load(data: &'a mut [u8]) -> Result<Self> {
+        let owner_fee_direction = SwapStateRef::check(data)?;
+        Ok(Self {
+            data: bytemuck::from_bytes_mut(&mut data[..SwapStateRef::LEN]),
+            owner_fee_direction,
+        })
+    }
+
+    /// Read-only view of the same account
+    pub fn to_ref(&self) -> SwapStateRef<'_> {
+        SwapStateRef {
+            data: self.data,
+            owner_fee_direction: self.owner_fee_direction,
+        }
+    }
+
+    /// See `SwapState::lock`
+    pub fn lock(&mut self) -> std::result::Result<(), SwapError> {
+        if self.data.in_progress != 0 {
+            return Err(SwapError::InvalidInput);
+        }
+        self.data.in_progress = 1;
+        Ok(())
+    }
+
+    /// See `SwapState::unlock`
+    pub fn unlock(&mut self) {
+        self.data.in_progress = 0;
+    }
+
+    /// See `SwapState::apply_swap`
+    pub fn apply_swap(
+        &mut self,
+        result: &SwapResult,
+        trade_direction: TradeDirection,
+        balances_before: (u64, u64),
+        balances_after: (u64, u64),
+    ) -> std::result::Result<(), SwapError> {
+        let expected = reserves_after_swap(
+            balances_before.0,
+            balances_before.1,
+            result,
+            trade_direction,
+        )?;
+        let (token_a_reserve, token_b_reserve) = check_balances(expected, balances_after)?;
+        self.data.token_a_reserve = token_a_reserve.to_le_bytes();
+        self.data.token_b_reserve = token_b_reserve.to_le_bytes();
+        Ok(())
+    }
+
+    /// See `SwapState::accrue_fees`
+    pub fn accrue_fees(
+        &mut self,
+        trade_fee: u128,
+        trade_direction: TradeDirection,
+        owner_fee_pool_tokens: u128,
+    ) -> std::result::Result<(), SwapError> {
+        let cumulative_fees = self.to_ref().cumulative_fees().accrue(
+            trade_fee,
+            trade_direction,
+            owner_fee_pool_tokens,
+        )?;
+        self.data.cumulative_trade_fee_a = cumulative_fees.trade_fee_a.to_le_bytes();
+        self.data.cumulative_trade_fee_b = cumulative_fees.trade_fee_b.to_le_bytes();
+        self.data.cumulative_owner_fee = cumulative_fees.owner_fee.to_le_bytes();
+        Ok(())
+    }
+
+    /// See `SwapState::update_price_oracle`
+    pub fn update_price_oracle(&mut self, swap_curve: &SwapCurve, now: i64) {
+        let swap = self.to_ref();
+        let snapshot = swap.last_price_snapshot().accumulate(
+            swap_curve,
+            swap.token_a_reserve(),
+            swap.token_b_reserve(),
+            now,
+        );
+        self.data.cumulative_price_a = snapshot.cumulative_price_a.to_le_bytes();
+        self.data.last_update_ts = snapshot.timestamp.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialized_len() {
+        let state = SwapState {
+            fees: Fees {
+                owner_fee_direction: OwnerFeeDirection::AtoB,
+                ..Fees::default()
+            },
+            ..SwapState::default()
+        };
+        assert_eq!(state.try_to_vec().unwrap().len(), SwapState::LEN);
+    }
+
+    #[test]
+    fn swap_curve_round_trip() {
+        let mut curve_parameters = [0u8; SwapCurve::PARAMETERS_LEN];
+        Pack::pack_into_slice(&StableCurve { amp: 50 }, &mut curve_parameters);
+        let state = SwapState {
+            curve_type: CurveType::Stable,
+            curve_parameters,
+            ..SwapState::default()
+        };
+        let swap_curve = state.swap_curve().unwrap();
+        assert_eq!(swap_curve.curve_type, CurveType::Stable);
+        let mut repacked = [0u8; SwapCurve::PARAMETERS_LEN];
+        swap_curve.calculator.pack_into_slice(&mut repacked);
+        assert_eq!(curve_parameters, repacked);
+    }
+
+    #[test]
+    fn swap_curve_ramps_amp() {
+        let mut curve_parameters = [0u8; SwapCurve::PARAMETERS_LEN];
+        Pack::pack_into_slice(&StableCurve { amp: 100 }, &mut curve_parameters);
+        let mut state = SwapState {
+            curve_type: CurveType::Stable,
+            curve_parameters,
+            ..SwapState::default()
+        };
+        // never ramped, the packed amplifier is used
+        assert_eq!(state.amp_at(1_500), Ok(100));
+
+        state.target_amp = 300;
+        state.ramp_start_amp = 100;
+        state.ramp_start_ts = 1_000;
+        state.ramp_stop_ts = 2_000;
+        for (now, expected) in [(500, 100), (1_500, 200), (1_750, 250), (3_000, 300)] {
+            assert_eq!(state.amp_at(now), Ok(expected));
+            let swap_curve = state.swap_curve_at(now).unwrap();
+            let mut packed = [0u8; SwapCurve::PARAMETERS_LEN];
+            swap_curve.calculator.pack_into_slice(&mut packed);
+            assert_eq!(
+                StableCurve::unpack_from_slice(&packed).unwrap().amp,
+                expected
+            );
+        }
+
+        let state = SwapState::default();
+        assert_eq!(
+            state.amp_at(0),
+            Err(SwapError::UnsupportedCurveOperation.into())
+        );
+    }
+
+    #[test]
+    fn apply_swap() {
+        let mut state = SwapState {
+            token_a_reserve: 1_000,
+            token_b_reserve: 50_000,
+            ..SwapState::default()
+        };
+        let result = SwapResult {
+            new_swap_source_amount: 1_100,
+            new_swap_destination_amount: 45_500,
+            source_amount_swapped: 100,
+            destination_amount_swapped: 4_500,
+            trade_fee: 0,
+            owner_fee: 0,
+        };
+        state
+            .apply_swap(
+                &result,
+                TradeDirection::AtoB,
+                (1_000, 50_000),
+                (1_100, 45_500),
+            )
+            .unwrap();
+        assert_eq!(state.token_a_reserve, 1_100);
+        assert_eq!(state.token_b_reserve, 45_500);
+
+        // the reserves follow the balances, including tokens donated to the
+        // pool's accounts since the last update
+        let result = SwapResult {
+            new_swap_source_amount: 50_500,
+            new_swap_destination_amount: 1_000,
+            source_amount_swapped: 4_500,
+            destination_amount_swapped: 200,
+            trade_fee: 0,
+            owner_fee: 0,
+        };
+        state
+            .apply_swap(
+                &result,
+                TradeDirection::BtoA,
+                (1_200, 46_000),
+                (1_000, 50_500),
+            )
+            .unwrap();
+        assert_eq!(state.token_a_reserve, 1_000);
+        assert_eq!(state.token_b_reserve, 50_500);
+    }
+
+    #[test]
+    fn apply_swap_mismatch() {
+        let mut state = SwapState {
+            token_a_reserve: 1_000,
+            token_b_reserve: 1_000,
+            ..SwapState::default()
+        };
+        let result = SwapResult {
+            new_swap_source_amount: 1_100,
+            new_swap_destination_amount: 0,
+            source_amount_swapped: 100,
+            destination_amount_swapped: 1_001,
+            trade_fee: 0,
+            owner_fee: 0,
+        };
+        // more than the balance of the destination account
+        assert_eq!(
+            state.apply_swap(&result, TradeDirection::AtoB, (1_000, 1_000), (1_100, 0)),
+            Err(SwapError::CalculationFailure)
+        );
+        // the reserves are left untouched
+        assert_eq!(state.token_a_reserve, 1_000);
+        assert_eq!(state.token_b_reserve, 1_000);
+
+        let result = SwapResult {
+            destination_amount_swapped: 1,
+            ..result
+        };
+        assert_eq!(
+            state.apply_swap(
+                &result,
+                TradeDirection::AtoB,
+                (u64::MAX, 1_000),
+                (u64::MAX, 999)
+            ),
+            Err(SwapError::CalculationFailure)
+        );
+        // balances which didn't move by the swapped amounts
+        assert_eq!(
+            state.apply_swap(
+                &result,
+                TradeDirection::AtoB,
+                (1_000, 1_000),
+                (1_100, 1_000)
+            ),
+            Err(SwapError::CalculationFailure)
+        );
+        assert_eq!(
+            state.apply_withdraw(0, 1_001, (1_000, 1_000), (1_000, 0)),
+            Err(SwapError::CalculationFailure)
+        );
+        assert_eq!(
+            state.apply_withdraw(0, 1, (1_000, 1_000), (1_000, 1_000)),
+            Err(SwapError::CalculationFailure)
+        );
+        assert_eq!(
+            state.apply_deposit(1, 0, (u64::MAX, 1_000), (u64::MAX, 1_000)),
+            Err(SwapError::CalculationFailure)
+        );
+        assert_eq!(
+            state.apply_deposit(1, 0, (1_000, 1_000), (1_000, 1_000)),
+            Err(SwapError::CalculationFailure)
+        );
+        assert_eq!(state.token_a_reserve, 1_000);
+        assert_eq!(state.token_b_reserve, 1_000);
+    }
+
+    #[test]
+    fn apply_deposit_and_withdraw() {
+        let mut state = SwapState {
+            token_a_reserve: 1_000,
+            token_b_reserve: 1_000,
+            ..SwapState::default()
+        };
+        // a withdrawal of donated tokens, larger than the recorded reserves
+        state
+            .apply_withdraw(1_500, 500, (2_000, 1_000), (500, 500))
+            .unwrap();
+        assert_eq!(state.token_a_reserve, 500);
+        assert_eq!(state.token_b_reserve, 500);
+        state
+            .apply_deposit(100, 200, (600, 500), (700, 700))
+            .unwrap();
+        assert_eq!(state.token_a_reserve, 700);
+        assert_eq!(state.token_b_reserve, 700);
+    }
+
+    #[test]
+    fn price_oracle() {
+        let swap_curve = SwapCurve::default();
+        let mut state = SwapState {
+            token_a_reserve: 1_000,
+            token_b_reserve: 2_000,
+            last_update_ts: 100,
+            ..SwapState::default()
+        };
+        let start = state.price_snapshot(&swap_curve, 100);
+        assert_eq!(start.cumulative_price_a, 0);
+
+        // 2 B per A for 10 seconds
+        state.update_price_oracle(&swap_curve, 110);
+        assert_eq!(state.cumulative_price_a, 20 << 64);
+        // a second trade in the same second doesn't count
+        state.update_price_oracle(&swap_curve, 110);
+        assert_eq!(state.cumulative_price_a, 20 << 64);
+        let middle = state.price_snapshot(&swap_curve, 110);
+
+        // then 0.5 B per A for 20 seconds
+        state.token_a_reserve = 2_000;
+        state.token_b_reserve = 1_000;
+        let end = state.price_snapshot(&swap_curve, 130);
+        assert_eq!(end.cumulative_price_a, 30 << 64);
+        assert_eq!(state.cumulative_price_a, 20 << 64);
+
+        assert_eq!(twap(&start, &middle), Some(2 << 64));
+        assert_eq!(twap(&middle, &end), Some(1 << 63));
+        assert_eq!(twap(&start, &end), Some(1 << 64));
+        assert_eq!(twap(&end, &start), None);
+        assert_eq!(twap(&end, &end), None);
+
+        // the accumulator wraps
+        let start = PriceSnapshot {
+            cumulative_price_a: u128::MAX - (1 << 64) + 1,
+            timestamp: 0,
+        };
+        let end = PriceSnapshot {
+            cumulative_price_a: 1 << 64,
+            timestamp: 2,
+        };
+        assert_eq!(twap(&start, &end), Some(1 << 64));
+    }
+
+    #[test]
+    fn accrue_fees() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5);
+        let mut state = SwapState::default();
+
+        let result = swap_curve
+            .swap(100_000, 1_000_000, 1_000_000, TradeDirection::AtoB, &fees)
+            .unwrap();
+        assert_eq!(result.trade_fee, 250);
+        state
+            .accrue_fees(result.trade_fee, TradeDirection::AtoB, 40)
+            .unwrap();
+        assert_eq!(
+            state.cumulative_fees(),
+            CumulativeFees {
+                trade_fee_a: 250,
+                trade_fee_b: 0,
+                owner_fee: 40,
+            }
+        );
+
+        let result = swap_curve
+            .swap(200_000, 1_000_000, 1_000_000, TradeDirection::BtoA, &fees)
+            .unwrap();
+        assert_eq!(result.trade_fee, 500);
+        state
+            .accrue_fees(result.trade_fee, TradeDirection::BtoA, 80)
+            .unwrap();
+        assert_eq!(
+            state.cumulative_fees(),
+            CumulativeFees {
+                trade_fee_a: 250,
+                trade_fee_b: 500,
+                owner_fee: 120,
+            }
+        );
+
+        // overflow leaves the counters untouched
+        state.cumulative_owner_fee = u128::MAX;
+        assert_eq!(
+            state.accrue_fees(result.trade_fee, TradeDirection::AtoB, 1),
+            Err(SwapError::CalculationFailure)
+        );
+        assert_eq!(state.cumulative_trade_fee_a, 250);
+    }
+
+    #[test]
+    fn check_reserve_floor() {
+        let swap_curve = SwapCurve::default();
+        let fees = Fees::default().with_trade_fee_bps(25);
+        let state = SwapState {
+            reserve_floor_b: 900_000,
+            ..SwapState::default()
+        };
+        let swap = |source_amount| {
+            swap_curve
+                .swap(
+                    source_amount,
+                    1_000_000,
+                    1_000_000,
+                    TradeDirection::AtoB,
+                    &fees,
+                )
+                .unwrap()
+                .new_swap_destination_amount
+        };
+
+        // under 50_000 B out leaves the reserve above the floor
+        assert_eq!(
+            state.check_reserve_floor(swap(50_000), TradeDirection::AtoB),
+            Ok(())
+        );
+        // about 166_000 B out would go below it
+        assert_eq!(
+            state.check_reserve_floor(swap(200_000), TradeDirection::AtoB),
+            Err(SwapError::ExceededSlippage)
+        );
+        // token A has no floor
+        assert_eq!(
+            state.check_reserve_floor(swap(200_000), TradeDirection::BtoA),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn packed_regions_survive_account_serialization() {
+        let mut curve_parameters = [0u8; SwapCurve::PARAMETERS_LEN];
+        Pack::pack_into_slice(&StableCurve { amp: 85 }, &mut curve_parameters);
+        let fees = Fees::default()
+            .with_trade_fee_bps(25)
+            .with_owner_trade_fee_bps(5)
+            .with_host_fee_bps(2_000);
+        let state = SwapState {
+            is_initialized: true,
+            fees: fees.clone(),
+            curve_type: CurveType::Stable,
+            curve_parameters,
+            ..SwapState::default()
+        };
+        let mut data = vec![];
+        state.try_serialize(&mut data).unwrap();
+        assert_eq!(data.len(), 8 + SwapState::LEN);
+
+        // the discriminator, flags, bump seed and public keys come first
+        let fees_offset = 8 + 1 + 1 + 1 + 32 * 7;
+        // Borsh and `Pack` agree on the fees
+        let mut packed_fees = [0u8; Fees::LEN];
+        Pack::pack_into_slice(&fees, &mut packed_fees);
+        assert_eq!(&data[fees_offset..fees_offset + Fees::LEN], &packed_fees);
+
+        let curve_type_offset = fees_offset + Fees::LEN;
+        let curve_type = CurveType::try_from_slice(&data[curve_type_offset..][..1]).unwrap();
+        assert_eq!(curve_type, CurveType::Stable);
+        let parameters_offset = curve_type_offset + 1;
+        let swap_curve = SwapCurve::from_parameters(
+            curve_type,
+            &data[parameters_offset..][..SwapCurve::PARAMETERS_LEN],
+        )
+        .unwrap();
+        let mut repacked = [0u8; SwapCurve::PARAMETERS_LEN];
+        swap_curve.calculator.pack_into_slice(&mut repacked);
+        assert_eq!(repacked, curve_parameters);
+
+        let deserialized = SwapState::try_deserialize(&mut data.as_slice()).unwrap();
+        assert_eq!(deserialized, state);
+    }
+
+    #[test]
+    fn fees_for_reserves() {
+        let fees = Fees::default().with_trade_fee_bps(30);
+        let mut state = SwapState {
+            fees: fees.clone(),
+            ..SwapState::default()
+        };
+        assert_eq!(state.fees_for_reserves(1_000_000, 0), fees);
+
+        state.imbalance_multiplier_bps = 10_000;
+        assert_eq!(state.fees_for_reserves(1_000_000, 1_000_000), fees);
+        assert_eq!(
+            state.fees_for_reserves(1_000_000, 0).trade_fee_numerator,
+            60
+        );
+    }
+
+    #[test]
+    fn load_swap_state_ref() {
+        let mut curve_parameters = [0u8; SwapCurve::PARAMETERS_LEN];
+        Pack::pack_into_slice(&StableCurve { amp: 85 }, &mut curve_parameters);
+        for owner_fee_direction in [OwnerFeeDirection::All, OwnerFeeDirection::BtoA] {
+            let state = SwapState {
+                is_initialized: true,
+                token_a: Pubkey::new_unique(),
+                token_b: Pubkey::new_unique(),
+                pool_mint: Pubkey::new_unique(),
+                fees: Fees {
+                    owner_fee_direction,
+                    ..Fees::default()
+                        .with_trade_fee_bps(25)
+                        .with_owner_trade_fee_bps(5)
+                        .with_owner_withdraw_fee_bps(10)
+                        .with_host_fee_bps(2_000)
+                },
+                curve_type: CurveType::Stable,
+                curve_parameters,
+                target_amp: 100,
+                token_a_reserve: 1_000,
+                token_b_reserve: 2_000,
+                ..SwapState::default()
+            };
+            let mut data = vec![];
+            state.try_serialize(&mut data).unwrap();
+
+            let view = SwapStateRef::load(&data).unwrap();
+            assert!(view.is_initialized());
+            assert_eq!(view.token_a(), state.token_a);
+            assert_eq!(view.token_b(), state.token_b);
+            assert_eq!(view.pool_mint(), state.pool_mint);
+            assert_eq!(view.fees(), state.fees);
+            assert_eq!(view.curve_type().unwrap(), CurveType::Stable);
+            assert_eq!(view.curve_parameters(), &curve_parameters[..]);
+            assert_eq!(view.token_a_reserve(), 1_000);
+            assert_eq!(view.token_b_reserve(), 2_000);
+        }
+        assert_eq!(std::mem::size_of::<SwapStateLayout>(), 8 + SwapState::LEN);
+
+        let mut data = vec![];
+        SwapState::default().try_serialize(&mut data).unwrap();
+        assert!(SwapStateRef::load(&data[..8 + SwapState::LEN - 1]).is_err());
+        // an invalid owner fee direction
+        data[SwapState::FEES_OFFSET + Fees::LEN - 1] = 3;
+        assert!(SwapStateRef::load(&data).is_err());
+        data[SwapState::FEES_OFFSET + Fees::LEN - 1] = 0;
+        data[0] ^= 1;
+        assert!(SwapStateRef::load(&data).is_err());
+        assert!(SwapStateRef::load(&data[..16]).is_err());
+    }
+
+    #[test]
+    fn swap_state_ref_mut_matches_swap_state() {
+        let mut curve_parameters = [0u8; SwapCurve::PARAMETERS_LEN];
+        Pack::pack_into_slice(&StableCurve { amp: 85 }, &mut curve_parameters);
+        let mut state = SwapState {
+            is_initialized: true,
+            fees: Fees {
+                owner_fee_direction: OwnerFeeDirection::AtoB,
+                ..Fees::default().with_trade_fee_bps(25)
+            },
+            curve_type: CurveType::Stable,
+            curve_parameters,
+            target_amp: 200,
+            ramp_start_amp: 100,
+            ramp_start_ts: 0,
+            ramp_stop_ts: 100,
+            token_a_reserve: 1_000_000,
+            token_b_reserve: 2_000_000,
+            imbalance_multiplier_bps: 5_000,
+            cumulative_trade_fee_b: 7,
+            reserve_floor_a: 500_000,
+            last_update_ts: 40,
+            ..SwapState::default()
+        };
+        let mut data = vec![];
+        state.try_serialize(&mut data).unwrap();
+        let mut view = SwapStateRefMut::load(&mut data).unwrap();
+
+        let now = 50;
+        let swap_curve = view.to_ref().swap_curve_at(now).unwrap();
+        assert_eq!(
+            swap_curve.pack_parameters(),
+            state.swap_curve_at(now).unwrap().pack_parameters()
+        );
+        assert_eq!(
+            view.to_ref().fees_for_reserves(1_000_000, 2_000_000),
+            state.fees_for_reserves(1_000_000, 2_000_000)
+        );
+        assert_eq!(view.to_ref().cumulative_fees(), state.cumulative_fees());
+        assert_eq!(
+            view.to_ref()
+                .check_reserve_floor(400_000, TradeDirection::BtoA),
+            Err(SwapError::ExceededSlippage)
+        );
+        assert_eq!(
+            view.to_ref()
+                .check_reserve_floor(600_000, TradeDirection::BtoA),
+            Ok(())
+        );
+
+        let result = swap_curve
+            .swap(
+                10_000,
+                2_000_000,
+                1_000_000,
+                TradeDirection::BtoA,
+                &state.fees,
+            )
+            .unwrap();
+        view.lock().unwrap();
+        assert_eq!(view.lock(), Err(SwapError::InvalidInput));
+        view.accrue_fees(result.trade_fee, TradeDirection::BtoA, 3)
+            .unwrap();
+        view.update_price_oracle(&swap_curve, now);
+        let balances_after = (
+            u64::try_from(result.new_swap_destination_amount).unwrap(),
+            u64::try_from(result.new_swap_source_amount).unwrap(),
+        );
+        view.apply_swap(
+            &result,
+            TradeDirection::BtoA,
+            (1_000_000, 2_000_000),
+            balances_after,
+        )
+        .unwrap();
+        view.unlock();
+        // underflowing the balances leaves the reserves untouched
+        let overdrawn = SwapResult {
+            destination_amount_swapped: u128::from(u64::MAX),
+            ..result
+        };
+        assert_eq!(
+            view.apply_swap(
+                &overdrawn,
+                TradeDirection::BtoA,
+                (1_000_000, 2_000_000),
+                balances_after,
+            ),
+            Err(SwapError::CalculationFailure)
+        );
+
+        state
+            .accrue_fees(result.trade_fee, TradeDirection::BtoA, 3)
+            .unwrap();
+        state.update_price_oracle(&swap_curve, now);
+        state
+            .apply_swap(
+                &result,
+                TradeDirection::BtoA,
+                (1_000_000, 2_000_000),
+                balances_after,
+            )
+            .unwrap();
+        assert_eq!(
+            SwapState::try_deserialize(&mut data.as_slice()).unwrap(),
+            state
+        );
+    }
+
+    #[test]
+    fn reentrant_lock_fails() {
+        let mut state = SwapState::default();
+        state.lock().unwrap();
+        // a reentrant call sees the persisted flag and is rejected
+        let mut reentrant_state = SwapState::try_from_slice(&state.try_to_vec().unwrap()).unwrap();
+        assert_eq!(reentrant_state.lock(), Err(SwapError::InvalidInput));
+        assert_eq!(state.lock(), Err(SwapError::InvalidInput));
+
+        // once the operation completes, the pool can be used again
+        state.unlock();
+        assert_eq!(state.lock(), Ok(()));
+    }
+}
@@ -1,16 +1,267 @@
 import * as anchor from "@project-serum/anchor";
 import { Program } from "@project-serum/anchor";
+import {
+  createAccount,
+  createMint,
+  getAccount,
+  mintTo,
+  TOKEN_PROGRAM_ID,
+} from "@solana/spl-token";
+import { Keypair, PublicKey, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
 import { TokenSwap } from "../target/types/token_swap";
 
+// INITIAL_SWAP_POOL_AMOUNT and MINIMUM_LIQUIDITY
+const INITIAL_SWAP_POOL_AMOUNT = BigInt(1_000_000_000);
+const MINIMUM_LIQUIDITY = BigInt(1_000);
+// Fees::LEN: eight fee fractions and the owner fee direction
+const FEES_LEN = 8 * 8 + 1;
+
+// SwapError::IncorrectPoolMint
+const INCORRECT_POOL_MINT_ERROR = 11;
+// SwapError::RepeatedMint
+const REPEATED_MINT_ERROR = 15;
+
 describe("token-swap", () => {
   // Configure the client to use the local cluster.
-  anchor.setProvider(anchor.AnchorProvider.env());
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
 
   const program = anchor.workspace.TokenSwap as Program<TokenSwap>;
+  const payer = (provider.wallet as anchor.Wallet).payer;
+
+  const fees = {
+    tradeFeeNumerator: new anchor.BN(25),
+    tradeFeeDenominator: new anchor.BN(10000),
+    ownerTradeFeeNumerator: new anchor.BN(5),
+    ownerTradeFeeDenominator: new anchor.BN(10000),
+    ownerWithdrawFeeNumerator: new anchor.BN(0),
+    ownerWithdrawFeeDenominator: new anchor.BN(0),
+    hostFeeNumerator: new anchor.BN(20),
+    hostFeeDenominator: new anchor.BN(100),
+    ownerFeeDirection: { all: {} },
+  };
+  const curveParameters = new Array(32).fill(0);
+
+  // Creates the swap authority's token accounts and the pool mint, and
+  // returns the accounts needed by `initialize`
+  async function setupPool(mintA: PublicKey, mintB: PublicKey) {
+    const swap = Keypair.generate();
+    const [authority] = await PublicKey.findProgramAddress(
+      [swap.publicKey.toBuffer()],
+      program.programId
+    );
+    const poolMint = await createMint(
+      provider.connection,
+      payer,
+      authority,
+      null,
+      2
+    );
+    const tokenA = await createAccount(
+      provider.connection,
+      payer,
+      mintA,
+      authority,
+      Keypair.generate()
+    );
+    await mintTo(provider.connection, payer, mintA, tokenA, payer, 1_000_000);
+    const tokenB = await createAccount(
+      provider.connection,
+      payer,
+      mintB,
+      authority,
+      Keypair.generate()
+    );
+    await mintTo(provider.connection, payer, mintB, tokenB, payer, 1_000_000);
+    const poolFeeAccount = await createAccount(
+      provider.connection,
+      payer,
+      poolMint,
+      payer.publicKey,
+      Keypair.generate()
+    );
+    const destination = await createAccount(
+      provider.connection,
+      payer,
+      poolMint,
+      payer.publicKey,
+      Keypair.generate()
+    );
+    const lockedLiquidity = await createAccount(
+      provider.connection,
+      payer,
+      poolMint,
+      authority,
+      Keypair.generate()
+    );
+    return {
+      swap,
+      accounts: {
+        swap: swap.publicKey,
+        authority,
+        tokenA,
+        tokenB,
+        poolMint,
+        poolFeeAccount,
+        destination,
+        lockedLiquidity,
+        payer: payer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      },
+    };
+  }
 
   it("Is initialized!", async () => {
-    // Add your test here.
-    const tx = await program.methods.initialize().rpc();
-    console.log("Your transaction signature", tx);
+    const mintA = await createMint(
+      provider.connection,
+      payer,
+      payer.publicKey,
+      null,
+      2
+    );
+    const mintB = await createMint(
+      provider.connection,
+      payer,
+      payer.publicKey,
+      null,
+      2
+    );
+    const { swap, accounts } = await setupPool(mintA, mintB);
+
+    await program.methods
+      .initialize(fees, { constantProduct: {} }, curveParameters)
+      .accounts(accounts)
+      .signers([swap])
+      .rpc();
+
+    const state = await program.account.swapState.fetch(swap.publicKey);
+    assert.isTrue(state.isInitialized);
+    assert.isTrue(state.tokenAMint.equals(mintA));
+    assert.isTrue(state.tokenBMint.equals(mintB));
+
+    const destination = await getAccount(
+      provider.connection,
+      accounts.destination
+    );
+    assert.equal(
+      destination.amount,
+      INITIAL_SWAP_POOL_AMOUNT - MINIMUM_LIQUIDITY
+    );
+    const lockedLiquidity = await getAccount(
+      provider.connection,
+      accounts.lockedLiquidity
+    );
+    assert.equal(lockedLiquidity.amount, MINIMUM_LIQUIDITY);
+  });
+
+  it("Stores the packed curve parameters in the account data", async () => {
+    const mintA = await createMint(
+      provider.connection,
+      payer,
+      payer.publicKey,
+      null,
+      2
+    );
+    const mintB = await createMint(
+      provider.connection,
+      payer,
+      payer.publicKey,
+      null,
+      2
+    );
+    const { swap, accounts } = await setupPool(mintA, mintB);
+    // StableCurve { amp: 85 }, packed as a little-endian u64
+    const stableParameters = new Array(32).fill(0);
+    new anchor.BN(85)
+      .toArray("le", 8)
+      .forEach((byte, i) => (stableParameters[i] = byte));
+
+    await program.methods
+      .initialize(fees, { stable: {} }, stableParameters)
+      .accounts(accounts)
+      .signers([swap])
+      .rpc();
+
+    const info = await provider.connection.getAccountInfo(swap.publicKey);
+    // discriminator, flags, bump seed and public keys
+    const feesOffset = 8 + 3 + 32 * 7;
+    assert.isTrue(
+      new anchor.BN(info.data.subarray(feesOffset, feesOffset + 8), "le").eq(
+        fees.tradeFeeNumerator
+      )
+    );
+    const curveTypeOffset = feesOffset + FEES_LEN;
+    // CurveType::Stable
+    assert.equal(info.data[curveTypeOffset], 2);
+    assert.deepEqual(
+      Array.from(info.data.subarray(curveTypeOffset + 1, curveTypeOffset + 33)),
+      stableParameters
+    );
+  });
+
+  it("Rejects identical token A and B mints", async () => {
+    const mint = await createMint(
+      provider.connection,
+      payer,
+      payer.publicKey,
+      null,
+      2
+    );
+    const { swap, accounts } = await setupPool(mint, mint);
+
+    try {
+      await program.methods
+        .initialize(fees, { constantProduct: {} }, curveParameters)
+        .accounts(accounts)
+        .signers([swap])
+        .rpc();
+      assert.fail("initialize should fail with RepeatedMint");
+    } catch (err) {
+      assert.include(
+        err.toString(),
+        `custom program error: 0x${REPEATED_MINT_ERROR.toString(16)}`
+      );
+    }
+  });
+
+  it("Rejects a pool fee account for another mint", async () => {
+    const mintA = await createMint(
+      provider.connection,
+      payer,
+      payer.publicKey,
+      null,
+      2
+    );
+    const mintB = await createMint(
+      provider.connection,
+      payer,
+      payer.publicKey,
+      null,
+      2
+    );
+    const { swap, accounts } = await setupPool(mintA, mintB);
+    const poolFeeAccount = await createAccount(
+      provider.connection,
+      payer,
+      mintA,
+      payer.publicKey,
+      Keypair.generate()
+    );
+
+    try {
+      await program.methods
+        .initialize(fees, { constantProduct: {} }, curveParameters)
+        .accounts({ ...accounts, poolFeeAccount })
+        .signers([swap])
+        .rpc();
+      assert.fail("initialize should fail with IncorrectPoolMint");
+    } catch (err) {
+      assert.include(
+        err.toString(),
+        `custom program error: 0x${INCORRECT_POOL_MINT_ERROR.toString(16)}`
+      );
+    }
   });
 });